@@ -0,0 +1,115 @@
+//! Just enough AML to read the sleep state packages out of the DSDT, without an interpreter.
+
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const ROOT_PREFIX: u8 = b'\\';
+
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const DWORD_PREFIX: u8 = 0x0C;
+
+/// The values to write to the SLP_TYP fields of the PM1 control registers to enter a
+/// sleep state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepType {
+    pub pm1a: u16,
+    pub pm1b: u16,
+}
+
+/// Finds the `\_Sx` package of the sleep state `state` in the AML of a DSDT or SSDT.
+///
+/// Only finds packages defined with a plain `Name`, which is how virtually every firmware
+/// defines them, and not ones computed by methods.
+pub fn find_sleep_type(aml: &[u8], state: u8) -> Option<SleepType> {
+    let name = [b'_', b'S', b'0' + state, b'_'];
+
+    (1..aml.len().saturating_sub(3))
+        .filter(|&i| aml[i..i + 4] == name)
+        .filter(|&i| aml[i - 1] == NAME_OP || (i >= 2 && aml[i - 2..i] == [NAME_OP, ROOT_PREFIX]))
+        .find_map(|i| parse_sleep_package(&aml[i + 4..]))
+}
+
+/// Parses a package of at least two integers, the SLP_TYPa and SLP_TYPb values.
+fn parse_sleep_package(aml: &[u8]) -> Option<SleepType> {
+    if *aml.first()? != PACKAGE_OP {
+        return None;
+    }
+
+    // the top two bits of the first byte are the number of additional length bytes
+    let length_bytes = (*aml.get(1)? >> 6) as usize;
+    let element_count_offset = 2 + length_bytes;
+    if *aml.get(element_count_offset)? < 2 {
+        return None;
+    }
+
+    let elements = aml.get(element_count_offset + 1..)?;
+    let (pm1a, length) = parse_integer(elements)?;
+    let (pm1b, _) = parse_integer(elements.get(length..)?)?;
+    Some(SleepType {
+        pm1a: pm1a as u16,
+        pm1b: pm1b as u16,
+    })
+}
+
+/// Parses an integer constant and returns it along with its encoded length.
+fn parse_integer(aml: &[u8]) -> Option<(u32, usize)> {
+    let bytes = |count: usize| -> Option<u32> {
+        let bytes = aml.get(1..1 + count)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u32),
+        )
+    };
+
+    match *aml.first()? {
+        ZERO_OP => Some((0, 1)),
+        ONE_OP => Some((1, 1)),
+        BYTE_PREFIX => Some((bytes(1)?, 2)),
+        WORD_PREFIX => Some((bytes(2)?, 3)),
+        DWORD_PREFIX => Some((bytes(4)?, 5)),
+        _ => None,
+    }
+}
+
+#[test_case]
+fn sleep_packages_are_found() {
+    // Name (\_S5, Package (0x04) { 0x05, Zero, Zero, Zero }), after an unrelated "_S5_"
+    let package = [
+        PACKAGE_OP,
+        0x0A,
+        0x04,
+        BYTE_PREFIX,
+        0x05,
+        ZERO_OP,
+        ZERO_OP,
+        ZERO_OP,
+    ];
+    let parts: [&[u8]; 4] = [b"[_S5_\0", &[NAME_OP], b"\\_S5_", &package];
+    let aml = parts.concat();
+    let expected = SleepType { pm1a: 5, pm1b: 0 };
+    assert_eq!(find_sleep_type(&aml, 5), Some(expected));
+    assert_eq!(find_sleep_type(&aml, 4), None);
+
+    // Name (_S5, Package (0x02) { One, 0x0207 }) with a two byte package length
+    let package = [
+        PACKAGE_OP,
+        0x47,
+        0x00,
+        0x02,
+        ONE_OP,
+        WORD_PREFIX,
+        0x07,
+        0x02,
+    ];
+    let parts: [&[u8]; 3] = [&[NAME_OP], b"_S5_", &package];
+    let aml = parts.concat();
+    let expected = SleepType {
+        pm1a: 1,
+        pm1b: 0x207,
+    };
+    assert_eq!(find_sleep_type(&aml, 5), Some(expected));
+}
@@ -0,0 +1,125 @@
+use super::sdt::{
+    read_u16, read_u32, read_u64, AcpiError, AddressSpace, GenericAddress, Sdt,
+    GENERIC_ADDRESS_LENGTH,
+};
+use x86_64::PhysAddr;
+
+/// The length of the FADT of ACPI 1.0, which lacks everything from the reset register on
+const ACPI_1_LENGTH: usize = 116;
+
+const OFFSET_DSDT: usize = 40;
+const OFFSET_SCI_INTERRUPT: usize = 46;
+const OFFSET_SMI_COMMAND: usize = 48;
+const OFFSET_ACPI_ENABLE: usize = 52;
+const OFFSET_ACPI_DISABLE: usize = 53;
+const OFFSET_PM1A_EVENT_BLOCK: usize = 56;
+const OFFSET_PM1B_EVENT_BLOCK: usize = 60;
+const OFFSET_PM1A_CONTROL_BLOCK: usize = 64;
+const OFFSET_PM1B_CONTROL_BLOCK: usize = 68;
+const OFFSET_PM_TIMER_BLOCK: usize = 76;
+const OFFSET_CENTURY: usize = 108;
+const OFFSET_BOOT_ARCHITECTURE: usize = 109;
+const OFFSET_FLAGS: usize = 112;
+const OFFSET_RESET_REGISTER: usize = 116;
+const OFFSET_RESET_VALUE: usize = 128;
+const OFFSET_X_DSDT: usize = 140;
+const OFFSET_X_PM1A_CONTROL_BLOCK: usize = 172;
+const OFFSET_X_PM1B_CONTROL_BLOCK: usize = 184;
+
+/// The reset register is supported
+const FLAGS_RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+/// The system has an 8042 keyboard controller
+const BOOT_ARCHITECTURE_8042: u16 = 1 << 1;
+
+/// The fixed ACPI description table, describing the power management hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    /// The physical address of the differentiated system description table
+    pub dsdt: PhysAddr,
+    /// The ISA IRQ of the system control interrupt
+    pub sci_interrupt: u16,
+    /// The port to write [Fadt::acpi_enable] to for switching to ACPI mode, zero if the
+    /// system is always in ACPI mode
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: Option<u32>,
+    /// The PM1a control register, which puts the system to sleep
+    pub pm1a_control_block: GenericAddress,
+    pub pm1b_control_block: Option<GenericAddress>,
+    /// The I/O port of the ACPI power management timer
+    pub pm_timer_block: Option<u32>,
+    /// The CMOS register holding the century
+    pub century_register: Option<u8>,
+    pub has_8042: bool,
+    /// The register resetting the system when [Fadt::reset_value] is written to it
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        table.require_length(ACPI_1_LENGTH)?;
+        let bytes = table.bytes;
+        let optional = |value: u32| if value == 0 { None } else { Some(value) };
+        // the extended fields override the 32 bit ones in newer revisions, if they are set
+        let extended = |offset: usize| {
+            if bytes.len() >= offset + GENERIC_ADDRESS_LENGTH {
+                Some(GenericAddress::parse(bytes, offset)).filter(|a| a.address != 0)
+            } else {
+                None
+            }
+        };
+        // the 32 bit fields hold the port of a 16 bit register
+        let io_register = |port: u32| GenericAddress {
+            address_space: AddressSpace::Io,
+            bit_width: 16,
+            bit_offset: 0,
+            access_size: 2,
+            address: u64::from(port),
+        };
+
+        let x_dsdt = if bytes.len() >= OFFSET_X_DSDT + 8 {
+            read_u64(bytes, OFFSET_X_DSDT)
+        } else {
+            0
+        };
+        let dsdt = match x_dsdt {
+            0 => read_u32(bytes, OFFSET_DSDT) as u64,
+            x_dsdt => x_dsdt,
+        };
+
+        let has_reset_register = bytes.len() > OFFSET_RESET_VALUE
+            && read_u32(bytes, OFFSET_FLAGS) & FLAGS_RESET_REGISTER_SUPPORTED != 0;
+
+        Ok(Self {
+            dsdt: PhysAddr::new(dsdt),
+            sci_interrupt: read_u16(bytes, OFFSET_SCI_INTERRUPT),
+            smi_command_port: read_u32(bytes, OFFSET_SMI_COMMAND),
+            acpi_enable: bytes[OFFSET_ACPI_ENABLE],
+            acpi_disable: bytes[OFFSET_ACPI_DISABLE],
+            pm1a_event_block: read_u32(bytes, OFFSET_PM1A_EVENT_BLOCK),
+            pm1b_event_block: optional(read_u32(bytes, OFFSET_PM1B_EVENT_BLOCK)),
+            pm1a_control_block: extended(OFFSET_X_PM1A_CONTROL_BLOCK)
+                .unwrap_or_else(|| io_register(read_u32(bytes, OFFSET_PM1A_CONTROL_BLOCK))),
+            pm1b_control_block: extended(OFFSET_X_PM1B_CONTROL_BLOCK)
+                .or_else(|| optional(read_u32(bytes, OFFSET_PM1B_CONTROL_BLOCK)).map(io_register)),
+            pm_timer_block: optional(read_u32(bytes, OFFSET_PM_TIMER_BLOCK)),
+            century_register: Some(bytes[OFFSET_CENTURY]).filter(|&r| r != 0),
+            // revision 1 tables predate the field and always had an 8042
+            has_8042: table.header.revision < 2
+                || read_u16(bytes, OFFSET_BOOT_ARCHITECTURE) & BOOT_ARCHITECTURE_8042 != 0,
+            reset_register: if has_reset_register {
+                Some(GenericAddress::parse(bytes, OFFSET_RESET_REGISTER))
+            } else {
+                None
+            },
+            reset_value: if has_reset_register {
+                bytes[OFFSET_RESET_VALUE]
+            } else {
+                0
+            },
+        })
+    }
+}
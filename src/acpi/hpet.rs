@@ -0,0 +1,27 @@
+use super::sdt::{read_u16, read_u32, AcpiError, GenericAddress, Sdt, HEADER_LENGTH};
+
+/// The HPET description table, locating the registers of a high precision event timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HpetTable {
+    /// A copy of the capabilities register, with the vendor and the number of comparators
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    /// The sequence number of this timer block
+    pub number: u8,
+    /// The minimum main counter ticks between two periodic interrupts
+    pub minimum_tick: u16,
+}
+
+impl HpetTable {
+    pub fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        table.require_length(HEADER_LENGTH + 20)?;
+        let body = table.body();
+
+        Ok(Self {
+            event_timer_block_id: read_u32(body, 0),
+            base_address: GenericAddress::parse(body, 4),
+            number: body[16],
+            minimum_tick: read_u16(body, 17),
+        })
+    }
+}
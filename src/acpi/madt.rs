@@ -0,0 +1,217 @@
+use super::sdt::{read_u16, read_u32, read_u64, AcpiError, Sdt};
+use crate::interrupt::apic::{
+    InterruptOverride, IoApicDescriptor, Polarity, Topology, TriggerMode,
+};
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+/// The system also has the dual 8259 PICs
+const FLAGS_PCAT_COMPATIBLE: u32 = 1 << 0;
+
+/// The multiple APIC description table, listing the interrupt controllers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    /// The physical address of the local APIC registers, with any override applied
+    pub local_apic_address: PhysAddr,
+    pub pcat_compatible: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicDescriptor>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// The processor is ready to use
+    pub enabled: bool,
+    /// The processor is disabled, but can be brought online
+    pub online_capable: bool,
+}
+
+/// A local APIC interrupt input wired to the NMI line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// The ACPI processor UID, `0xFF` for all processors
+    pub processor_uid: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    /// LINT0 or LINT1
+    pub lint: u8,
+}
+
+impl Madt {
+    pub fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        table.require_length(44)?;
+        let body = table.body();
+
+        let mut madt = Self {
+            local_apic_address: PhysAddr::new(read_u32(body, 0) as u64),
+            pcat_compatible: read_u32(body, 4) & FLAGS_PCAT_COMPATIBLE != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        let mut offset = 8;
+        while offset + 2 <= body.len() {
+            let (entry_type, length) = (body[offset], body[offset + 1] as usize);
+            if length < 2 || offset + length > body.len() {
+                return Err(AcpiError::TableTooShort(table.header.address));
+            }
+            madt.parse_entry(entry_type, &body[offset..offset + length]);
+            offset += length;
+        }
+
+        Ok(madt)
+    }
+
+    fn parse_entry(&mut self, entry_type: u8, entry: &[u8]) {
+        match (entry_type, entry.len()) {
+            (ENTRY_LOCAL_APIC, 8..=usize::MAX) => {
+                let flags = read_u32(entry, 4);
+                self.processors.push(Processor {
+                    processor_uid: entry[2] as u32,
+                    apic_id: entry[3] as u32,
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            (ENTRY_IO_APIC, 12..=usize::MAX) => self.io_apics.push(IoApicDescriptor {
+                id: entry[2],
+                address: PhysAddr::new(read_u32(entry, 4) as u64),
+                gsi_base: read_u32(entry, 8),
+            }),
+            (ENTRY_INTERRUPT_SOURCE_OVERRIDE, 10..=usize::MAX) => {
+                let (polarity, trigger) = decode_mps_flags(read_u16(entry, 8));
+                self.overrides.push(InterruptOverride {
+                    irq: entry[3],
+                    gsi: read_u32(entry, 4),
+                    polarity,
+                    trigger,
+                });
+            }
+            (ENTRY_LOCAL_APIC_NMI, 6..=usize::MAX) => {
+                let (polarity, trigger) = decode_mps_flags(read_u16(entry, 3));
+                self.nmis.push(LocalApicNmi {
+                    processor_uid: entry[2],
+                    polarity,
+                    trigger,
+                    lint: entry[5],
+                });
+            }
+            (ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE, 12..=usize::MAX) => {
+                self.local_apic_address = PhysAddr::new(read_u64(entry, 4));
+            }
+            (ENTRY_LOCAL_X2APIC, 16..=usize::MAX) => {
+                let flags = read_u32(entry, 8);
+                self.processors.push(Processor {
+                    processor_uid: read_u32(entry, 12),
+                    apic_id: read_u32(entry, 4),
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            // entries for other interrupt controllers, or truncated ones
+            _ => {}
+        }
+    }
+
+    /// Returns how the interrupt controllers are wired, for [crate::interrupt::init_apic].
+    pub fn apic_topology(&self) -> Topology {
+        Topology {
+            local_apic_address: Some(self.local_apic_address),
+            io_apics: self.io_apics.clone(),
+            overrides: self.overrides.clone(),
+        }
+    }
+}
+
+/// Decodes the polarity and trigger mode flags, where "conforms to the bus" means active
+/// high and edge triggered for ISA interrupts.
+fn decode_mps_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+    (polarity, trigger)
+}
+
+#[test_case]
+fn entries_are_parsed() {
+    use super::sdt::{SdtHeader, HEADER_LENGTH};
+    use alloc::vec;
+
+    let mut bytes = vec![0u8; HEADER_LENGTH];
+    bytes.extend_from_slice(&0xFEE0_0000u32.to_le_bytes());
+    bytes.extend_from_slice(&FLAGS_PCAT_COMPATIBLE.to_le_bytes());
+    // processor 0 with APIC ID 1, enabled
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC, 8, 0, 1, 1, 0, 0, 0]);
+    // I/O APIC 2 at 0xFEC00000 from GSI 0
+    bytes.extend_from_slice(&[ENTRY_IO_APIC, 12, 2, 0, 0, 0, 0xC0, 0xFE, 0, 0, 0, 0]);
+    // IRQ 9 to GSI 9, active low and level triggered
+    bytes.extend_from_slice(&[
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE,
+        10,
+        0,
+        9,
+        9,
+        0,
+        0,
+        0,
+        0x0F,
+        0,
+    ]);
+    // an unknown entry is skipped
+    bytes.extend_from_slice(&[0x7F, 4, 0, 0]);
+    // LINT1 of all processors is the NMI
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC_NMI, 6, 0xFF, 0, 0, 1]);
+
+    let bytes: &'static [u8] = alloc::boxed::Box::leak(bytes.into_boxed_slice());
+    let table = Sdt {
+        header: SdtHeader {
+            signature: *b"APIC",
+            length: bytes.len() as u32,
+            revision: 1,
+            oem_id: *b"VOLUSP",
+            oem_table_id: *b"TESTTABL",
+            address: PhysAddr::new(0),
+        },
+        bytes,
+    };
+    let madt = Madt::parse(&table).unwrap();
+
+    assert!(madt.pcat_compatible);
+    assert_eq!(madt.local_apic_address, PhysAddr::new(0xFEE0_0000));
+    assert_eq!(madt.processors.len(), 1);
+    assert_eq!(madt.processors[0].apic_id, 1);
+    assert!(madt.processors[0].enabled);
+    assert_eq!(madt.io_apics[0].id, 2);
+    assert_eq!(madt.io_apics[0].address, PhysAddr::new(0xFEC0_0000));
+    assert_eq!(
+        madt.overrides,
+        [InterruptOverride {
+            irq: 9,
+            gsi: 9,
+            polarity: Polarity::ActiveLow,
+            trigger: TriggerMode::Level,
+        }]
+    );
+    assert_eq!(madt.nmis[0].lint, 1);
+    assert_eq!(madt.apic_topology().resolve(9).trigger, TriggerMode::Level);
+}
@@ -0,0 +1,35 @@
+use super::sdt::{read_u16, read_u64, AcpiError, Sdt};
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+const ENTRY_LENGTH: usize = 16;
+/// The entries follow 8 reserved bytes
+const ENTRIES_OFFSET: usize = 8;
+
+/// Where the memory mapped configuration space of a PCI segment group is located.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// The address of the configuration space of bus 0, even if `start_bus` is higher
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Parses the entries of the MCFG table, one per PCI segment group.
+pub fn parse(table: &Sdt) -> Result<Vec<McfgEntry>, AcpiError> {
+    let body = table.body();
+    if body.len() < ENTRIES_OFFSET {
+        return Err(AcpiError::TableTooShort(table.header.address));
+    }
+
+    Ok(body[ENTRIES_OFFSET..]
+        .chunks_exact(ENTRY_LENGTH)
+        .map(|entry| McfgEntry {
+            base_address: PhysAddr::new(read_u64(entry, 0)),
+            segment_group: read_u16(entry, 8),
+            start_bus: entry[10],
+            end_bus: entry[11],
+        })
+        .collect())
+}
@@ -0,0 +1,293 @@
+mod aml;
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+mod sdt;
+
+pub use aml::SleepType;
+pub use fadt::Fadt;
+pub use hpet::HpetTable;
+pub use madt::{LocalApicNmi, Madt, Processor};
+pub use mcfg::McfgEntry;
+pub use sdt::{AcpiError, AddressSpace, GenericAddress, Sdt, SdtHeader};
+
+use crate::{print, println, serial_println};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::iter;
+use sdt::{checksum_valid, physical_bytes, read_u16, read_u32, read_u64, HEADER_LENGTH};
+use spin::Mutex;
+use x86_64::PhysAddr;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// The RSDP of ACPI 1.0, the checksum covers only these bytes
+const RSDP_V1_LENGTH: usize = 20;
+const RSDP_V2_LENGTH: usize = 36;
+/// The RSDP is aligned to 16 bytes
+const RSDP_ALIGNMENT: usize = 16;
+
+/// Holds the real mode segment of the extended BIOS data area
+const EBDA_SEGMENT_POINTER: u64 = 0x40E;
+/// Only the first KiB of the EBDA may hold the RSDP
+const EBDA_SCAN_LENGTH: usize = 1024;
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+
+/// Everything parsed by [init].
+static TABLES: Mutex<Option<Tables>> = Mutex::new(None);
+
+struct Tables {
+    rsdp: Rsdp,
+    headers: Vec<SdtHeader>,
+    madt: Option<Madt>,
+    fadt: Option<Fadt>,
+    hpet: Option<HpetTable>,
+    mcfg: Vec<McfgEntry>,
+}
+
+/// The root system description pointer, leading to the other tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    pub address: PhysAddr,
+    /// 0 for ACPI 1.0, 2 for every later version
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub rsdt_address: u32,
+    /// The 64 bit successor of the RSDT, since revision 2
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    /// Parses and validates the RSDP at the start of `bytes`, which have to hold at least
+    /// [RSDP_V2_LENGTH] bytes.
+    fn parse(bytes: &[u8], address: PhysAddr) -> Option<Self> {
+        if &bytes[..8] != RSDP_SIGNATURE || !checksum_valid(&bytes[..RSDP_V1_LENGTH]) {
+            return None;
+        }
+
+        let revision = bytes[15];
+        let xsdt_address = if revision >= 2 {
+            let length = read_u32(bytes, 20) as usize;
+            if length < RSDP_V2_LENGTH || !checksum_valid(&bytes[..RSDP_V2_LENGTH]) {
+                return None;
+            }
+            Some(read_u64(bytes, 24)).filter(|&address| address != 0)
+        } else {
+            None
+        };
+
+        Some(Self {
+            address,
+            revision,
+            oem_id: bytes[9..15].try_into().unwrap(),
+            rsdt_address: read_u32(bytes, 16),
+            xsdt_address,
+        })
+    }
+}
+
+pub fn init() {
+    print!("Parsing ACPI tables...   ");
+
+    match unsafe { load() } {
+        Ok(tables) => {
+            println!("[Ok]");
+            serial_println!(
+                "ACPI: revision {} RSDP at {:#x}",
+                tables.rsdp.revision,
+                tables.rsdp.address.as_u64()
+            );
+            for header in tables.headers.iter() {
+                serial_println!(
+                    "ACPI: {} at {:#x}, {} bytes",
+                    header.signature(),
+                    header.address.as_u64(),
+                    header.length
+                );
+            }
+            *TABLES.lock() = Some(tables);
+        }
+        Err(e) => {
+            println!("[Failed]");
+            serial_println!("ACPI: {:?}", e);
+        }
+    }
+}
+
+/// Returns `true` if [init] found and parsed the ACPI tables.
+pub fn is_available() -> bool {
+    TABLES.lock().is_some()
+}
+
+pub fn rsdp() -> Option<Rsdp> {
+    TABLES.lock().as_ref().map(|tables| tables.rsdp)
+}
+
+/// Returns the headers of all tables listed by the RSDT or XSDT.
+pub fn tables() -> Vec<SdtHeader> {
+    TABLES
+        .lock()
+        .as_ref()
+        .map_or_else(Vec::new, |tables| tables.headers.clone())
+}
+
+/// Returns the first table with the given signature, e.g. `b"SSDT"`.
+pub fn find_table(signature: &[u8; 4]) -> Option<Sdt> {
+    let address = tables()
+        .into_iter()
+        .find(|header| &header.signature == signature)?
+        .address;
+    // the table was validated in init, and the firmware does not move it
+    unsafe { Sdt::from_address(address).ok() }
+}
+
+pub fn madt() -> Option<Madt> {
+    TABLES.lock().as_ref()?.madt.clone()
+}
+
+pub fn fadt() -> Option<Fadt> {
+    TABLES.lock().as_ref()?.fadt
+}
+
+pub fn hpet() -> Option<HpetTable> {
+    TABLES.lock().as_ref()?.hpet
+}
+
+/// Returns the differentiated system description table, holding the AML of the platform.
+pub fn dsdt() -> Option<Sdt> {
+    let address = fadt()?.dsdt;
+    // the DSDT is not listed in the RSDT, so it is validated here
+    unsafe { Sdt::from_address(address).ok() }.filter(|table| &table.header.signature == b"DSDT")
+}
+
+/// Returns the SLP_TYP values of the sleep state `state`, e.g. 5 for soft off, from the
+/// DSDT or any SSDT.
+pub fn sleep_type(state: u8) -> Option<SleepType> {
+    let ssdts = tables()
+        .into_iter()
+        .filter(|header| &header.signature == b"SSDT")
+        .filter_map(|header| unsafe { Sdt::from_address(header.address).ok() });
+
+    dsdt()
+        .into_iter()
+        .chain(ssdts)
+        .find_map(|table| aml::find_sleep_type(table.body(), state))
+}
+
+/// Returns the memory mapped PCI configuration space of every segment group.
+pub fn mcfg() -> Vec<McfgEntry> {
+    TABLES
+        .lock()
+        .as_ref()
+        .map_or_else(Vec::new, |tables| tables.mcfg.clone())
+}
+
+/// Finds the RSDP, walks the RSDT or XSDT and parses the known tables. Tables that fail
+/// to validate are skipped.
+///
+/// This function is unsafe because the complete physical memory has to be mapped.
+unsafe fn load() -> Result<Tables, AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+
+    let (root_address, entry_size) = match rsdp.xsdt_address {
+        Some(address) => (PhysAddr::new(address), 8),
+        None => (PhysAddr::new(rsdp.rsdt_address as u64), 4),
+    };
+    let root = Sdt::from_address(root_address)?;
+    let expected_signature = if entry_size == 8 { b"XSDT" } else { b"RSDT" };
+    if &root.header.signature != expected_signature {
+        return Err(AcpiError::UnexpectedSignature(root_address));
+    }
+
+    let mut tables = Tables {
+        rsdp,
+        headers: Vec::new(),
+        madt: None,
+        fadt: None,
+        hpet: None,
+        mcfg: Vec::new(),
+    };
+
+    for entry in root.body().chunks_exact(entry_size) {
+        let address = match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        };
+        let table = match Sdt::from_address(PhysAddr::new(address)) {
+            Ok(table) => table,
+            Err(e) => {
+                serial_println!("ACPI: skipping table: {:?}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = tables.parse(&table) {
+            serial_println!("ACPI: skipping {}: {:?}", table.header.signature(), e);
+            continue;
+        }
+        tables.headers.push(table.header);
+    }
+
+    Ok(tables)
+}
+
+impl Tables {
+    fn parse(&mut self, table: &Sdt) -> Result<(), AcpiError> {
+        match &table.header.signature {
+            b"APIC" => self.madt = Some(Madt::parse(table)?),
+            b"FACP" => self.fadt = Some(Fadt::parse(table)?),
+            b"HPET" => self.hpet = Some(HpetTable::parse(table)?),
+            b"MCFG" => self.mcfg = mcfg::parse(table)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Scans the first KiB of the EBDA and then the BIOS read-only memory for the RSDP.
+unsafe fn find_rsdp() -> Option<Rsdp> {
+    let ebda_segment = read_u16(physical_bytes(PhysAddr::new(EBDA_SEGMENT_POINTER), 2), 0);
+    let ebda = PhysAddr::new((ebda_segment as u64) << 4);
+
+    // without an EBDA the pointer is zero, and the interrupt vector table is not worth a scan
+    let ebda_area = (ebda_segment != 0).then(|| (ebda, EBDA_SCAN_LENGTH));
+    let bios_area = (
+        PhysAddr::new(BIOS_AREA_START),
+        (BIOS_AREA_END - BIOS_AREA_START) as usize,
+    );
+
+    ebda_area
+        .into_iter()
+        .chain(iter::once(bios_area))
+        .find_map(|(start, length)| {
+            let bytes = physical_bytes(start, length);
+            (0..=length - RSDP_V2_LENGTH)
+                .step_by(RSDP_ALIGNMENT)
+                .find_map(|offset| Rsdp::parse(&bytes[offset..], start + offset as u64))
+        })
+}
+
+#[test_case]
+fn tables_are_found() {
+    if !is_available() {
+        return;
+    }
+
+    let rsdp = rsdp().unwrap();
+    assert_eq!(rsdp.address.as_u64() % RSDP_ALIGNMENT as u64, 0);
+
+    for header in tables() {
+        let table = find_table(&header.signature).expect("listed table not found");
+        assert!(checksum_valid(table.bytes));
+        assert!(table.bytes.len() >= HEADER_LENGTH);
+    }
+
+    let madt = madt().expect("no MADT");
+    assert!(madt.processors.iter().any(|processor| processor.enabled));
+    assert!(!madt.io_apics.is_empty());
+    let fadt = fadt().expect("no FADT");
+    // QEMU, like nearly every PC, has its PM1 registers in I/O space
+    assert_eq!(fadt.pm1a_control_block.address_space, AddressSpace::Io);
+    assert!(sleep_type(5).is_some(), "no \\_S5 package");
+}
@@ -0,0 +1,180 @@
+use crate::memory;
+use core::convert::TryInto;
+use core::fmt;
+use core::slice;
+use x86_64::PhysAddr;
+
+/// The length of the header every system description table starts with
+pub const HEADER_LENGTH: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP was found in the BIOS areas
+    NoRsdp,
+    /// The table or structure at the contained address has an invalid checksum
+    InvalidChecksum(PhysAddr),
+    /// The table at the contained address is shorter than its fixed fields
+    TableTooShort(PhysAddr),
+    /// The table at the contained address does not have the expected signature
+    UnexpectedSignature(PhysAddr),
+}
+
+/// The header of a system description table.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// The length of the table including the header
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    /// The physical address of the table
+    pub address: PhysAddr,
+}
+
+impl SdtHeader {
+    fn parse(bytes: &[u8], address: PhysAddr) -> Self {
+        Self {
+            signature: bytes[0..4].try_into().unwrap(),
+            length: read_u32(bytes, 4),
+            revision: bytes[8],
+            oem_id: bytes[10..16].try_into().unwrap(),
+            oem_table_id: bytes[16..24].try_into().unwrap(),
+            address,
+        }
+    }
+
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+}
+
+impl fmt::Debug for SdtHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SdtHeader")
+            .field("signature", &self.signature())
+            .field("length", &self.length)
+            .field("revision", &self.revision)
+            .field("oem_id", &core::str::from_utf8(&self.oem_id).unwrap_or("?"))
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+/// A register location in memory or I/O space, as used by the FADT and the HPET table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    /// 1 for byte, 2 for word, 3 for dword and 4 for qword access, 0 if undefined
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    Memory,
+    Io,
+    PciConfiguration,
+    Other(u8),
+}
+
+/// The length of a generic address structure
+pub const GENERIC_ADDRESS_LENGTH: usize = 12;
+
+impl GenericAddress {
+    pub fn parse(bytes: &[u8], offset: usize) -> Self {
+        Self {
+            address_space: match bytes[offset] {
+                0 => AddressSpace::Memory,
+                1 => AddressSpace::Io,
+                2 => AddressSpace::PciConfiguration,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address: read_u64(bytes, offset + 4),
+        }
+    }
+}
+
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Returns `true` if the bytes sum up to zero, as every ACPI structure does.
+pub fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Returns the `length` bytes at `address` through the physical memory mapping.
+///
+/// This function is unsafe because the caller must guarantee that the range is mapped
+/// and is not written to while the slice is alive, which holds for the ACPI tables.
+pub unsafe fn physical_bytes(address: PhysAddr, length: usize) -> &'static [u8] {
+    let virt = memory::physical_memory_offset() + address.as_u64();
+    slice::from_raw_parts(virt.as_ptr(), length)
+}
+
+/// A validated system description table.
+#[derive(Debug, Clone, Copy)]
+pub struct Sdt {
+    pub header: SdtHeader,
+    /// The complete table, including the header
+    pub bytes: &'static [u8],
+}
+
+impl Sdt {
+    /// Reads and validates the table at `address`.
+    ///
+    /// This function is unsafe for the same reason as [physical_bytes].
+    pub unsafe fn from_address(address: PhysAddr) -> Result<Self, AcpiError> {
+        let header = SdtHeader::parse(physical_bytes(address, HEADER_LENGTH), address);
+        if (header.length as usize) < HEADER_LENGTH {
+            return Err(AcpiError::TableTooShort(address));
+        }
+
+        let bytes = physical_bytes(address, header.length as usize);
+        if !checksum_valid(bytes) {
+            return Err(AcpiError::InvalidChecksum(address));
+        }
+        Ok(Self { header, bytes })
+    }
+
+    /// Returns the bytes after the header.
+    pub fn body(&self) -> &'static [u8] {
+        &self.bytes[HEADER_LENGTH..]
+    }
+
+    /// Fails with [AcpiError::TableTooShort] unless the table has `length` bytes, including
+    /// the header.
+    pub fn require_length(&self, length: usize) -> Result<(), AcpiError> {
+        if self.bytes.len() < length {
+            return Err(AcpiError::TableTooShort(self.header.address));
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn checksums_and_fields() {
+    let mut bytes = [0x12u8, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0];
+    bytes[8] = 0u8.wrapping_sub(bytes[..8].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+    assert!(checksum_valid(&bytes));
+    bytes[0] ^= 1;
+    assert!(!checksum_valid(&bytes));
+
+    assert_eq!(read_u16(&bytes, 2), 0x7856);
+    assert_eq!(read_u32(&bytes, 4), 0xF0DE_BC9A);
+    assert_eq!(read_u64(&bytes, 0), 0xF0DE_BC9A_7856_3413);
+}
@@ -0,0 +1,173 @@
+use super::{map_heap_region, HeapStats, Locked, HEAP_GROWTH_STEP, HEAP_MAX_SIZE};
+use core::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr, ptr::NonNull};
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+/// The block sizes to use.
+///
+/// The sizes must each be a power of 2 because they are also used as
+/// the block alignment (alignments must always be powers of 2).
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// An allocator that serves small allocations from per-size free lists and falls back
+/// to a linked list allocator for everything larger than the biggest block size.
+///
+/// Freed blocks are never merged or handed back to the fallback allocator,
+/// they are pushed onto the free list of their size and reused for the next allocation.
+/// When the fallback allocator runs out of memory, the heap is grown by mapping new
+/// frames directly behind it, up to `HEAP_MAX_SIZE`.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: linked_list_allocator::Heap,
+    in_use: usize,
+    peak: usize,
+    allocations: usize,
+    total_allocations: usize,
+}
+
+impl FixedSizeBlockAllocator {
+    /// Creates an empty allocator, every allocation fails until [init](Self::init) is called.
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        Self {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: linked_list_allocator::Heap::empty(),
+            in_use: 0,
+            peak: 0,
+            allocations: 0,
+            total_allocations: 0,
+        }
+    }
+
+    /// Initializes the allocator with the given heap bounds.
+    ///
+    /// This function is unsafe because the caller must guarantee that the given
+    /// heap bounds are valid and that the heap is unused. This method must be
+    /// called only once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.fallback_allocator.size(),
+            in_use: self.in_use,
+            peak: self.peak,
+            reserved: self.fallback_allocator.used(),
+            allocations: self.allocations,
+            total_allocations: self.total_allocations,
+        }
+    }
+
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        loop {
+            match self.fallback_allocator.allocate_first_fit(layout) {
+                Ok(ptr) => return ptr.as_ptr(),
+                Err(_) if self.grow(layout) => {}
+                Err(_) => return ptr::null_mut(),
+            }
+        }
+    }
+
+    /// Maps more memory behind the end of the heap, enough for at least `layout`.
+    ///
+    /// Returns `false` if the heap would exceed `HEAP_MAX_SIZE` or no frames are left.
+    fn grow(&mut self, layout: Layout) -> bool {
+        let heap_size = self.fallback_allocator.size();
+        if heap_size == 0 {
+            // not initialized yet
+            return false;
+        }
+
+        let page_size = Size4KiB::SIZE as usize;
+        let required = layout.size() + layout.align();
+        let by = (required.max(HEAP_GROWTH_STEP) + page_size - 1) & !(page_size - 1);
+        let by = by.min(HEAP_MAX_SIZE - heap_size);
+        if by < required {
+            return false;
+        }
+
+        match map_heap_region(self.fallback_allocator.top(), by) {
+            Ok(()) => {
+                unsafe { self.fallback_allocator.extend(by) };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn record_alloc(&mut self, layout: &Layout) {
+        self.in_use += layout.size();
+        self.peak = self.peak.max(self.in_use);
+        self.allocations += 1;
+        self.total_allocations += 1;
+    }
+
+    fn record_dealloc(&mut self, layout: &Layout) {
+        self.in_use -= layout.size();
+        self.allocations -= 1;
+    }
+}
+
+/// Chooses an appropriate block size for the given layout.
+///
+/// Returns an index into the `BLOCK_SIZES` array.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = match list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    // no block exists in list => allocate new block
+                    let block_size = BLOCK_SIZES[index];
+                    // only works if all block sizes are a power of 2
+                    let block_align = block_size;
+                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                    allocator.fallback_alloc(layout)
+                }
+            },
+            None => allocator.fallback_alloc(layout),
+        };
+
+        if !ptr.is_null() {
+            allocator.record_alloc(&layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.record_dealloc(&layout);
+
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                // verify that block has size and alignment required for storing node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => {
+                let ptr = NonNull::new(ptr).unwrap();
+                allocator.fallback_allocator.deallocate(ptr, layout);
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::memory::{self, MemoryError};
+use crate::{print, println};
+use core::fmt;
+use fixed_size_block::FixedSizeBlockAllocator;
+use spin::{Mutex, MutexGuard};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+mod fixed_size_block;
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// The hard cap the heap may grow to, the region above `HEAP_START` is reserved up to here
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// The minimum amount of memory mapped at once when the heap grows
+const HEAP_GROWTH_STEP: usize = 64 * 1024; // 64 KiB
+
+#[global_allocator]
+static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+
+/// Maps the initial heap region to fresh frames and hands it to the global allocator.
+pub fn init_heap() -> Result<(), MemoryError> {
+    print!("Initializing heap...   ");
+
+    map_heap_region(HEAP_START, HEAP_SIZE)?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    println!("[Ok]");
+    Ok(())
+}
+
+/// Returns a snapshot of the heap usage.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// Maps `size` bytes starting at `start` to freshly allocated frames.
+fn map_heap_region(start: usize, size: usize) -> Result<(), MemoryError> {
+    let first = Page::containing_address(VirtAddr::new(start as u64));
+    let last = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    memory::map_range(Page::range_inclusive(first, last), flags)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes currently mapped for the heap
+    pub size: usize,
+    /// Bytes requested by allocations that are still alive
+    pub in_use: usize,
+    /// The highest value `in_use` has reached
+    pub peak: usize,
+    /// Bytes taken from the heap, including rounding to block sizes and freed blocks
+    /// kept for reuse
+    pub reserved: usize,
+    /// Allocations that are still alive
+    pub allocations: usize,
+    /// Allocations made since the heap was initialized
+    pub total_allocations: usize,
+}
+
+impl HeapStats {
+    /// The percentage of reserved heap memory that is not used by any allocation.
+    pub fn fragmentation(&self) -> usize {
+        match self.reserved {
+            0 => 0,
+            reserved => (reserved - self.in_use) * 100 / reserved,
+        }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "heap: {} KiB mapped, {} B in use (peak {} B), {} B reserved, {} live / {} total allocations, {}% fragmentation",
+            self.size / 1024,
+            self.in_use,
+            self.peak,
+            self.reserved,
+            self.allocations,
+            self.total_allocations,
+            self.fragmentation()
+        )
+    }
+}
+
+/// A wrapper around `spin::Mutex`, needed because `GlobalAlloc` can't be implemented
+/// for a foreign type.
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<A> {
+        self.inner.lock()
+    }
+}
@@ -1,11 +1,26 @@
 use core::mem::transmute;
+use core::slice;
 use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
 use volatile::Volatile;
 
-use crate::serial_print;
+use crate::graphics::{self, Canvas};
+use crate::memory::{self, MemoryError};
+use crate::pci::{self, Bar};
+use crate::serial_println;
 
 const VBE_DISPI_IOPORT_INDEX: u16 = 0x01CE;
 const VBE_DISPI_IOPORT_DATA: u16 = 0x01CF;
+const VBE_DISPI_INDEX_VIDEO_MEMORY_64K: u16 = 0x0A;
+
+/// (vendor, device) of the QEMU/Bochs stdvga and the VirtualBox graphics adapter
+const BGA_PCI_IDS: [(u16, u16); 2] = [(0x1234, 0x1111), (0x80EE, 0xBEEF)];
+/// The framebuffer address Bochs uses when there is no PCI device to ask
+const BGA_DEFAULT_LFB_ADDRESS: u64 = 0xE000_0000;
+
+/// The virtual address the linear framebuffer gets mapped to
+const FRAMEBUFFER_START: u64 = 0x_5555_0000_0000;
 
 const KB64: u16 = u16::MAX;
 
@@ -13,7 +28,9 @@ pub struct BgaController {
     port_reg: Port<u16>,
     port_data: Port<u16>,
     resolution: (u16,u16),
+    depth: u16,
     n_banks: u16,
+    lfb: Option<VirtAddr>,
 }
 
 impl BgaController {
@@ -22,14 +39,46 @@ impl BgaController {
             port_reg: Port::new(VBE_DISPI_IOPORT_INDEX),
             port_data: Port::new(VBE_DISPI_IOPORT_DATA),
             resolution: (640,480),
+            depth: 0x20,
             n_banks: 0,
+            lfb: None,
         };
+        if let Err(e) = controler.set_lfb_mode() {
+            serial_println!("BGA: linear framebuffer unavailable ({:?}), using banked mode", e);
+        }
         controler.set_res(controler.resolution.0, controler.resolution.1, 0x20);
         //controler.write_gibberish();
         controler
     }
 
-    fn set_lfb_mode(&mut self) {
+    /// Locates the linear framebuffer and maps it write-combining at `FRAMEBUFFER_START`,
+    /// after which all drawing goes through [framebuffer](Self::framebuffer) instead of
+    /// the banked window at `0xA0000`.
+    fn set_lfb_mode(&mut self) -> Result<(), MemoryError> {
+        let lfb_address = find_lfb_address().unwrap_or(BGA_DEFAULT_LFB_ADDRESS);
+
+        let video_memory = match self.read_from_reg(VBE_DISPI_INDEX_VIDEO_MEMORY_64K) {
+            0 => 16 * 1024 * 1024, // registers of older BGA versions don't report it
+            blocks => blocks as u64 * 64 * 1024,
+        };
+
+        let first_page = Page::containing_address(VirtAddr::new(FRAMEBUFFER_START));
+        let last_page = Page::containing_address(VirtAddr::new(FRAMEBUFFER_START + video_memory - 1));
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | memory::WRITE_COMBINING;
+
+        let start_frame = PhysFrame::containing_address(PhysAddr::new(lfb_address));
+        match unsafe { memory::map_physical_range(start_frame, Page::range_inclusive(first_page, last_page), flags) } {
+            // another controller already mapped the framebuffer
+            Ok(()) | Err(MemoryError::PageAlreadyMapped(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        serial_println!("BGA: linear framebuffer at {:#x}, {} KiB video memory", lfb_address, video_memory / 1024);
+        self.lfb = Some(first_page.start_address());
+        Ok(())
     }
 
     pub fn set_res(&mut self, x: u16, y: u16, depth: u16) {
@@ -39,12 +88,54 @@ impl BgaController {
         self.write_to_reg(0x01, x);
         //set yres
         self.write_to_reg(0x02, y);
-        //set collor depth to 15 bit
+        //set collor depth
         self.write_to_reg(0x03, depth);
-        //enable bga
+        //enable bga with the linear framebuffer
         self.write_to_reg(0x04, 0x01 | 0x40);
 
-        self.n_banks = (((x as usize) * (y as usize) * (depth as usize)) as f64 / KB64 as f64) as u16;
+        self.resolution = (x, y);
+        self.depth = depth;
+        let bytes = (x as usize) * (y as usize) * (depth as usize / 8);
+        self.n_banks = ((bytes + KB64 as usize) / (KB64 as usize + 1)) as u16;
+    }
+
+    pub fn resolution(&self) -> (u16, u16) {
+        self.resolution
+    }
+
+    /// Returns the visible part of the linear framebuffer, one `u32` per pixel in row-major order.
+    ///
+    /// Returns `None` if the framebuffer could not be mapped or if the color depth is not 32 bit.
+    pub fn framebuffer(&mut self) -> Option<&mut [u32]> {
+        let lfb = self.lfb?;
+        if self.depth != 0x20 {
+            return None;
+        }
+
+        let len = self.resolution.0 as usize * self.resolution.1 as usize;
+        Some(unsafe { slice::from_raw_parts_mut(lfb.as_mut_ptr::<u32>(), len) })
+    }
+
+    /// Returns the linear framebuffer as a [Canvas], or `None` under the same conditions
+    /// as [framebuffer](Self::framebuffer).
+    pub fn surface(&mut self) -> Option<BgaSurface> {
+        let (width, height) = (self.resolution.0 as usize, self.resolution.1 as usize);
+        let pixels = graphics::as_pixels_mut(self.framebuffer()?);
+        Some(BgaSurface { pixels, width, height })
+    }
+
+    /// Gives up the controller for a surface that lives as long as the framebuffer mapping,
+    /// so the resolution can't change underneath it.
+    pub fn into_surface(mut self) -> Option<BgaSurface<'static>> {
+        let (width, height) = (self.resolution.0 as usize, self.resolution.1 as usize);
+        let framebuffer = self.framebuffer()?;
+        // the mapping at FRAMEBUFFER_START is never removed
+        let framebuffer = unsafe { slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), framebuffer.len()) };
+        Some(BgaSurface {
+            pixels: graphics::as_pixels_mut(framebuffer),
+            width,
+            height,
+        })
     }
 
     pub fn write_to_reg(&mut self, reg: u16, data: u16) {
@@ -62,6 +153,11 @@ impl BgaController {
     }
 
     pub fn write_gibberish(&mut self) {
+        if let Some(framebuffer) = self.framebuffer() {
+            framebuffer.fill(Pixel::new(255, 0, 255).into());
+            return;
+        }
+
         for b in 0..self.n_banks {
            let bank = unsafe{
                 &mut *(0xA0000 as *mut [Volatile<u32>; (KB64 / 4) as usize])
@@ -81,25 +177,36 @@ impl BgaController {
     }
 
     pub fn set_pixel(&mut self, x: u16, y: u16, pixel: u32) -> Result<(),VbaError> {
-        if x > self.resolution.0 {
+        if x >= self.resolution.0 {
             return Err(VbaError::PixelOutOfBound);
         }
-        if y > self.resolution.1 {
+        if y >= self.resolution.1 {
             return Err(VbaError::PixelOutOfBound);
         }
-        let offset = x + y * self.resolution.0;
-        let bank_num_f = (offset as f32 / (KB64 / 4) as f32);
-        let bank_num = unsafe { bank_num_f.to_int_unchecked::<u16>() };
+        let offset = x as usize + y as usize * self.resolution.0 as usize;
+
+        if let Some(framebuffer) = self.framebuffer() {
+            framebuffer[offset] = pixel;
+            return Ok(());
+        }
+
+        let bank_size = (KB64 as usize + 1) / 4;
+        let bank_num = (offset / bank_size) as u16;
         self.write_to_reg(0x05, bank_num);
-        let bank_pixel_pos = offset - bank_num * (KB64 / 4);
+        let bank_pixel_pos = offset % bank_size;
         let bank = unsafe{
             &mut *(0xA0000 as *mut [Volatile<u32>; (KB64 / 4) as usize])
         };
-        bank[bank_pixel_pos as usize].write(pixel);
+        bank[bank_pixel_pos].write(pixel);
         Ok(())
     }
 
     pub fn clear_screen(&mut self, color: Pixel) {
+        if let Some(framebuffer) = self.framebuffer() {
+            framebuffer.fill(color.into());
+            return;
+        }
+
         for x in 0..self.resolution.0 {
             for y in 0..self.resolution.1 {
                 self.set_pixel(x, y, color.into()).unwrap();
@@ -108,33 +215,71 @@ impl BgaController {
     }
 }
 
-#[repr(transparent)]
-struct Bank {
-    bank: [Volatile<u32>; 16384]
+/// Returns the address in BAR0 of the BGA PCI device, which holds the linear framebuffer.
+fn find_lfb_address() -> Option<u64> {
+    let device = BGA_PCI_IDS
+        .iter()
+        .find_map(|&(vendor, device)| pci::find_by_id(vendor, device))?;
+
+    match device.bars[0]? {
+        Bar::Memory { address, .. } => Some(address),
+        Bar::Io { .. } => None,
+    }
 }
 
 #[repr(C, align(4))]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Pixel {
     b: u8,
     g: u8,
     r: u8,
+    /// Ignored by the hardware, used as alpha channel by [graphics](crate::graphics)
     pad: u8,
 }
 
 impl Pixel {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
+    /// Creates an opaque pixel.
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self::with_alpha(r, g, b, u8::MAX)
+    }
+
+    /// Creates a pixel with the given alpha, 0 being fully transparent and 255 fully opaque.
+    pub const fn with_alpha(r: u8, g: u8, b: u8, alpha: u8) -> Self {
         Self {
-            r,g,b, pad: 0
+            r,g,b, pad: alpha
         }
     }
 
     pub fn from_u32(input: u32) -> Self {
-        Self { 
-            r: input as u8,
+        Self {
+            b: input as u8,
             g: (input >> 8) as u8,
-            b: (input >> 16) as u8,
-            pad: 0,
+            r: (input >> 16) as u8,
+            pad: (input >> 24) as u8,
+        }
+    }
+
+    pub fn alpha(&self) -> u8 {
+        self.pad
+    }
+
+    /// Composites this pixel over `background` according to this pixel's alpha.
+    pub fn blend_over(self, background: Pixel) -> Pixel {
+        match self.pad {
+            0 => background,
+            u8::MAX => self,
+            alpha => {
+                let alpha = alpha as u16;
+                let mix = |top: u8, bottom: u8| {
+                    ((top as u16 * alpha + bottom as u16 * (255 - alpha) + 127) / 255) as u8
+                };
+                Pixel {
+                    r: mix(self.r, background.r),
+                    g: mix(self.g, background.g),
+                    b: mix(self.b, background.b),
+                    pad: (alpha + background.pad as u16 * (255 - alpha) / 255) as u8,
+                }
+            }
         }
     }
 }
@@ -145,7 +290,62 @@ impl Into<u32> for Pixel {
     }
 }
 
+/// The linear framebuffer of a [BgaController] as a [Canvas].
+pub struct BgaSurface<'a> {
+    pixels: &'a mut [Pixel],
+    width: usize,
+    height: usize,
+}
+
+impl Canvas for BgaSurface<'_> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixels(&self) -> &[Pixel] {
+        self.pixels
+    }
+
+    fn pixels_mut(&mut self) -> &mut [Pixel] {
+        self.pixels
+    }
+}
+
 #[derive(Debug)]
 pub enum VbaError {
     PixelOutOfBound,
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn linear_framebuffer_clear_and_set_pixel() {
+    let mut controller = BgaController::init();
+    controller.set_res(1024, 768, 0x20);
+
+    controller.clear_screen(Pixel::new(0, 0, 255));
+    controller.set_pixel(1023, 767, Pixel::new(255, 0, 0).into()).unwrap();
+    assert!(controller.set_pixel(1024, 0, 0).is_err());
+
+    let framebuffer = controller.framebuffer().expect("no linear framebuffer");
+    assert_eq!(framebuffer.len(), 1024 * 768);
+    assert_eq!(framebuffer[0], Pixel::new(0, 0, 255).into());
+    assert_eq!(framebuffer[1024 * 768 - 1], Pixel::new(255, 0, 0).into());
+}
+
+#[test_case]
+fn surface_draws_to_framebuffer() {
+    let mut controller = BgaController::init();
+    controller.set_res(640, 480, 0x20);
+
+    let mut surface = controller.surface().expect("no linear framebuffer");
+    surface.fill(Pixel::new(0, 0, 0));
+    surface.fill_rect(10, 10, 20, 20, Pixel::new(0, 255, 0));
+    assert_eq!(surface.get_pixel(15, 15), Some(Pixel::new(0, 255, 0)));
+
+    let framebuffer = controller.framebuffer().unwrap();
+    assert_eq!(framebuffer[15 * 640 + 15], Pixel::new(0, 255, 0).into());
+    assert_eq!(framebuffer[0], Pixel::new(0, 0, 0).into());
+}
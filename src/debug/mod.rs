@@ -0,0 +1,295 @@
+mod symbols;
+
+pub use symbols::{resolve, Symbol};
+
+use crate::memory;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, FS, GS, SS};
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::rflags;
+use x86_64::VirtAddr;
+
+/// The most return addresses a [Backtrace] records
+const MAX_FRAMES: usize = 32;
+
+/// The registers of the code interrupted by the exception that is panicking, see
+/// [record_fault]
+static FAULT: Mutex<Option<Registers>> = Mutex::new(None);
+
+/// Records the registers of the code an exception interrupted, for the panic handlers to
+/// report instead of their own.
+pub fn record_fault(registers: Registers) {
+    interrupts::without_interrupts(|| *FAULT.lock() = Some(registers));
+}
+
+/// Returns the registers and backtrace a panic handler reports, those of the faulting code
+/// if an exception recorded them, else those of the caller.
+#[inline(always)]
+pub fn panic_state() -> (Registers, Backtrace) {
+    // the panic may come from code holding the lock
+    let fault = interrupts::without_interrupts(|| FAULT.try_lock().and_then(|mut f| f.take()));
+    match fault {
+        Some(registers) => (registers, Backtrace::from_fault(&registers)),
+        None => (Registers::capture(), Backtrace::capture()),
+    }
+}
+
+/// A snapshot of the CPU registers, taken by [Registers::capture].
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub cs: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub gs: u16,
+    pub ss: u16,
+}
+
+impl Registers {
+    /// Captures the registers at the call site. The general purpose registers are stored
+    /// first, so only the ones the compiler used to set up the call are clobbered.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut registers = Self::default();
+        unsafe {
+            asm!(
+                "mov [{0} + 0x00], rax",
+                "mov [{0} + 0x08], rbx",
+                "mov [{0} + 0x10], rcx",
+                "mov [{0} + 0x18], rdx",
+                "mov [{0} + 0x20], rsi",
+                "mov [{0} + 0x28], rdi",
+                "mov [{0} + 0x30], rbp",
+                "mov [{0} + 0x38], rsp",
+                "mov [{0} + 0x40], r8",
+                "mov [{0} + 0x48], r9",
+                "mov [{0} + 0x50], r10",
+                "mov [{0} + 0x58], r11",
+                "mov [{0} + 0x60], r12",
+                "mov [{0} + 0x68], r13",
+                "mov [{0} + 0x70], r14",
+                "mov [{0} + 0x78], r15",
+                "lea rax, [rip]",
+                "mov [{0} + 0x80], rax",
+                in(reg) &mut registers as *mut Registers,
+                out("rax") _,
+                options(nostack, preserves_flags),
+            );
+        }
+
+        let (level_4_table, cr3_flags) = Cr3::read_raw();
+        registers.rflags = rflags::read_raw();
+        registers.cr0 = Cr0::read_raw();
+        registers.cr2 = Cr2::read().as_u64();
+        registers.cr3 = level_4_table.start_address().as_u64() | u64::from(cr3_flags);
+        registers.cr4 = Cr4::read_raw();
+        registers.cs = CS::get_reg().0;
+        registers.ds = DS::get_reg().0;
+        registers.es = ES::get_reg().0;
+        registers.fs = FS::get_reg().0;
+        registers.gs = GS::get_reg().0;
+        registers.ss = SS::get_reg().0;
+        registers
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = [
+            [("RAX", self.rax), ("RBX", self.rbx), ("RCX", self.rcx)],
+            [("RDX", self.rdx), ("RSI", self.rsi), ("RDI", self.rdi)],
+            [("RBP", self.rbp), ("RSP", self.rsp), ("R8 ", self.r8)],
+            [("R9 ", self.r9), ("R10", self.r10), ("R11", self.r11)],
+            [("R12", self.r12), ("R13", self.r13), ("R14", self.r14)],
+            [("R15", self.r15), ("RIP", self.rip), ("RFL", self.rflags)],
+            [("CR0", self.cr0), ("CR2", self.cr2), ("CR3", self.cr3)],
+        ];
+        for row in rows.iter() {
+            for (i, (name, value)) in row.iter().enumerate() {
+                let separator = if i + 1 == row.len() { "\n" } else { "  " };
+                write!(f, "{}={:016x}{}", name, value, separator)?;
+            }
+        }
+        writeln!(f, "CR4={:016x}", self.cr4)?;
+        write!(
+            f,
+            "CS={:04x} DS={:04x} ES={:04x} FS={:04x} GS={:04x} SS={:04x}",
+            self.cs, self.ds, self.es, self.fs, self.gs, self.ss
+        )
+    }
+}
+
+/// The return addresses of the calls leading to [Backtrace::capture], found by following
+/// the chain of saved frame pointers. The kernel is built with frame pointers forced on,
+/// see `.cargo/config.toml`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+    /// The first frame is the instruction an exception interrupted, not a return address
+    interrupted: bool,
+}
+
+impl Backtrace {
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let frame_pointer: u64;
+        unsafe {
+            asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack, preserves_flags))
+        };
+        unsafe { Self::from_frame_pointer(frame_pointer) }
+    }
+
+    /// Walks the frame pointer chain starting at `frame_pointer`, until it reaches an
+    /// unmapped or misaligned frame, or a frame that is not above the previous one.
+    ///
+    /// This function is unsafe because the caller must guarantee that `frame_pointer` is
+    /// either invalid or points to a saved frame pointer followed by a return address.
+    pub unsafe fn from_frame_pointer(mut frame_pointer: u64) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+            interrupted: false,
+        };
+
+        while backtrace.len < MAX_FRAMES && is_valid_frame(frame_pointer) {
+            let frame = frame_pointer as *const u64;
+            let (caller_frame_pointer, return_address) = (*frame, *frame.add(1));
+
+            // the frame of an interrupt handler is followed by the error code or the
+            // interrupted instruction pointer, an error code is never a mapped address
+            if is_mapped(return_address) {
+                backtrace.frames[backtrace.len] = return_address;
+                backtrace.len += 1;
+            }
+
+            if caller_frame_pointer <= frame_pointer {
+                break;
+            }
+            frame_pointer = caller_frame_pointer;
+        }
+        backtrace
+    }
+
+    /// Starts at the instruction an exception interrupted and follows the frame pointer of
+    /// the interrupted code to its callers.
+    pub fn from_fault(registers: &Registers) -> Self {
+        // the frame pointer may hold anything after a fault, but only mapped frames are read
+        let callers = unsafe { Self::from_frame_pointer(registers.rbp) };
+        let len = callers.len.min(MAX_FRAMES - 1);
+
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: len + 1,
+            interrupted: true,
+        };
+        backtrace.frames[0] = registers.rip;
+        backtrace.frames[1..=len].copy_from_slice(&callers.frames[..len]);
+        backtrace
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Backtrace:")?;
+        for (i, &address) in self.frames().iter().enumerate() {
+            write!(f, "\n{:>4}: {:#018x}", i, address)?;
+            // a return address may be just past the end of the calling function
+            let adjustment = if i == 0 && self.interrupted { 0 } else { 1 };
+            if let Some(symbol) = resolve(address - adjustment) {
+                let offset = symbol.offset + adjustment;
+                write!(f, " {}", Symbol { offset, ..symbol })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_frame(frame_pointer: u64) -> bool {
+    frame_pointer != 0
+        && frame_pointer % 8 == 0
+        && is_mapped(frame_pointer)
+        && is_mapped(frame_pointer + 8)
+}
+
+/// Checks if `address` is mapped without taking the mapper lock, which the panicking code
+/// may hold. Nothing is mapped before the memory subsystem is initialized.
+fn is_mapped(address: u64) -> bool {
+    let physical_memory_offset = memory::physical_memory_offset();
+    match VirtAddr::try_new(address) {
+        Ok(address) if !physical_memory_offset.is_null() => unsafe {
+            memory::translate_addr(address, physical_memory_offset).is_some()
+        },
+        _ => false,
+    }
+}
+
+#[test_case]
+fn registers_match_the_cpu_state() {
+    let registers = Registers::capture();
+    let (level_4_table, _) = Cr3::read();
+
+    assert_eq!(
+        registers.cr3 & !0xFFF,
+        level_4_table.start_address().as_u64()
+    );
+    assert_eq!(registers.cs, CS::get_reg().0);
+    assert!(registers.rsp <= registers.rbp);
+    assert!(is_mapped(registers.rip));
+}
+
+#[test_case]
+fn backtrace_follows_the_callers() {
+    #[inline(never)]
+    fn nested(depth: usize) -> Backtrace {
+        match depth {
+            0 => Backtrace::capture(),
+            _ => nested(depth - 1),
+        }
+    }
+
+    let backtrace = nested(3);
+    // three recursive calls and the call from this test
+    assert!(backtrace.frames().len() >= 4);
+    let recursive = &backtrace.frames()[..3];
+    assert!(recursive.iter().all(|&address| address == recursive[0]));
+    assert!(backtrace.frames().iter().all(|&address| is_mapped(address)));
+}
+
+#[test_case]
+fn backtrace_starts_at_the_fault() {
+    let registers = Registers::capture();
+    let backtrace = Backtrace::from_fault(&registers);
+
+    assert_eq!(backtrace.frames()[0], registers.rip);
+    assert_eq!(&backtrace.frames()[1..], Backtrace::capture().frames());
+}
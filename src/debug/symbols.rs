@@ -0,0 +1,120 @@
+use core::fmt;
+use core::{ptr, slice, str};
+
+/// The size of the `.symbols` section, which `tools/symbol_table.rs` fills after linking.
+/// The tool fails the build if the table doesn't fit.
+const SYMBOL_TABLE_CAPACITY: usize = 1024 * 1024;
+const MAGIC: [u8; 4] = *b"VSYM";
+const HEADER_SIZE: usize = 12;
+const SYMBOL_SIZE: usize = 16;
+
+/// The symbol table of the kernel, see `tools/symbol_table.rs` for the layout. It starts
+/// with the magic only, so the section has contents in the ELF file the tool can overwrite.
+#[link_section = ".symbols"]
+#[used]
+static mut SYMBOL_TABLE: [u8; SYMBOL_TABLE_CAPACITY] = {
+    let mut table = [0; SYMBOL_TABLE_CAPACITY];
+    table[0] = MAGIC[0];
+    table[1] = MAGIC[1];
+    table[2] = MAGIC[2];
+    table[3] = MAGIC[3];
+    table
+};
+
+/// The function containing an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: u64,
+    /// The distance of the looked up address from the start of the function
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Returns the symbol table, or `None` if the kernel was not run through the symbol table
+/// tool.
+fn table() -> Option<&'static [u8]> {
+    // read through volatile pointers, the compiler only knows the empty table
+    let table = unsafe { ptr::addr_of!(SYMBOL_TABLE) as *const u8 };
+    let read_u32 = |offset: usize| unsafe { ptr::read_volatile(table.add(offset) as *const u32) };
+
+    let length = read_u32(4) as usize;
+    if read_u32(0) != u32::from_le_bytes(MAGIC) || length < HEADER_SIZE {
+        return None;
+    }
+    Some(unsafe { slice::from_raw_parts(table, length.min(SYMBOL_TABLE_CAPACITY)) })
+}
+
+fn read_u16(table: &[u8], offset: usize) -> Option<u16> {
+    let bytes = table.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(table: &[u8], offset: usize) -> Option<u32> {
+    let bytes = table.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(table: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from(read_u32(table, offset)?) | (u64::from(read_u32(table, offset + 4)?) << 32))
+}
+
+/// Returns the address, size and name of the symbol at `index`.
+fn symbol_at(table: &'static [u8], index: usize) -> Option<(u64, u64, &'static str)> {
+    let entry = HEADER_SIZE + index * SYMBOL_SIZE;
+    let address = read_u64(table, entry)?;
+    let size = u64::from(read_u32(table, entry + 8)?);
+    let name_offset = read_u32(table, entry + 12)? as usize;
+
+    let name_length = read_u16(table, name_offset)? as usize;
+    let name = table.get(name_offset + 2..name_offset + 2 + name_length)?;
+    Some((address, size, str::from_utf8(name).ok()?))
+}
+
+/// Finds the function containing `address`.
+pub fn resolve(address: u64) -> Option<Symbol> {
+    let table = table()?;
+    let count = read_u32(table, 8)? as usize;
+
+    // the symbols are sorted, find the last one starting at or before the address
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if symbol_at(table, middle)?.0 <= address {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    let (start, size, name) = symbol_at(table, low.checked_sub(1)?)?;
+    let offset = address - start;
+    // a size of 0 is unknown, assembly functions often don't have one
+    if size != 0 && offset >= size {
+        return None;
+    }
+    Some(Symbol {
+        name,
+        address: start,
+        offset,
+    })
+}
+
+#[test_case]
+fn functions_resolve_to_their_name() {
+    let address = functions_resolve_to_their_name as usize as u64;
+    let symbol = resolve(address + 1)
+        .expect("the symbol table is empty, the kernel was not linked through tools/linker.sh");
+
+    assert!(symbol
+        .name
+        .ends_with("debug::symbols::functions_resolve_to_their_name"));
+    assert_eq!(symbol.address, address);
+    assert_eq!(symbol.offset, 1);
+    assert_eq!(resolve(0), None);
+}
@@ -1,6 +1,14 @@
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
 static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+// NMIs and machine checks can arrive in the middle of anything, including a stack switch,
+// so they never run on the interrupted stack
+const NMI_STACK_SIZE: usize = 4096 * 2;
+static mut NMI_STACK: [u8; NMI_STACK_SIZE] = [0; NMI_STACK_SIZE];
+const MACHINE_CHECK_STACK_SIZE: usize = 4096 * 2;
+static mut MACHINE_CHECK_STACK: [u8; MACHINE_CHECK_STACK_SIZE] = [0; MACHINE_CHECK_STACK_SIZE];
 
 use crate::{print, println};
 use lazy_static::lazy_static;
@@ -22,6 +30,14 @@ lazy_static! {
             let stack_end = stack_start + DOUBLE_FAULT_STACK_SIZE;
             stack_end
         };
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = {
+            let stack_start = VirtAddr::from_ptr(unsafe { &NMI_STACK });
+            stack_start + NMI_STACK_SIZE
+        };
+        tss.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] = {
+            let stack_start = VirtAddr::from_ptr(unsafe { &MACHINE_CHECK_STACK });
+            stack_start + MACHINE_CHECK_STACK_SIZE
+        };
         tss
     };
 }
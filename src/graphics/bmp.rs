@@ -0,0 +1,351 @@
+use super::{Buffer, Canvas, Pixel};
+use core::convert::TryInto;
+
+/// The boot splash, 1024x768 at 24 bit
+pub static BOOT_SPLASH: &[u8] = include_bytes!("../../files/boot.bmp");
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BmpError {
+    InvalidSignature,
+    Truncated,
+    /// The info header is older than `BITMAPINFOHEADER`
+    UnsupportedHeader(u32),
+    UnsupportedFormat {
+        bits_per_pixel: u16,
+        compression: u32,
+    },
+    InvalidDimensions,
+}
+
+/// How the bits of a 32 bit pixel map to color channels.
+#[derive(Debug, Clone, Copy)]
+struct ChannelMasks {
+    red: u32,
+    green: u32,
+    blue: u32,
+}
+
+impl ChannelMasks {
+    const BGRX: ChannelMasks = ChannelMasks {
+        red: 0x00FF_0000,
+        green: 0x0000_FF00,
+        blue: 0x0000_00FF,
+    };
+
+    fn extract(mask: u32, value: u32) -> u8 {
+        if mask == 0 {
+            return 0;
+        }
+        let bits = mask.count_ones();
+        let channel = (value & mask) >> mask.trailing_zeros();
+        // scale to 8 bits, e.g. for 10 bit channels
+        if bits >= 8 {
+            (channel >> (bits - 8)) as u8
+        } else {
+            (channel * 255 / ((1 << bits) - 1)) as u8
+        }
+    }
+}
+
+/// A decoded view of an uncompressed BMP file with 1, 4, 8, 24 or 32 bits per pixel.
+///
+/// The pixel data is not copied, pixels are decoded from the file on access.
+pub struct Bmp<'a> {
+    width: usize,
+    height: usize,
+    bits_per_pixel: u16,
+    /// Whether the first row in the file is the top row, instead of the bottom one
+    top_down: bool,
+    stride: usize,
+    pixel_data: &'a [u8],
+    /// `BGRX` entries for images with up to 8 bits per pixel
+    palette: &'a [u8],
+    masks: ChannelMasks,
+}
+
+impl<'a> Bmp<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, BmpError> {
+        if !data.starts_with(b"BM") {
+            return Err(BmpError::InvalidSignature);
+        }
+
+        let u16_at = |offset: usize| -> Result<u16, BmpError> {
+            let bytes = data.get(offset..offset + 2).ok_or(BmpError::Truncated)?;
+            Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let u32_at = |offset: usize| -> Result<u32, BmpError> {
+            let bytes = data.get(offset..offset + 4).ok_or(BmpError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let pixel_offset = u32_at(10)? as usize;
+        let header_size = u32_at(FILE_HEADER_SIZE)?;
+        if (header_size as usize) < INFO_HEADER_SIZE {
+            return Err(BmpError::UnsupportedHeader(header_size));
+        }
+
+        let width = u32_at(FILE_HEADER_SIZE + 4)? as i32;
+        let height = u32_at(FILE_HEADER_SIZE + 8)? as i32;
+        let bits_per_pixel = u16_at(FILE_HEADER_SIZE + 14)?;
+        let compression = u32_at(FILE_HEADER_SIZE + 16)?;
+        let colors_used = u32_at(FILE_HEADER_SIZE + 32)? as usize;
+
+        if width <= 0 || height == 0 {
+            return Err(BmpError::InvalidDimensions);
+        }
+
+        let unsupported = BmpError::UnsupportedFormat {
+            bits_per_pixel,
+            compression,
+        };
+        let masks = match (bits_per_pixel, compression) {
+            (1 | 4 | 8 | 24 | 32, BI_RGB) => ChannelMasks::BGRX,
+            (32, BI_BITFIELDS) => {
+                // the masks follow a BITMAPINFOHEADER, later headers contain them
+                let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+                ChannelMasks {
+                    red: u32_at(offset)?,
+                    green: u32_at(offset + 4)?,
+                    blue: u32_at(offset + 8)?,
+                }
+            }
+            _ => return Err(unsupported),
+        };
+
+        let palette = if bits_per_pixel <= 8 {
+            let entries = match colors_used {
+                0 => 1 << bits_per_pixel,
+                n => n.min(1 << bits_per_pixel),
+            };
+            let start = FILE_HEADER_SIZE + header_size as usize;
+            data.get(start..start + entries * 4)
+                .ok_or(BmpError::Truncated)?
+        } else {
+            &[]
+        };
+
+        let (width, top_down) = (width as usize, height < 0);
+        let height = height.unsigned_abs() as usize;
+        let stride = width
+            .checked_mul(bits_per_pixel as usize)
+            .map(|bits| (bits + 31) / 32 * 4)
+            .ok_or(BmpError::InvalidDimensions)?;
+        let size = stride
+            .checked_mul(height)
+            .ok_or(BmpError::InvalidDimensions)?;
+        let pixel_data = data
+            .get(pixel_offset..)
+            .filter(|pixels| pixels.len() >= size)
+            .ok_or(BmpError::Truncated)?;
+
+        Ok(Self {
+            width,
+            height,
+            bits_per_pixel,
+            top_down,
+            stride,
+            pixel_data,
+            palette,
+            masks,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixel at `(x, y)`, with `(0, 0)` being the top left corner.
+    ///
+    /// Panics if the coordinates are outside the image.
+    pub fn pixel(&self, x: usize, y: usize) -> Pixel {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is outside the image",
+            x,
+            y
+        );
+
+        let row = if self.top_down {
+            y
+        } else {
+            self.height - 1 - y
+        };
+        let line = &self.pixel_data[row * self.stride..(row + 1) * self.stride];
+
+        match self.bits_per_pixel {
+            24 => Pixel::new(line[x * 3 + 2], line[x * 3 + 1], line[x * 3]),
+            32 => {
+                let value = u32::from_le_bytes(line[x * 4..x * 4 + 4].try_into().unwrap());
+                Pixel::new(
+                    ChannelMasks::extract(self.masks.red, value),
+                    ChannelMasks::extract(self.masks.green, value),
+                    ChannelMasks::extract(self.masks.blue, value),
+                )
+            }
+            bits => {
+                let bits = bits as usize;
+                let per_byte = 8 / bits;
+                let shift = 8 - bits * (x % per_byte + 1);
+                let index = (line[x / per_byte] >> shift) as usize & ((1 << bits) - 1);
+                match self.palette.get(index * 4..index * 4 + 3) {
+                    Some(entry) => Pixel::new(entry[2], entry[1], entry[0]),
+                    // indexes past the palette are invalid, show them black
+                    None => Pixel::new(0, 0, 0),
+                }
+            }
+        }
+    }
+
+    /// Decodes the whole image into a [Buffer].
+    pub fn to_buffer(&self) -> Buffer {
+        let mut buffer = Buffer::new(self.width, self.height, Pixel::new(0, 0, 0));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                buffer.pixels_mut()[y * self.width + x] = self.pixel(x, y);
+            }
+        }
+        buffer
+    }
+
+    /// Draws the image scaled to `width` x `height` with its top left corner at `(x, y)`,
+    /// using nearest neighbour sampling.
+    pub fn draw_scaled(
+        &self,
+        canvas: &mut dyn Canvas,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+    ) {
+        for dy in 0..height {
+            let target_y = y + dy as isize;
+            if target_y < 0 || target_y as usize >= canvas.height() {
+                continue;
+            }
+            let source_y = dy * self.height / height;
+
+            for dx in 0..width {
+                let target_x = x + dx as isize;
+                if canvas.contains(target_x, target_y) {
+                    canvas.set_pixel(
+                        target_x,
+                        target_y,
+                        self.pixel(dx * self.width / width, source_y),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Draws the image as large as fits on `canvas` while keeping its aspect ratio, centered.
+    pub fn draw_centered(&self, canvas: &mut dyn Canvas) {
+        let (canvas_width, canvas_height) = (canvas.width(), canvas.height());
+        // compare width / height ratios without dividing
+        let (width, height) = if canvas_width * self.height <= canvas_height * self.width {
+            (canvas_width, self.height * canvas_width / self.width)
+        } else {
+            (self.width * canvas_height / self.height, canvas_height)
+        };
+
+        let x = (canvas_width - width) / 2;
+        let y = (canvas_height - height) / 2;
+        self.draw_scaled(canvas, x as isize, y as isize, width, height);
+    }
+}
+
+/// Builds a BMP file with a `BITMAPINFOHEADER` from the given header fields and data.
+#[cfg(test)]
+fn build_bmp(
+    width: i32,
+    height: i32,
+    bits_per_pixel: u16,
+    palette: &[u8],
+    pixels: &[u8],
+) -> alloc::vec::Vec<u8> {
+    let pixel_offset = (FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette.len()) as u32;
+    let mut data = alloc::vec::Vec::new();
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&(pixel_offset + pixels.len() as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&pixel_offset.to_le_bytes());
+    data.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&bits_per_pixel.to_le_bytes());
+    data.extend_from_slice(&[0; 20]);
+    data.extend_from_slice(palette);
+    data.extend_from_slice(pixels);
+    data
+}
+
+#[test_case]
+fn bottom_up_24_bit() {
+    // rows are padded to 4 bytes, the first row in the file is the bottom one
+    let pixels = [
+        0, 0, 255, 0, 255, 0, 0, 0, //
+        255, 0, 0, 255, 255, 255, 0, 0,
+    ];
+    let data = build_bmp(2, 2, 24, &[], &pixels);
+    let bmp = Bmp::parse(&data).unwrap();
+
+    assert_eq!((bmp.width(), bmp.height()), (2, 2));
+    assert_eq!(bmp.pixel(0, 0), Pixel::new(0, 0, 255));
+    assert_eq!(bmp.pixel(1, 0), Pixel::new(255, 255, 255));
+    assert_eq!(bmp.pixel(0, 1), Pixel::new(255, 0, 0));
+    assert_eq!(bmp.pixel(1, 1), Pixel::new(0, 255, 0));
+}
+
+#[test_case]
+fn top_down_8_bit_palette() {
+    let palette = [0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0];
+    let pixels = [0, 1, 2, 0, 2, 1, 0, 0];
+    let data = build_bmp(3, -2, 8, &palette, &pixels);
+    let bmp = Bmp::parse(&data).unwrap();
+
+    assert_eq!(bmp.pixel(0, 0), Pixel::new(0, 0, 0));
+    assert_eq!(bmp.pixel(1, 0), Pixel::new(0, 0, 255));
+    assert_eq!(bmp.pixel(2, 0), Pixel::new(0, 255, 0));
+    assert_eq!(bmp.pixel(0, 1), Pixel::new(0, 255, 0));
+}
+
+#[test_case]
+fn invalid_files_are_rejected() {
+    assert_eq!(
+        Bmp::parse(b"GIF89a").err(),
+        Some(BmpError::InvalidSignature)
+    );
+    let data = build_bmp(2, 2, 24, &[], &[0; 8]);
+    assert_eq!(Bmp::parse(&data).err(), Some(BmpError::Truncated));
+    let data = build_bmp(1, 1, 16, &[], &[0; 4]);
+    assert!(matches!(
+        Bmp::parse(&data),
+        Err(BmpError::UnsupportedFormat {
+            bits_per_pixel: 16,
+            ..
+        })
+    ));
+}
+
+#[test_case]
+fn boot_splash_scales_to_fit() {
+    let bmp = Bmp::parse(BOOT_SPLASH).unwrap();
+    assert_eq!((bmp.width(), bmp.height()), (1024, 768));
+
+    // a wide canvas gets black bars on the sides
+    let background = Pixel::with_alpha(1, 2, 3, 0);
+    let mut buffer = Buffer::new(64, 24, background);
+    bmp.draw_centered(&mut buffer);
+    assert_eq!(buffer.get_pixel(0, 12), Some(background));
+    assert_ne!(buffer.get_pixel(32, 12), Some(background));
+    assert_ne!(buffer.get_pixel(16, 0), Some(background));
+}
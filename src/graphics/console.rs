@@ -0,0 +1,430 @@
+use super::cursor::Cursor;
+use super::font::{PsfFont, DEFAULT_FONT};
+use super::{Canvas, Pixel};
+use crate::bga::{BgaController, BgaSurface};
+use crate::vga::Color;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+const TAB_WIDTH: usize = 4;
+/// The number of pixel rows at the bottom of a cell the cursor covers
+const CURSOR_HEIGHT: usize = 2;
+
+/// The standard VGA palette, indexed by [Color]
+const PALETTE: [Pixel; 16] = [
+    Pixel::new(0x00, 0x00, 0x00),
+    Pixel::new(0x00, 0x00, 0xAA),
+    Pixel::new(0x00, 0xAA, 0x00),
+    Pixel::new(0x00, 0xAA, 0xAA),
+    Pixel::new(0xAA, 0x00, 0x00),
+    Pixel::new(0xAA, 0x00, 0xAA),
+    Pixel::new(0xAA, 0x55, 0x00),
+    Pixel::new(0xAA, 0xAA, 0xAA),
+    Pixel::new(0x55, 0x55, 0x55),
+    Pixel::new(0x55, 0x55, 0xFF),
+    Pixel::new(0x55, 0xFF, 0x55),
+    Pixel::new(0x55, 0xFF, 0xFF),
+    Pixel::new(0xFF, 0x55, 0x55),
+    Pixel::new(0xFF, 0x55, 0xFF),
+    Pixel::new(0xFF, 0xFF, 0x55),
+    Pixel::new(0xFF, 0xFF, 0xFF),
+];
+
+/// The console [print](crate::print) writes to once [activate] has been called.
+static CONSOLE: Mutex<Option<FramebufferConsole<BgaSurface<'static>>>> = Mutex::new(None);
+
+pub const fn color_to_pixel(color: Color) -> Pixel {
+    PALETTE[color as usize]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    foreground: Color,
+    background: Color,
+}
+
+/// A text console drawing glyphs of a [PsfFont] onto a [Canvas].
+///
+/// The text is kept in a grid of cells besides the canvas, so scrolling redraws the
+/// screen from the grid instead of reading back from the framebuffer, which is slow
+/// when it is mapped write-combining.
+pub struct FramebufferConsole<C: Canvas> {
+    canvas: C,
+    font: &'static PsfFont,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    column: usize,
+    row: usize,
+    foreground: Color,
+    background: Color,
+    cursor_visible: bool,
+    /// The mouse pointer, hidden while the console draws
+    pointer: Option<Cursor>,
+}
+
+impl<C: Canvas> FramebufferConsole<C> {
+    pub fn new(canvas: C) -> Self {
+        Self::with_font(canvas, &DEFAULT_FONT)
+    }
+
+    pub fn with_font(canvas: C, font: &'static PsfFont) -> Self {
+        let columns = canvas.width() / font.width();
+        let rows = canvas.height() / font.height();
+        let blank = Cell {
+            c: ' ',
+            foreground: Color::White,
+            background: Color::Black,
+        };
+
+        let mut console = Self {
+            canvas,
+            font,
+            columns,
+            rows,
+            cells: vec![blank; columns * rows],
+            column: 0,
+            row: 0,
+            foreground: blank.foreground,
+            background: blank.background,
+            cursor_visible: true,
+            pointer: None,
+        };
+        console.clear();
+        console
+    }
+
+    /// Returns the size of the console in characters as `(columns, rows)`.
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// Returns the cursor position as `(column, row)`.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// Returns the canvas the console draws on. Whatever is drawn directly is
+    /// overwritten once the console scrolls, and the mouse pointer should be hidden
+    /// while drawing.
+    pub fn canvas_mut(&mut self) -> &mut C {
+        &mut self.canvas
+    }
+
+    /// Sets the mouse pointer drawn on top of the text, or removes it.
+    pub fn set_pointer(&mut self, pointer: Option<Cursor>) {
+        self.hide_pointer();
+        self.pointer = pointer;
+        self.show_pointer();
+    }
+
+    pub fn pointer(&self) -> Option<&Cursor> {
+        self.pointer.as_ref()
+    }
+
+    /// Moves the mouse pointer by the given amount, keeping it on the screen.
+    pub fn move_pointer_by(&mut self, dx: isize, dy: isize) {
+        if let Some(pointer) = &mut self.pointer {
+            pointer.move_by(&mut self.canvas, dx, dy);
+        }
+    }
+
+    pub fn hide_pointer(&mut self) {
+        if let Some(pointer) = &mut self.pointer {
+            pointer.hide(&mut self.canvas);
+        }
+    }
+
+    pub fn show_pointer(&mut self) {
+        if let Some(pointer) = &mut self.pointer {
+            pointer.show(&mut self.canvas);
+        }
+    }
+
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.hide_pointer();
+        self.cursor_visible = visible;
+        self.draw_cell(self.column, self.row);
+        self.show_pointer();
+    }
+
+    /// Clears the screen with the current background color and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        self.hide_pointer();
+        let blank = self.blank();
+        self.cells.fill(blank);
+        self.canvas.fill(color_to_pixel(self.background));
+        self.column = 0;
+        self.row = 0;
+        self.draw_cell(0, 0);
+        self.show_pointer();
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        self.hide_pointer();
+        self.put_char(c);
+        self.show_pointer();
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        self.hide_pointer();
+        for c in s.chars() {
+            self.put_char(c);
+        }
+        self.show_pointer();
+    }
+
+    /// Handles a character and moves the text cursor, the mouse pointer must be hidden.
+    fn put_char(&mut self, c: char) {
+        if self.columns == 0 || self.rows == 0 {
+            return;
+        }
+
+        let (column, row) = (self.column, self.row);
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next.min(self.columns) {
+                    self.put(' ');
+                }
+            }
+            // backspace
+            '\x08' => {
+                if self.column > 0 {
+                    self.column -= 1;
+                    let blank = self.blank();
+                    self.cells[self.row * self.columns + self.column] = blank;
+                }
+            }
+            c => self.put(c),
+        }
+
+        // erase the cursor from its old cell
+        if (column, row) != (self.column, self.row) && row < self.rows {
+            self.draw_cell(column, row);
+        }
+        self.draw_cell(self.column, self.row);
+    }
+
+    /// Writes a character at the cursor and advances it, wrapping at the end of the line.
+    fn put(&mut self, c: char) {
+        if self.column >= self.columns {
+            self.new_line();
+        }
+
+        self.cells[self.row * self.columns + self.column] = Cell {
+            c,
+            foreground: self.foreground,
+            background: self.background,
+        };
+        self.draw_cell(self.column, self.row);
+        self.column += 1;
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves all lines up by one and clears the last one.
+    fn scroll(&mut self) {
+        let blank = self.blank();
+        self.cells.copy_within(self.columns.., 0);
+        let last_line = (self.rows - 1) * self.columns;
+        self.cells[last_line..].fill(blank);
+        self.redraw();
+    }
+
+    fn redraw(&mut self) {
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                self.draw_cell(column, row);
+            }
+        }
+    }
+
+    fn draw_cell(&mut self, column: usize, row: usize) {
+        if column >= self.columns || row >= self.rows {
+            return;
+        }
+
+        let cell = self.cells[row * self.columns + column];
+        let glyph = self.font.glyph(cell.c);
+        let foreground = color_to_pixel(cell.foreground);
+        let background = color_to_pixel(cell.background);
+        let has_cursor = self.cursor_visible && (column, row) == (self.column, self.row);
+
+        let (width, height) = (self.font.width(), self.font.height());
+        let canvas_width = self.canvas.width();
+        let pixels = self.canvas.pixels_mut();
+
+        for y in 0..height {
+            let under_cursor = has_cursor && y + CURSOR_HEIGHT >= height;
+            let line = (row * height + y) * canvas_width + column * width;
+
+            for x in 0..width {
+                let set = under_cursor || self.font.is_set(glyph, x, y);
+                pixels[line + x] = if set { foreground } else { background };
+            }
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell {
+            c: ' ',
+            foreground: self.foreground,
+            background: self.background,
+        }
+    }
+}
+
+impl<C: Canvas> fmt::Write for FramebufferConsole<C> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Routes [print](crate::print) and [println](crate::println) to a console on the linear
+/// framebuffer of `controller`, which must be in a 32 bit graphics mode.
+///
+/// Returns `false` if the controller has no usable linear framebuffer, in which case
+/// output keeps going to the VGA text buffer.
+pub fn activate(controller: BgaController) -> bool {
+    let surface = match controller.into_surface() {
+        Some(surface) => surface,
+        None => return false,
+    };
+
+    let console = FramebufferConsole::new(surface);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        *CONSOLE.lock() = Some(console);
+    });
+    true
+}
+
+/// Routes [print](crate::print) back to the VGA text buffer.
+pub fn deactivate() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        CONSOLE.lock().take();
+    });
+}
+
+pub fn is_active() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+/// Runs `f` with the active console, or returns `None` if there is none.
+///
+/// Interrupts must be disabled while calling this, like the VGA [WRITER](crate::vga::WRITER).
+pub fn with_console<R>(
+    f: impl FnOnce(&mut FramebufferConsole<BgaSurface<'static>>) -> R,
+) -> Option<R> {
+    CONSOLE.lock().as_mut().map(f)
+}
+
+/// Runs `f` with the active console from the panic handler, which can't wait for the code
+/// it interrupted to release the console. Returns `None` if there is no console.
+///
+/// This function is unsafe because it takes the console from its holder, which must never
+/// run again.
+pub unsafe fn with_console_on_panic<R>(
+    f: impl FnOnce(&mut FramebufferConsole<BgaSurface<'static>>) -> R,
+) -> Option<R> {
+    if CONSOLE.is_locked() {
+        CONSOLE.force_unlock();
+    }
+    CONSOLE.lock().as_mut().map(f)
+}
+
+#[cfg(test)]
+use super::Buffer;
+
+#[test_case]
+fn text_is_rendered_in_cells() {
+    let mut console = FramebufferConsole::new(Buffer::new(80, 32, color_to_pixel(Color::Blue)));
+    assert_eq!(console.size(), (10, 2));
+    // the canvas is cleared to the background
+    assert_eq!(
+        console.canvas.get_pixel(79, 31),
+        Some(color_to_pixel(Color::Black))
+    );
+
+    console.set_color(Color::Yellow, Color::Red);
+    console.write_string("\u{2588}a");
+    assert_eq!(console.cursor(), (2, 0));
+
+    // the full block covers the whole cell, the space after the cursor is background
+    assert_eq!(
+        console.canvas.get_pixel(0, 0),
+        Some(color_to_pixel(Color::Yellow))
+    );
+    assert_eq!(
+        console.canvas.get_pixel(7, 15),
+        Some(color_to_pixel(Color::Yellow))
+    );
+    assert_eq!(
+        console.canvas.get_pixel(16, 0),
+        Some(color_to_pixel(Color::Black))
+    );
+    // the cursor is drawn in the last rows of its cell, in the color of the cell
+    assert_eq!(
+        console.canvas.get_pixel(16, 15),
+        Some(color_to_pixel(Color::White))
+    );
+
+    console.write_char('\x08');
+    assert_eq!(console.cursor(), (1, 0));
+    assert_eq!(console.cells[1].c, ' ');
+}
+
+#[test_case]
+fn text_is_drawn_under_the_pointer() {
+    let mut console = FramebufferConsole::new(Buffer::new(32, 32, color_to_pixel(Color::Black)));
+    console.set_cursor_visible(false);
+    console.set_pointer(Some(Cursor::arrow()));
+    console.move_pointer_by(1, 0);
+    assert_eq!(console.canvas.get_pixel(1, 0), Some(Pixel::new(0, 0, 0)));
+    assert_eq!(console.canvas.get_pixel(2, 1), Some(Pixel::new(0, 0, 0)));
+
+    console.write_char('\u{2588}');
+    // the pointer is still drawn on top, and the block is under it
+    assert_eq!(console.canvas.get_pixel(2, 1), Some(Pixel::new(0, 0, 0)));
+    console.set_pointer(None);
+    assert_eq!(
+        console.canvas.get_pixel(2, 1),
+        Some(color_to_pixel(Color::White))
+    );
+}
+
+#[test_case]
+fn lines_wrap_and_scroll() {
+    let mut console = FramebufferConsole::new(Buffer::new(32, 32, color_to_pixel(Color::Black)));
+    console.set_cursor_visible(false);
+    console.write_string("abcdef");
+    assert_eq!(console.cursor(), (2, 1));
+    assert_eq!(console.cells[4].c, 'e');
+
+    console.write_string("\n\u{2588}");
+    assert_eq!(console.cursor(), (1, 1));
+    assert_eq!(console.cells[0].c, 'e');
+    assert_eq!(console.cells[4].c, '\u{2588}');
+    assert_eq!(
+        console.canvas.get_pixel(0, 16),
+        Some(color_to_pixel(Color::White))
+    );
+}
@@ -0,0 +1,215 @@
+use super::{Buffer, Canvas, Pixel, Rect};
+
+/// A 12x19 arrow, `X` is the outline, `.` the fill and spaces are transparent
+const ARROW: [&str; 19] = [
+    "X           ",
+    "XX          ",
+    "X.X         ",
+    "X..X        ",
+    "X...X       ",
+    "X....X      ",
+    "X.....X     ",
+    "X......X    ",
+    "X.......X   ",
+    "X........X  ",
+    "X.........X ",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X    ",
+    "X..XX..X    ",
+    "X.X  X..X   ",
+    "XX   X..X   ",
+    "      X..X  ",
+    "      XXX   ",
+];
+
+const TRANSPARENT: Pixel = Pixel::with_alpha(0, 0, 0, 0);
+
+/// A software mouse pointer drawn on top of a [Canvas].
+///
+/// The pixels under the sprite are saved when it is drawn and restored when it is hidden
+/// or moved, so nothing else has to be redrawn. Whoever draws on the canvas must hide the
+/// pointer first, otherwise moving it restores stale pixels.
+pub struct Cursor {
+    sprite: Buffer,
+    /// The pixel of the sprite that points at the position
+    hotspot: (isize, isize),
+    x: isize,
+    y: isize,
+    /// The canvas pixels under the sprite, while it is drawn
+    saved: Buffer,
+    /// The top left corner of the sprite, while it is drawn
+    drawn_at: Option<(isize, isize)>,
+}
+
+impl Cursor {
+    pub fn new(sprite: Buffer, hotspot: (usize, usize)) -> Self {
+        let saved = Buffer::new(sprite.width(), sprite.height(), TRANSPARENT);
+        Self {
+            sprite,
+            hotspot: (hotspot.0 as isize, hotspot.1 as isize),
+            x: 0,
+            y: 0,
+            saved,
+            drawn_at: None,
+        }
+    }
+
+    /// The default black and white arrow, pointing with its tip.
+    pub fn arrow() -> Self {
+        let mut sprite = Buffer::new(ARROW[0].len(), ARROW.len(), TRANSPARENT);
+        for (y, line) in ARROW.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                let pixel = match c {
+                    'X' => Pixel::new(0, 0, 0),
+                    '.' => Pixel::new(255, 255, 255),
+                    _ => continue,
+                };
+                sprite.set_pixel(x as isize, y as isize, pixel);
+            }
+        }
+        Self::new(sprite, (0, 0))
+    }
+
+    pub fn position(&self) -> (isize, isize) {
+        (self.x, self.y)
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.drawn_at.is_some()
+    }
+
+    fn top_left(&self) -> (isize, isize) {
+        (self.x - self.hotspot.0, self.y - self.hotspot.1)
+    }
+
+    /// Draws the pointer, saving the pixels under it.
+    pub fn show(&mut self, canvas: &mut dyn Canvas) {
+        if self.drawn_at.is_none() {
+            self.draw(canvas, (0, 0));
+        }
+    }
+
+    /// Removes the pointer, restoring the pixels under it.
+    pub fn hide(&mut self, canvas: &mut dyn Canvas) {
+        self.restore(canvas, (0, 0));
+    }
+
+    /// Moves the hotspot to `(x, y)`, clamped to the canvas.
+    ///
+    /// A visible pointer is redrawn in a single write to the canvas covering both the old and
+    /// the new position, so it doesn't flicker.
+    pub fn move_to(&mut self, canvas: &mut dyn Canvas, x: isize, y: isize) {
+        let x = x.clamp(0, canvas.width().saturating_sub(1) as isize);
+        let y = y.clamp(0, canvas.height().saturating_sub(1) as isize);
+
+        let old = match self.drawn_at {
+            Some(old) => old,
+            None => {
+                self.x = x;
+                self.y = y;
+                return;
+            }
+        };
+        if (x, y) == (self.x, self.y) {
+            return;
+        }
+
+        self.x = x;
+        self.y = y;
+        let new = self.top_left();
+        let (width, height) = (self.sprite.width() as isize, self.sprite.height() as isize);
+
+        let left = old.0.min(new.0);
+        let top = old.1.min(new.1);
+        let right = (old.0 + width).max(new.0 + width);
+        let bottom = (old.1 + height).max(new.1 + height);
+
+        // compose the move off-screen, starting from what's on the canvas
+        let mut scratch = Buffer::new(
+            (right - left) as usize,
+            (bottom - top) as usize,
+            TRANSPARENT,
+        );
+        let region = Rect::new(
+            left.max(0) as usize,
+            top.max(0) as usize,
+            scratch.width(),
+            scratch.height(),
+        );
+        scratch.blit_region(canvas, region, -left.min(0), -top.min(0));
+
+        self.restore(&mut scratch, (left, top));
+        self.draw(&mut scratch, (left, top));
+        canvas.blit(&scratch, left, top);
+    }
+
+    pub fn move_by(&mut self, canvas: &mut dyn Canvas, dx: isize, dy: isize) {
+        self.move_to(canvas, self.x + dx, self.y + dy);
+    }
+
+    /// Draws the sprite on `target`, whose top left corner is at `origin` on the canvas.
+    fn draw(&mut self, target: &mut dyn Canvas, origin: (isize, isize)) {
+        let (left, top) = self.top_left();
+        let (x, y) = (left - origin.0, top - origin.1);
+
+        for sy in 0..self.sprite.height() as isize {
+            for sx in 0..self.sprite.width() as isize {
+                let under = target.get_pixel(x + sx, y + sy).unwrap_or(TRANSPARENT);
+                self.saved.set_pixel(sx, sy, under);
+            }
+        }
+        target.blit_blended(&self.sprite, x, y);
+        self.drawn_at = Some((left, top));
+    }
+
+    /// Puts the saved pixels back on `target`, whose top left corner is at `origin` on the canvas.
+    fn restore(&mut self, target: &mut dyn Canvas, origin: (isize, isize)) {
+        if let Some((left, top)) = self.drawn_at.take() {
+            target.blit(&self.saved, left - origin.0, top - origin.1);
+        }
+    }
+}
+
+#[cfg(test)]
+const BACKGROUND: Pixel = Pixel::new(0, 0, 255);
+
+#[test_case]
+fn show_and_hide_restore_the_background() {
+    let mut canvas = Buffer::new(40, 40, BACKGROUND);
+    canvas.set_pixel(5, 5, Pixel::new(255, 0, 0));
+    let mut cursor = Cursor::arrow();
+
+    cursor.move_to(&mut canvas, 4, 4);
+    cursor.show(&mut canvas);
+    assert!(cursor.is_visible());
+    assert_eq!(canvas.get_pixel(4, 4), Some(Pixel::new(0, 0, 0)));
+    assert_eq!(canvas.get_pixel(5, 6), Some(Pixel::new(255, 255, 255)));
+    // transparent parts of the sprite leave the canvas alone
+    assert_eq!(canvas.get_pixel(10, 4), Some(BACKGROUND));
+
+    cursor.hide(&mut canvas);
+    assert!(!cursor.is_visible());
+    assert_eq!(canvas.get_pixel(4, 4), Some(BACKGROUND));
+    assert_eq!(canvas.get_pixel(5, 5), Some(Pixel::new(255, 0, 0)));
+}
+
+#[test_case]
+fn moving_is_clamped_and_keeps_the_background() {
+    let mut canvas = Buffer::new(40, 40, BACKGROUND);
+    let mut cursor = Cursor::arrow();
+    cursor.show(&mut canvas);
+
+    cursor.move_by(&mut canvas, 3, 2);
+    assert_eq!(cursor.position(), (3, 2));
+    assert_eq!(canvas.get_pixel(0, 0), Some(BACKGROUND));
+    assert_eq!(canvas.get_pixel(3, 2), Some(Pixel::new(0, 0, 0)));
+
+    cursor.move_by(&mut canvas, 100, -100);
+    assert_eq!(cursor.position(), (39, 0));
+    assert_eq!(canvas.get_pixel(3, 2), Some(BACKGROUND));
+    assert_eq!(canvas.get_pixel(39, 0), Some(Pixel::new(0, 0, 0)));
+
+    cursor.hide(&mut canvas);
+    assert!(canvas.pixels().iter().all(|&pixel| pixel == BACKGROUND));
+}
@@ -0,0 +1,218 @@
+use alloc::collections::BTreeMap;
+use core::convert::TryInto;
+use lazy_static::lazy_static;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_START_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+
+/// The glyph shown for characters the font has no glyph for, a small square in code page 437
+const REPLACEMENT_GLYPH: usize = 0xFE;
+
+lazy_static! {
+    /// An 8x16 font rendered from DejaVu Sans Mono Bold, with the glyphs in code page 437 order.
+    /// See `files/font.psf.LICENSE` for its license.
+    pub static ref DEFAULT_FONT: PsfFont = PsfFont::parse(include_bytes!("../../files/font.psf"))
+        .expect("The embedded font is not a valid PSF font");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    UnknownMagic,
+    Truncated,
+    InvalidHeader,
+}
+
+/// A bitmap font in the PC Screen Font format (version 1 or 2), as used by the Linux console.
+pub struct PsfFont {
+    data: &'static [u8],
+    glyph_offset: usize,
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+    /// Maps characters to glyph indexes, if the font has a unicode table
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl PsfFont {
+    pub fn parse(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else {
+            Err(FontError::UnknownMagic)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Self, FontError> {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+        let mut font = Self {
+            data,
+            glyph_offset: 4,
+            glyph_count,
+            bytes_per_glyph: height,
+            width: 8,
+            height,
+            unicode: None,
+        };
+        let table = font.check_glyphs()?;
+
+        if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) != 0 {
+            let mut unicode = BTreeMap::new();
+            let mut glyph = 0;
+            let mut in_sequence = false;
+
+            for entry in table.chunks_exact(2) {
+                if glyph >= glyph_count {
+                    break;
+                }
+                match u16::from_le_bytes([entry[0], entry[1]]) {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    PSF1_START_SEQUENCE => in_sequence = true,
+                    // sequences of combining characters are not rendered, only single characters
+                    _ if in_sequence => {}
+                    code => {
+                        if let Some(c) = char::from_u32(code as u32) {
+                            unicode.entry(c).or_insert(glyph);
+                        }
+                    }
+                }
+            }
+            font.unicode = Some(unicode);
+        }
+
+        Ok(font)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Self, FontError> {
+        let field = |index: usize| -> Result<u32, FontError> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let (header_size, flags, glyph_count) = (field(2)?, field(3)?, field(4)?);
+        let (bytes_per_glyph, height, width) = (field(5)?, field(6)?, field(7)?);
+
+        let row_bytes = (width as usize + 7) / 8;
+        if width == 0 || bytes_per_glyph as usize != row_bytes * height as usize {
+            return Err(FontError::InvalidHeader);
+        }
+
+        let mut font = Self {
+            data,
+            glyph_offset: header_size as usize,
+            glyph_count: glyph_count as usize,
+            bytes_per_glyph: bytes_per_glyph as usize,
+            width: width as usize,
+            height: height as usize,
+            unicode: None,
+        };
+        let table = font.check_glyphs()?;
+
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let mut unicode = BTreeMap::new();
+            let entries = table.split(|&byte| byte == PSF2_SEPARATOR);
+
+            for (glyph, entry) in entries.take(font.glyph_count).enumerate() {
+                // everything after the first start byte are sequences, which are not rendered
+                let singles = entry
+                    .split(|&byte| byte == PSF2_START_SEQUENCE)
+                    .next()
+                    .unwrap_or(&[]);
+                if let Ok(chars) = core::str::from_utf8(singles) {
+                    for c in chars.chars() {
+                        unicode.entry(c).or_insert(glyph);
+                    }
+                }
+            }
+            font.unicode = Some(unicode);
+        }
+
+        Ok(font)
+    }
+
+    /// Checks that all glyphs are present and returns the data following them.
+    fn check_glyphs(&self) -> Result<&'static [u8], FontError> {
+        let end = self.glyph_offset + self.glyph_count * self.bytes_per_glyph;
+        if self.height == 0 {
+            return Err(FontError::InvalidHeader);
+        }
+        self.data.get(end..).ok_or(FontError::Truncated)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the glyph index for `c`, or the replacement glyph if the font has none.
+    pub fn glyph_index(&self, c: char) -> usize {
+        let index = match &self.unicode {
+            Some(unicode) => unicode.get(&c).copied(),
+            // without a table, the glyphs are assumed to be in Latin-1 order
+            None => Some(c as usize).filter(|&index| index < 256),
+        };
+
+        index
+            .filter(|&index| index < self.glyph_count)
+            .unwrap_or(REPLACEMENT_GLYPH.min(self.glyph_count - 1))
+    }
+
+    /// Returns the bitmap of the glyph for `c`, `height` rows of `(width + 7) / 8` bytes each,
+    /// the most significant bit being the leftmost pixel.
+    pub fn glyph(&self, c: char) -> &'static [u8] {
+        let start = self.glyph_offset + self.glyph_index(c) * self.bytes_per_glyph;
+        &self.data[start..start + self.bytes_per_glyph]
+    }
+
+    /// Returns whether the pixel at `(x, y)` of a glyph bitmap returned by [glyph](Self::glyph) is set.
+    pub fn is_set(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let row_bytes = (self.width + 7) / 8;
+        glyph[y * row_bytes + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[test_case]
+fn default_font_is_parsed() {
+    assert_eq!(DEFAULT_FONT.width(), 8);
+    assert_eq!(DEFAULT_FONT.height(), 16);
+    assert_eq!(DEFAULT_FONT.glyph_index('A'), 0x41);
+    // code page 437 places the full block at 0xDB
+    assert_eq!(DEFAULT_FONT.glyph_index('█'), 0xDB);
+    assert_eq!(DEFAULT_FONT.glyph_index('\u{1F980}'), REPLACEMENT_GLYPH);
+
+    assert!(DEFAULT_FONT.glyph(' ').iter().all(|&row| row == 0));
+    assert!(DEFAULT_FONT.glyph('A').iter().any(|&row| row != 0));
+}
+
+#[test_case]
+fn invalid_fonts_are_rejected() {
+    assert_eq!(
+        PsfFont::parse(b"not a font").err(),
+        Some(FontError::UnknownMagic)
+    );
+    assert_eq!(
+        PsfFont::parse(&[0x36, 0x04, 0x00, 0x10, 0x00]).err(),
+        Some(FontError::Truncated)
+    );
+}
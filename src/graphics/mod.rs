@@ -0,0 +1,405 @@
+pub mod bmp;
+pub mod console;
+pub mod cursor;
+pub mod font;
+
+pub use crate::bga::Pixel;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+/// A rectangular area of pixels that can be drawn on.
+///
+/// Implementors only expose their pixels in row-major order, all drawing operations are
+/// provided on top of that. Coordinates are signed so shapes may be partially outside the
+/// canvas, everything is clipped to the canvas bounds.
+pub trait Canvas {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    /// The pixels in row-major order, `width * height` long
+    fn pixels(&self) -> &[Pixel];
+    fn pixels_mut(&mut self) -> &mut [Pixel];
+
+    fn contains(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height()
+    }
+
+    fn get_pixel(&self, x: isize, y: isize) -> Option<Pixel> {
+        if !self.contains(x, y) {
+            return None;
+        }
+        Some(self.pixels()[y as usize * self.width() + x as usize])
+    }
+
+    /// Sets a pixel, ignoring the alpha channel.
+    fn set_pixel(&mut self, x: isize, y: isize, pixel: Pixel) {
+        if self.contains(x, y) {
+            let index = y as usize * self.width() + x as usize;
+            self.pixels_mut()[index] = pixel;
+        }
+    }
+
+    /// Draws `pixel` over the existing pixel according to its alpha channel.
+    fn blend_pixel(&mut self, x: isize, y: isize, pixel: Pixel) {
+        if self.contains(x, y) {
+            let index = y as usize * self.width() + x as usize;
+            let pixels = self.pixels_mut();
+            pixels[index] = pixel.blend_over(pixels[index]);
+        }
+    }
+
+    fn fill(&mut self, pixel: Pixel) {
+        self.pixels_mut().fill(pixel);
+    }
+
+    /// Draws a line from `(x0, y0)` to `(x1, y1)`, both inclusive, with Bresenham's algorithm.
+    fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, pixel: Pixel) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            self.set_pixel(x, y, pixel);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let error2 = 2 * error;
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    fn draw_rect(&mut self, x: isize, y: isize, width: usize, height: usize, pixel: Pixel) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let (right, bottom) = (x + width as isize - 1, y + height as isize - 1);
+
+        self.draw_line(x, y, right, y, pixel);
+        self.draw_line(x, bottom, right, bottom, pixel);
+        self.draw_line(x, y, x, bottom, pixel);
+        self.draw_line(right, y, right, bottom, pixel);
+    }
+
+    fn fill_rect(&mut self, x: isize, y: isize, width: usize, height: usize, pixel: Pixel) {
+        let (x0, x1) = clip_span(x, width, self.width());
+        let (y0, y1) = clip_span(y, height, self.height());
+        let canvas_width = self.width();
+
+        for row in y0..y1 {
+            self.pixels_mut()[row * canvas_width + x0..row * canvas_width + x1].fill(pixel);
+        }
+    }
+
+    /// Draws the outline of a circle with the midpoint circle algorithm.
+    fn draw_circle(&mut self, center_x: isize, center_y: isize, radius: usize, pixel: Pixel) {
+        let mut x = radius as isize;
+        let mut y = 0;
+        let mut error = 1 - x;
+
+        while x >= y {
+            for &(px, py) in &[
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                self.set_pixel(center_x + px, center_y + py, pixel);
+            }
+
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    fn fill_circle(&mut self, center_x: isize, center_y: isize, radius: usize, pixel: Pixel) {
+        let radius = radius as isize;
+        for dy in -radius..=radius {
+            // widest dx with dx^2 + dy^2 <= r^2
+            let mut dx = 0;
+            while (dx + 1) * (dx + 1) + dy * dy <= radius * radius {
+                dx += 1;
+            }
+            self.fill_rect(
+                center_x - dx,
+                center_y + dy,
+                (2 * dx + 1) as usize,
+                1,
+                pixel,
+            );
+        }
+    }
+
+    /// Copies all of `source` to `(x, y)`, clipped to this canvas.
+    fn blit(&mut self, source: &dyn Canvas, x: isize, y: isize) {
+        let region = Rect::new(0, 0, source.width(), source.height());
+        self.blit_region(source, region, x, y);
+    }
+
+    /// Copies `region` of `source` to `(x, y)`, clipped to both canvases.
+    fn blit_region(&mut self, source: &dyn Canvas, region: Rect, x: isize, y: isize) {
+        copy_region(self, source, region, x, y, |_, source| source);
+    }
+
+    /// Like [blit](Canvas::blit), but blends every pixel according to its alpha channel.
+    fn blit_blended(&mut self, source: &dyn Canvas, x: isize, y: isize) {
+        let region = Rect::new(0, 0, source.width(), source.height());
+        copy_region(self, source, region, x, y, |destination, source| {
+            source.blend_over(destination)
+        });
+    }
+
+    /// Moves the content up by `rows` and fills the freed rows at the bottom with `fill`.
+    fn scroll_up(&mut self, rows: usize, fill: Pixel) {
+        let rows = rows.min(self.height());
+        let offset = rows * self.width();
+        let pixels = self.pixels_mut();
+        let len = pixels.len();
+
+        pixels.copy_within(offset.., 0);
+        pixels[len - offset..].fill(fill);
+    }
+
+    /// Moves the content down by `rows` and fills the freed rows at the top with `fill`.
+    fn scroll_down(&mut self, rows: usize, fill: Pixel) {
+        let rows = rows.min(self.height());
+        let offset = rows * self.width();
+        let pixels = self.pixels_mut();
+        let len = pixels.len();
+
+        pixels.copy_within(..len - offset, offset);
+        pixels[..offset].fill(fill);
+    }
+}
+
+/// A rectangle of a canvas, with its top left corner at `(x, y)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Combines `region` of `source` into `destination` at `(x, y)`, clipped to both canvases.
+fn copy_region<C: Canvas + ?Sized>(
+    destination: &mut C,
+    source: &dyn Canvas,
+    region: Rect,
+    x: isize,
+    y: isize,
+    combine: impl Fn(Pixel, Pixel) -> Pixel,
+) {
+    let Rect {
+        x: source_x,
+        y: source_y,
+        width,
+        height,
+    } = region;
+
+    // clip the region to the source first
+    let width = width.min(source.width().saturating_sub(source_x));
+    let height = height.min(source.height().saturating_sub(source_y));
+
+    // then to the destination, shifting the source along with it
+    let (x0, x1) = clip_span(x, width, destination.width());
+    let (y0, y1) = clip_span(y, height, destination.height());
+    if x0 >= x1 {
+        return;
+    }
+    let source_x = source_x + (x0 as isize - x) as usize;
+    let source_y = source_y + (y0 as isize - y) as usize;
+
+    let (source_width, destination_width) = (source.width(), destination.width());
+    for row in 0..y1 - y0 {
+        let source_start = (source_y + row) * source_width + source_x;
+        let source_row = &source.pixels()[source_start..source_start + (x1 - x0)];
+        let start = (y0 + row) * destination_width + x0;
+        let destination_row = &mut destination.pixels_mut()[start..start + (x1 - x0)];
+
+        for (destination, source) in destination_row.iter_mut().zip(source_row) {
+            *destination = combine(*destination, *source);
+        }
+    }
+}
+
+/// Clips the span `start..start + len` to `0..limit`, returning an empty span if nothing is left.
+fn clip_span(start: isize, len: usize, limit: usize) -> (usize, usize) {
+    let end = (start + len as isize).min(limit as isize);
+    let start = start.max(0);
+    if start >= end {
+        return (0, 0);
+    }
+    (start as usize, end as usize)
+}
+
+/// An off-screen canvas in heap memory, e.g. for sprites or double buffering.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Buffer {
+    pub fn new(width: usize, height: usize, fill: Pixel) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+}
+
+impl Canvas for Buffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    fn pixels_mut(&mut self) -> &mut [Pixel] {
+        &mut self.pixels
+    }
+}
+
+/// Reinterprets a slice of raw 32 bit pixels as [Pixel]s, which have the same layout.
+pub fn as_pixels_mut(raw: &mut [u32]) -> &mut [Pixel] {
+    debug_assert_eq!(mem::size_of::<Pixel>(), mem::size_of::<u32>());
+    unsafe { core::slice::from_raw_parts_mut(raw.as_mut_ptr() as *mut Pixel, raw.len()) }
+}
+
+#[cfg(test)]
+const BLACK: Pixel = Pixel::new(0, 0, 0);
+#[cfg(test)]
+const WHITE: Pixel = Pixel::new(255, 255, 255);
+
+#[test_case]
+fn line_endpoints_and_diagonal() {
+    let mut buffer = Buffer::new(10, 10, BLACK);
+    buffer.draw_line(0, 0, 9, 9, WHITE);
+    for i in 0..10 {
+        assert_eq!(buffer.get_pixel(i, i), Some(WHITE));
+    }
+    assert_eq!(buffer.get_pixel(1, 0), Some(BLACK));
+
+    // clipped, must not panic
+    buffer.draw_line(-5, 3, 20, 3, WHITE);
+    assert_eq!(buffer.get_pixel(0, 3), Some(WHITE));
+    assert_eq!(buffer.get_pixel(9, 3), Some(WHITE));
+}
+
+#[test_case]
+fn rectangles_are_clipped() {
+    let mut buffer = Buffer::new(8, 8, BLACK);
+    buffer.fill_rect(-2, -2, 4, 4, WHITE);
+    assert_eq!(buffer.get_pixel(1, 1), Some(WHITE));
+    assert_eq!(buffer.get_pixel(2, 2), Some(BLACK));
+
+    buffer.draw_rect(4, 4, 10, 10, WHITE);
+    assert_eq!(buffer.get_pixel(4, 7), Some(WHITE));
+    assert_eq!(buffer.get_pixel(5, 5), Some(BLACK));
+}
+
+#[test_case]
+fn circle_is_symmetric() {
+    let mut buffer = Buffer::new(21, 21, BLACK);
+    buffer.draw_circle(10, 10, 5, WHITE);
+    for &(x, y) in &[(15, 10), (5, 10), (10, 15), (10, 5)] {
+        assert_eq!(buffer.get_pixel(x, y), Some(WHITE));
+    }
+    assert_eq!(buffer.get_pixel(10, 10), Some(BLACK));
+
+    buffer.fill_circle(10, 10, 3, WHITE);
+    assert_eq!(buffer.get_pixel(10, 10), Some(WHITE));
+    assert_eq!(buffer.get_pixel(13, 13), Some(BLACK));
+}
+
+#[test_case]
+fn blit_with_clipping() {
+    let mut sprite = Buffer::new(4, 4, WHITE);
+    sprite.set_pixel(3, 3, Pixel::new(255, 0, 0));
+
+    let mut buffer = Buffer::new(6, 6, BLACK);
+    buffer.blit(&sprite, 4, 4);
+    assert_eq!(buffer.get_pixel(4, 4), Some(WHITE));
+    assert_eq!(buffer.get_pixel(5, 5), Some(WHITE));
+    assert_eq!(buffer.get_pixel(3, 3), Some(BLACK));
+
+    buffer.blit(&sprite, -3, -3);
+    assert_eq!(buffer.get_pixel(0, 0), Some(Pixel::new(255, 0, 0)));
+    assert_eq!(buffer.get_pixel(1, 1), Some(BLACK));
+}
+
+#[test_case]
+fn blit_region_copies_part_of_the_source() {
+    let mut sprite = Buffer::new(4, 4, WHITE);
+    sprite.set_pixel(2, 1, Pixel::new(255, 0, 0));
+
+    let mut buffer = Buffer::new(6, 6, BLACK);
+    buffer.blit_region(&sprite, Rect::new(2, 1, 4, 4), 1, 1);
+    assert_eq!(buffer.get_pixel(1, 1), Some(Pixel::new(255, 0, 0)));
+    // only the 2x3 part inside the source is copied
+    assert_eq!(buffer.get_pixel(2, 3), Some(WHITE));
+    assert_eq!(buffer.get_pixel(3, 1), Some(BLACK));
+    assert_eq!(buffer.get_pixel(1, 4), Some(BLACK));
+}
+
+#[test_case]
+fn alpha_blending() {
+    let mut buffer = Buffer::new(2, 1, BLACK);
+    buffer.blend_pixel(0, 0, Pixel::with_alpha(255, 255, 255, 0));
+    buffer.blend_pixel(1, 0, Pixel::with_alpha(255, 255, 255, 128));
+    assert_eq!(buffer.get_pixel(0, 0), Some(BLACK));
+    assert_eq!(buffer.get_pixel(1, 0), Some(Pixel::new(128, 128, 128)));
+}
+
+#[test_case]
+fn scrolling() {
+    let mut buffer = Buffer::new(3, 3, BLACK);
+    buffer.fill_rect(0, 2, 3, 1, WHITE);
+    buffer.scroll_up(1, BLACK);
+    assert_eq!(buffer.get_pixel(0, 1), Some(WHITE));
+    assert_eq!(buffer.get_pixel(0, 2), Some(BLACK));
+
+    buffer.scroll_down(2, BLACK);
+    assert_eq!(buffer.get_pixel(0, 1), Some(BLACK));
+    assert_eq!(buffer.get_pixel(2, 2), Some(BLACK));
+}
@@ -0,0 +1,170 @@
+use super::{Polarity, TriggerMode};
+use x86_64::VirtAddr;
+
+const REGISTER_SELECT: u64 = 0x00;
+const REGISTER_WINDOW: u64 = 0x10;
+
+const REGISTER_VERSION: u32 = 0x01;
+/// Every entry takes two registers, the low half first
+const REGISTER_REDIRECTION_TABLE: u32 = 0x10;
+
+const ENTRY_POLARITY_LOW: u64 = 1 << 13;
+const ENTRY_TRIGGER_LEVEL: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+const ENTRY_DESTINATION_SHIFT: u64 = 56;
+
+/// An I/O APIC, which delivers the global system interrupts from `gsi_base` on to the
+/// local APICs.
+#[derive(Debug)]
+pub struct IoApic {
+    id: u8,
+    base: VirtAddr,
+    gsi_base: u32,
+    entry_count: u32,
+}
+
+/// Where and how an interrupt input of an I/O APIC is delivered, always with fixed
+/// delivery mode to a single local APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    pub masked: bool,
+    /// The ID of the local APIC that receives the interrupt
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+    fn from_bits(bits: u64) -> Self {
+        Self {
+            vector: bits as u8,
+            polarity: if bits & ENTRY_POLARITY_LOW != 0 {
+                Polarity::ActiveLow
+            } else {
+                Polarity::ActiveHigh
+            },
+            trigger: if bits & ENTRY_TRIGGER_LEVEL != 0 {
+                TriggerMode::Level
+            } else {
+                TriggerMode::Edge
+            },
+            masked: bits & ENTRY_MASKED != 0,
+            destination: (bits >> ENTRY_DESTINATION_SHIFT) as u8,
+        }
+    }
+
+    fn to_bits(self) -> u64 {
+        let mut bits = self.vector as u64 | (self.destination as u64) << ENTRY_DESTINATION_SHIFT;
+        if self.polarity == Polarity::ActiveLow {
+            bits |= ENTRY_POLARITY_LOW;
+        }
+        if self.trigger == TriggerMode::Level {
+            bits |= ENTRY_TRIGGER_LEVEL;
+        }
+        if self.masked {
+            bits |= ENTRY_MASKED;
+        }
+        bits
+    }
+}
+
+impl IoApic {
+    /// Creates the driver of the I/O APIC whose registers are mapped at `base`.
+    ///
+    /// This function is unsafe because the caller must guarantee that `base` maps the
+    /// registers of an I/O APIC uncached, and that no other driver accesses them.
+    pub(super) unsafe fn new(id: u8, base: VirtAddr, gsi_base: u32) -> Self {
+        let mut io_apic = Self {
+            id,
+            base,
+            gsi_base,
+            entry_count: 0,
+        };
+        // the version register holds the index of the last entry
+        io_apic.entry_count = ((io_apic.read(REGISTER_VERSION) >> 16) & 0xFF) + 1;
+        io_apic
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// Returns the number of interrupt inputs.
+    pub fn entry_count(&self) -> u32 {
+        self.entry_count
+    }
+
+    /// Returns `true` if `gsi` is one of the inputs of this I/O APIC.
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entry_count).contains(&gsi)
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        unsafe {
+            ((self.base + REGISTER_SELECT).as_mut_ptr::<u32>()).write_volatile(register);
+            ((self.base + REGISTER_WINDOW).as_ptr::<u32>()).read_volatile()
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            ((self.base + REGISTER_SELECT).as_mut_ptr::<u32>()).write_volatile(register);
+            ((self.base + REGISTER_WINDOW).as_mut_ptr::<u32>()).write_volatile(value);
+        }
+    }
+
+    fn entry_register(&self, gsi: u32) -> u32 {
+        assert!(
+            self.handles(gsi),
+            "GSI {} is not handled by I/O APIC {}",
+            gsi,
+            self.id
+        );
+        REGISTER_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2
+    }
+
+    pub fn entry(&mut self, gsi: u32) -> RedirectionEntry {
+        let register = self.entry_register(gsi);
+        let low = self.read(register) as u64;
+        let high = self.read(register + 1) as u64;
+        RedirectionEntry::from_bits(high << 32 | low)
+    }
+
+    pub fn set_entry(&mut self, gsi: u32, entry: RedirectionEntry) {
+        let register = self.entry_register(gsi);
+        let bits = entry.to_bits();
+        // mask the input while the halves of the entry disagree
+        self.write(register, (bits as u32) | ENTRY_MASKED as u32);
+        self.write(register + 1, (bits >> 32) as u32);
+        self.write(register, bits as u32);
+    }
+
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let entry = self.entry(gsi);
+        self.set_entry(gsi, RedirectionEntry { masked, ..entry });
+    }
+
+    pub fn mask_all(&mut self) {
+        for gsi in self.gsi_base..self.gsi_base + self.entry_count {
+            self.set_masked(gsi, true);
+        }
+    }
+}
+
+#[test_case]
+fn redirection_entries_round_trip() {
+    let entry = RedirectionEntry {
+        vector: 0x21,
+        polarity: Polarity::ActiveLow,
+        trigger: TriggerMode::Level,
+        masked: true,
+        destination: 3,
+    };
+    assert_eq!(entry.to_bits(), 0x0300_0000_0001_A021);
+    assert_eq!(RedirectionEntry::from_bits(entry.to_bits()), entry);
+}
@@ -0,0 +1,242 @@
+use super::{SPURIOUS_VECTOR, TIMER_VECTOR};
+use crate::time::pit;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+
+const APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const TSC_DEADLINE_MSR: u32 = 0x6E0;
+
+const CPUID_FEATURES: u32 = 0x1;
+const CPUID_FEATURES_ECX_TSC_DEADLINE: u32 = 1 << 24;
+
+const REGISTER_ID: usize = 0x20;
+const REGISTER_TASK_PRIORITY: usize = 0x80;
+const REGISTER_END_OF_INTERRUPT: usize = 0xB0;
+const REGISTER_SPURIOUS_VECTOR: usize = 0xF0;
+const REGISTER_ERROR_STATUS: usize = 0x280;
+const REGISTER_INTERRUPT_COMMAND_LOW: usize = 0x300;
+const REGISTER_INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const REGISTER_LVT_TIMER: usize = 0x320;
+const REGISTER_LVT_LINT0: usize = 0x350;
+const REGISTER_LVT_ERROR: usize = 0x370;
+const REGISTER_TIMER_INITIAL_COUNT: usize = 0x380;
+const REGISTER_TIMER_CURRENT_COUNT: usize = 0x390;
+const REGISTER_TIMER_DIVIDE: usize = 0x3E0;
+
+const SPURIOUS_VECTOR_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_ONE_SHOT: u32 = 0b00 << 17;
+const LVT_TIMER_PERIODIC: u32 = 0b01 << 17;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+const INTERRUPT_COMMAND_NMI: u32 = 0b100 << 8;
+const INTERRUPT_COMMAND_PENDING: u32 = 1 << 12;
+const INTERRUPT_COMMAND_ASSERT: u32 = 1 << 14;
+/// Divides the bus clock by 16 before it drives the timer
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// The number of PIT cycles the timer calibration lasts, about 10 ms
+const CALIBRATION_CYCLES: u16 = 11_932;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// The virtual address the registers are mapped to, zero while the local APIC is not enabled
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// The rate the timer counts down at, in Hz
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static TIMER_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the physical address of the registers, as set by the firmware.
+pub(super) fn base_address() -> PhysAddr {
+    let value = unsafe { Msr::new(APIC_BASE_MSR).read() };
+    PhysAddr::new(value & APIC_BASE_ADDRESS_MASK)
+}
+
+/// Enables the local APIC, whose registers are mapped at `base`, and calibrates its timer.
+///
+/// This function is unsafe because the caller must guarantee that `base` maps the registers
+/// of the local APIC uncached.
+pub(super) unsafe fn enable(base: VirtAddr) {
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+
+    let mut msr = Msr::new(APIC_BASE_MSR);
+    let value = msr.read();
+    msr.write(value | APIC_BASE_ENABLE);
+
+    // accept every interrupt, and stop the PIC from reaching the CPU through virtual wire mode
+    write(REGISTER_TASK_PRIORITY, 0);
+    write(REGISTER_LVT_LINT0, LVT_MASKED);
+    write(REGISTER_LVT_ERROR, super::ERROR_VECTOR as u32);
+    write(REGISTER_LVT_TIMER, LVT_MASKED);
+    write(
+        REGISTER_SPURIOUS_VECTOR,
+        SPURIOUS_VECTOR_APIC_ENABLE | SPURIOUS_VECTOR as u32,
+    );
+    error_status();
+    end_of_interrupt();
+
+    TIMER_FREQUENCY.store(calibrate_timer(), Ordering::Relaxed);
+}
+
+fn read(register: usize) -> u32 {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "local APIC is not enabled");
+    unsafe { ((base as usize + register) as *const u32).read_volatile() }
+}
+
+fn write(register: usize, value: u32) {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "local APIC is not enabled");
+    unsafe { ((base as usize + register) as *mut u32).write_volatile(value) }
+}
+
+/// Returns the ID of the local APIC of this CPU.
+pub fn id() -> u8 {
+    (read(REGISTER_ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(REGISTER_END_OF_INTERRUPT, 0);
+}
+
+/// Returns the errors the local APIC detected since the last call.
+pub fn error_status() -> u32 {
+    // the register only latches new errors when written
+    write(REGISTER_ERROR_STATUS, 0);
+    read(REGISTER_ERROR_STATUS)
+}
+
+/// Sends an NMI to this CPU and waits until the local APIC has accepted it.
+pub fn send_nmi_to_self() {
+    write(REGISTER_INTERRUPT_COMMAND_HIGH, (id() as u32) << 24);
+    write(
+        REGISTER_INTERRUPT_COMMAND_LOW,
+        INTERRUPT_COMMAND_NMI | INTERRUPT_COMMAND_ASSERT,
+    );
+    while read(REGISTER_INTERRUPT_COMMAND_LOW) & INTERRUPT_COMMAND_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns `true` if the timer can fire at a time stamp counter value, see [set_tsc_deadline].
+pub fn supports_tsc_deadline() -> bool {
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    features.ecx & CPUID_FEATURES_ECX_TSC_DEADLINE != 0
+}
+
+/// Measures the rate the timer counts down at against PIT channel 2 and returns it in Hz.
+fn calibrate_timer() -> u64 {
+    write(REGISTER_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REGISTER_LVT_TIMER, LVT_MASKED | LVT_TIMER_ONE_SHOT);
+    write(REGISTER_TIMER_INITIAL_COUNT, u32::MAX);
+
+    let (start, end) = interrupts::without_interrupts(|| {
+        pit::measure_cycles(CALIBRATION_CYCLES, || read(REGISTER_TIMER_CURRENT_COUNT))
+    });
+    write(REGISTER_TIMER_INITIAL_COUNT, 0);
+
+    (start - end) as u64 * pit::PIT_FREQUENCY as u64 / CALIBRATION_CYCLES as u64
+}
+
+/// Returns the rate the timer counts down at in Hz, zero while the local APIC is not enabled.
+pub fn timer_frequency() -> u64 {
+    TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Returns the number of timer interrupts so far.
+pub fn timer_interrupts() -> u64 {
+    TIMER_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler.
+pub(super) fn timer_interrupt() {
+    TIMER_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn timer_count(duration: Duration) -> u32 {
+    let count = duration.as_nanos() * timer_frequency() as u128 / NANOS_PER_SECOND;
+    count.clamp(1, u32::MAX as u128) as u32
+}
+
+/// Fires the timer interrupt every `period`, as close as the timer resolution allows.
+pub fn start_periodic_timer(period: Duration) {
+    write(REGISTER_LVT_TIMER, LVT_TIMER_PERIODIC | TIMER_VECTOR as u32);
+    write(REGISTER_TIMER_INITIAL_COUNT, timer_count(period));
+}
+
+/// Fires the timer interrupt once after `delay`.
+pub fn start_one_shot_timer(delay: Duration) {
+    write(REGISTER_LVT_TIMER, LVT_TIMER_ONE_SHOT | TIMER_VECTOR as u32);
+    write(REGISTER_TIMER_INITIAL_COUNT, timer_count(delay));
+}
+
+/// Fires the timer interrupt once the time stamp counter reaches `deadline`.
+///
+/// Returns `false` without arming the timer if the CPU does not support this mode.
+pub fn set_tsc_deadline(deadline: u64) -> bool {
+    if !supports_tsc_deadline() {
+        return false;
+    }
+
+    write(
+        REGISTER_LVT_TIMER,
+        LVT_TIMER_TSC_DEADLINE | TIMER_VECTOR as u32,
+    );
+    unsafe {
+        // the switch to deadline mode has to be visible before the deadline is written
+        asm!("mfence", options(nostack, preserves_flags));
+        Msr::new(TSC_DEADLINE_MSR).write(deadline);
+    }
+    true
+}
+
+/// Stops the timer in every mode.
+pub fn stop_timer() {
+    write(REGISTER_LVT_TIMER, LVT_MASKED);
+    write(REGISTER_TIMER_INITIAL_COUNT, 0);
+    if supports_tsc_deadline() {
+        unsafe { Msr::new(TSC_DEADLINE_MSR).write(0) };
+    }
+}
+
+/// Halts until the timer interrupt count passes `count`, or gives up after `ticks` ticks of
+/// the tick clock. Returns whether the interrupt count was reached.
+#[cfg(test)]
+fn wait_for_timer_interrupts(count: u64, ticks: u64) -> bool {
+    let deadline = crate::time::ticks() + ticks;
+    while timer_interrupts() < count && crate::time::ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+    timer_interrupts() >= count
+}
+
+#[test_case]
+fn timer_fires_in_every_mode() {
+    if !super::is_active() {
+        return;
+    }
+    assert!(timer_frequency() > 0);
+
+    let before = timer_interrupts();
+    start_one_shot_timer(Duration::from_millis(1));
+    assert!(wait_for_timer_interrupts(before + 1, 50));
+    // a one-shot timer must not fire again
+    crate::time::sleep_ms(5);
+    assert_eq!(timer_interrupts(), before + 1);
+
+    start_periodic_timer(Duration::from_millis(1));
+    assert!(wait_for_timer_interrupts(before + 4, 50));
+    stop_timer();
+
+    let before = timer_interrupts();
+    if let Some(frequency) = crate::time::tsc::frequency() {
+        if set_tsc_deadline(crate::time::tsc::read() + frequency / 1000) {
+            assert!(wait_for_timer_interrupts(before + 1, 50));
+            stop_timer();
+        }
+    }
+}
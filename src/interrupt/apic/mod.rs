@@ -0,0 +1,346 @@
+mod io_apic;
+pub mod local;
+
+pub use io_apic::{IoApic, RedirectionEntry};
+
+use super::pic;
+use crate::memory::{self, MemoryError};
+use crate::{print, println, serial_println};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The vector of the local APIC timer
+pub const TIMER_VECTOR: u8 = 0x30;
+/// The vectors of the first three HPET comparators, see [crate::time::hpet]
+pub const HPET_VECTORS: [u8; 3] = [0x31, 0x32, 0x33];
+/// The vector the local APIC reports internal errors at
+pub const ERROR_VECTOR: u8 = 0xFE;
+/// The vector of interrupts that vanished before the CPU accepted them, which need no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The virtual address the APIC registers get mapped to, one page for the local APIC
+/// followed by one page per I/O APIC
+const APIC_REGISTERS_START: u64 = 0x_6666_0000_0000;
+
+/// Where the I/O APIC of nearly every PC is located
+const DEFAULT_IO_APIC_ADDRESS: u64 = 0xFEC0_0000;
+
+const CPUID_FEATURES: u32 = 0x1;
+const CPUID_FEATURES_EDX_APIC: u32 = 1 << 9;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// The I/O APICs and the topology they were programmed with, filled by [init]. Only locked
+/// with interrupts disabled.
+static ROUTING: Mutex<Option<(Vec<IoApic>, Topology)>> = Mutex::new(None);
+
+/// The interrupt controllers of the platform and how the ISA IRQs are wired to them,
+/// as described by the ACPI MADT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    /// The physical address of the local APIC registers, `None` to use the one the
+    /// firmware programmed into the APIC base MSR
+    pub local_apic_address: Option<PhysAddr>,
+    pub io_apics: Vec<IoApicDescriptor>,
+    /// ISA IRQs that are not connected to the I/O APIC input of the same number
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicDescriptor {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The global system interrupt of the first input
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+impl Topology {
+    /// The wiring of nearly every PC, for when there is no MADT: a single I/O APIC at
+    /// `0xFEC00000` with the PIT connected to input 2, as the first input is taken by
+    /// the PIC.
+    pub fn legacy() -> Self {
+        Self {
+            local_apic_address: None,
+            io_apics: vec![IoApicDescriptor {
+                id: 0,
+                address: PhysAddr::new(DEFAULT_IO_APIC_ADDRESS),
+                gsi_base: 0,
+            }],
+            overrides: vec![InterruptOverride {
+                irq: 0,
+                gsi: 2,
+                polarity: Polarity::ActiveHigh,
+                trigger: TriggerMode::Edge,
+            }],
+        }
+    }
+
+    /// Returns the global system interrupt the ISA `irq` is connected to, along with its
+    /// polarity and trigger mode.
+    pub fn resolve(&self, irq: u8) -> InterruptOverride {
+        self.overrides
+            .iter()
+            .find(|o| o.irq == irq)
+            .copied()
+            // ISA interrupts are active high and edge triggered
+            .unwrap_or(InterruptOverride {
+                irq,
+                gsi: irq as u32,
+                polarity: Polarity::ActiveHigh,
+                trigger: TriggerMode::Edge,
+            })
+    }
+}
+
+/// Returns `true` if the CPU has a local APIC.
+pub fn is_supported() -> bool {
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    features.edx & CPUID_FEATURES_EDX_APIC != 0
+}
+
+/// Returns `true` if interrupts are delivered through the APICs instead of the PICs.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub(super) fn init(topology: &Topology) {
+    print!("Initializing APIC...   ");
+
+    if !is_supported() {
+        println!("[Not present, keeping the PIC]");
+        return;
+    }
+
+    match interrupts::without_interrupts(|| enable(topology)) {
+        Ok(()) => {
+            println!("[Ok]");
+            serial_println!(
+                "APIC: local APIC {} with a {} kHz timer, {} I/O APICs",
+                local::id(),
+                local::timer_frequency() / 1000,
+                topology.io_apics.len()
+            );
+        }
+        Err(e) => {
+            println!("[Failed]");
+            serial_println!(
+                "APIC: mapping the registers failed ({:?}), keeping the PIC",
+                e
+            );
+        }
+    }
+}
+
+fn enable(topology: &Topology) -> Result<(), MemoryError> {
+    // map everything first, so a failure leaves the PIC in charge
+    let local_apic_address = topology
+        .local_apic_address
+        .unwrap_or_else(local::base_address);
+    let local_apic = map_registers(local_apic_address, 0)?;
+
+    let mut io_apics = Vec::new();
+    for (i, descriptor) in topology.io_apics.iter().enumerate() {
+        let base = map_registers(descriptor.address, i as u64 + 1)?;
+        io_apics.push(unsafe { IoApic::new(descriptor.id, base, descriptor.gsi_base) });
+    }
+
+    pic::disable();
+    unsafe { local::enable(local_apic) };
+
+    let destination = local::id();
+    for io_apic in io_apics.iter_mut() {
+        io_apic.mask_all();
+    }
+    for &irq in pic::HANDLED_IRQS.iter() {
+        let wiring = topology.resolve(irq);
+        match io_apics
+            .iter_mut()
+            .find(|io_apic| io_apic.handles(wiring.gsi))
+        {
+            Some(io_apic) => io_apic.set_entry(
+                wiring.gsi,
+                RedirectionEntry {
+                    vector: pic::PIC_1_OFFSET + irq,
+                    polarity: wiring.polarity,
+                    trigger: wiring.trigger,
+                    masked: false,
+                    destination,
+                },
+            ),
+            None => serial_println!("APIC: no I/O APIC handles IRQ {} (GSI {})", irq, wiring.gsi),
+        }
+    }
+
+    *ROUTING.lock() = Some((io_apics, topology.clone()));
+    ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Maps the register page at `address` uncached to the `index`th page after
+/// `APIC_REGISTERS_START`.
+fn map_registers(address: PhysAddr, index: u64) -> Result<VirtAddr, MemoryError> {
+    let page = Page::containing_address(VirtAddr::new(APIC_REGISTERS_START)) + index;
+    let frame = PhysFrame::containing_address(address);
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::NO_CACHE;
+
+    match unsafe { memory::map_physical_range(frame, Page::range_inclusive(page, page), flags) } {
+        Ok(()) => {}
+        // mapped by an earlier attempt
+        Err(MemoryError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(e) => return Err(e),
+    }
+    Ok(page.start_address() + (address.as_u64() - frame.start_address().as_u64()))
+}
+
+/// Returns how the ISA `irq` is currently programmed, or `None` if the APICs are not active
+/// or no I/O APIC handles it.
+pub fn irq_entry(irq: u8) -> Option<RedirectionEntry> {
+    interrupts::without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let (io_apics, topology) = routing.as_mut()?;
+        let gsi = topology.resolve(irq).gsi;
+        let io_apic = io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi))?;
+        Some(io_apic.entry(gsi))
+    })
+}
+
+/// Masks or unmasks the ISA `irq` at its I/O APIC. Returns `false` if the APICs are not
+/// active or no I/O APIC handles the IRQ.
+pub fn set_irq_masked(irq: u8, masked: bool) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let (io_apics, topology) = match routing.as_mut() {
+            Some(routing) => routing,
+            None => return false,
+        };
+        let gsi = topology.resolve(irq).gsi;
+        match io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi)) {
+            Some(io_apic) => {
+                io_apic.set_masked(gsi, masked);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Delivers the global system interrupt `gsi` of a device outside the ISA IRQs to `vector`
+/// on this CPU. Returns `false` if the APICs are not active or no I/O APIC handles the GSI.
+pub fn route_gsi(gsi: u32, vector: u8, polarity: Polarity, trigger: TriggerMode) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut routing = ROUTING.lock();
+        let io_apic = routing
+            .as_mut()
+            .and_then(|(io_apics, _)| io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi)));
+        match io_apic {
+            Some(io_apic) => {
+                let entry = RedirectionEntry {
+                    vector,
+                    polarity,
+                    trigger,
+                    masked: false,
+                    destination: local::id(),
+                };
+                io_apic.set_entry(gsi, entry);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+pub(super) fn init_idt_interrupt_handlers(idt: &mut InterruptDescriptorTable) {
+    idt[TIMER_VECTOR as usize].set_handler_fn(timer_interrupt_handler);
+    idt[ERROR_VECTOR as usize].set_handler_fn(error_interrupt_handler);
+    idt[SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
+    idt[HPET_VECTORS[0] as usize].set_handler_fn(hpet_0_interrupt_handler);
+    idt[HPET_VECTORS[1] as usize].set_handler_fn(hpet_1_interrupt_handler);
+    idt[HPET_VECTORS[2] as usize].set_handler_fn(hpet_2_interrupt_handler);
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    local::timer_interrupt();
+    local::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn error_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    serial_println!("APIC: error {:#x}", local::error_status());
+    local::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+extern "x86-interrupt" fn hpet_0_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::time::hpet::comparator_interrupt(0);
+    local::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn hpet_1_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::time::hpet::comparator_interrupt(1);
+    local::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn hpet_2_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::time::hpet::comparator_interrupt(2);
+    local::end_of_interrupt();
+}
+
+#[test_case]
+fn overrides_are_resolved() {
+    let topology = Topology::legacy();
+    assert_eq!(topology.resolve(0).gsi, 2);
+    let keyboard = topology.resolve(1);
+    assert_eq!(keyboard.gsi, 1);
+    assert_eq!(
+        (keyboard.polarity, keyboard.trigger),
+        (Polarity::ActiveHigh, TriggerMode::Edge)
+    );
+}
+
+#[test_case]
+fn handled_irqs_are_routed() {
+    if !is_active() {
+        return;
+    }
+
+    for &irq in pic::HANDLED_IRQS.iter() {
+        let entry = irq_entry(irq).expect("IRQ not handled by any I/O APIC");
+        assert_eq!(entry.vector, pic::PIC_1_OFFSET + irq);
+        assert!(!entry.masked);
+        assert_eq!(entry.destination, local::id());
+    }
+
+    // the tick clock keeps running through the I/O APIC
+    let ticks = crate::time::ticks();
+    crate::time::sleep_ticks(2);
+    assert!(crate::time::ticks() >= ticks + 2);
+}
@@ -0,0 +1,994 @@
+use crate::debug::{self, Registers};
+use crate::memory::{self, RegionKind};
+use crate::{gdt, println, serial, serial_println};
+use core::arch::x86_64::__cpuid;
+#[cfg(test)]
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::{fmt, mem, ptr};
+#[cfg(test)]
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr2, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{
+    Entry, HandlerFuncWithErrCode, InterruptDescriptorTable, InterruptStackFrame,
+    PageFaultErrorCode,
+};
+use x86_64::VirtAddr;
+
+/// The control protection exception, which x86_64 0.14 keeps among the reserved entries
+const CONTROL_PROTECTION_VECTOR: usize = 21;
+
+// The control protection entry is reached by treating the table as an array of its 256
+// entries, which is how x86_64 0.14 lays it out. The position of the reserved entries is
+// checked against their public neighbours in [init_idt_exception_handlers].
+const _: [(); 16] = [(); mem::size_of::<Entry<HandlerFuncWithErrCode>>()];
+const _: [(); 256 * 16] = [(); mem::size_of::<InterruptDescriptorTable>()];
+
+/// Reports the cause of an NMI from the motherboard, shared with the PIT and the speaker
+const SYSTEM_CONTROL_PORT: u16 = 0x61;
+const SYSTEM_CONTROL_IO_CHECK: u8 = 1 << 6;
+const SYSTEM_CONTROL_PARITY_CHECK: u8 = 1 << 7;
+
+const CPUID_FEATURES: u32 = 0x1;
+const CPUID_FEATURES_EDX_MCE: u32 = 1 << 7;
+const CPUID_FEATURES_EDX_MCA: u32 = 1 << 14;
+const MCG_CAP_MSR: u32 = 0x179;
+const MCG_CAP_BANK_COUNT_MASK: u64 = 0xFF;
+/// The status register of bank `n` follows at `n` times `MC_BANK_STRIDE`
+const MC_STATUS_MSR: u32 = 0x401;
+const MC_ADDRESS_MSR: u32 = 0x402;
+const MC_BANK_STRIDE: u32 = 4;
+const MC_STATUS_VALID: u64 = 1 << 63;
+const MC_STATUS_UNCORRECTED: u64 = 1 << 61;
+const MC_STATUS_ADDRESS_VALID: u64 = 1 << 58;
+
+/// The exceptions the CPU raises, by their vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    DivideError = 0,
+    Debug = 1,
+    NonMaskableInterrupt = 2,
+    Breakpoint = 3,
+    Overflow = 4,
+    BoundRangeExceeded = 5,
+    InvalidOpcode = 6,
+    DeviceNotAvailable = 7,
+    DoubleFault = 8,
+    InvalidTss = 10,
+    SegmentNotPresent = 11,
+    StackSegmentFault = 12,
+    GeneralProtectionFault = 13,
+    PageFault = 14,
+    X87FloatingPoint = 16,
+    AlignmentCheck = 17,
+    MachineCheck = 18,
+    SimdFloatingPoint = 19,
+    Virtualization = 20,
+    ControlProtection = 21,
+    SecurityException = 30,
+}
+
+impl Exception {
+    pub fn vector(self) -> u8 {
+        self as u8
+    }
+
+    fn from_vector(vector: u8) -> Option<Self> {
+        Some(match vector {
+            0 => Self::DivideError,
+            1 => Self::Debug,
+            2 => Self::NonMaskableInterrupt,
+            3 => Self::Breakpoint,
+            4 => Self::Overflow,
+            5 => Self::BoundRangeExceeded,
+            6 => Self::InvalidOpcode,
+            7 => Self::DeviceNotAvailable,
+            8 => Self::DoubleFault,
+            10 => Self::InvalidTss,
+            11 => Self::SegmentNotPresent,
+            12 => Self::StackSegmentFault,
+            13 => Self::GeneralProtectionFault,
+            14 => Self::PageFault,
+            16 => Self::X87FloatingPoint,
+            17 => Self::AlignmentCheck,
+            18 => Self::MachineCheck,
+            19 => Self::SimdFloatingPoint,
+            20 => Self::Virtualization,
+            21 => Self::ControlProtection,
+            30 => Self::SecurityException,
+            _ => return None,
+        })
+    }
+
+    /// Returns whether the CPU pushes an error code when it raises the exception.
+    pub fn has_error_code(self) -> bool {
+        matches!(
+            self,
+            Self::DoubleFault
+                | Self::InvalidTss
+                | Self::SegmentNotPresent
+                | Self::StackSegmentFault
+                | Self::GeneralProtectionFault
+                | Self::PageFault
+                | Self::AlignmentCheck
+                | Self::ControlProtection
+                | Self::SecurityException
+        )
+    }
+
+    /// Returns the short name the manuals use, e.g. `#GP`.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Self::DivideError => "#DE",
+            Self::Debug => "#DB",
+            Self::NonMaskableInterrupt => "NMI",
+            Self::Breakpoint => "#BP",
+            Self::Overflow => "#OF",
+            Self::BoundRangeExceeded => "#BR",
+            Self::InvalidOpcode => "#UD",
+            Self::DeviceNotAvailable => "#NM",
+            Self::DoubleFault => "#DF",
+            Self::InvalidTss => "#TS",
+            Self::SegmentNotPresent => "#NP",
+            Self::StackSegmentFault => "#SS",
+            Self::GeneralProtectionFault => "#GP",
+            Self::PageFault => "#PF",
+            Self::X87FloatingPoint => "#MF",
+            Self::AlignmentCheck => "#AC",
+            Self::MachineCheck => "#MC",
+            Self::SimdFloatingPoint => "#XM",
+            Self::Virtualization => "#VE",
+            Self::ControlProtection => "#CP",
+            Self::SecurityException => "#SX",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::DivideError => "DIVIDE ERROR",
+            Self::Debug => "DEBUG",
+            Self::NonMaskableInterrupt => "NON-MASKABLE INTERRUPT",
+            Self::Breakpoint => "BREAKPOINT",
+            Self::Overflow => "OVERFLOW",
+            Self::BoundRangeExceeded => "BOUND RANGE EXCEEDED",
+            Self::InvalidOpcode => "INVALID OPCODE",
+            Self::DeviceNotAvailable => "DEVICE NOT AVAILABLE",
+            Self::DoubleFault => "DOUBLE FAULT",
+            Self::InvalidTss => "INVALID TSS",
+            Self::SegmentNotPresent => "SEGMENT NOT PRESENT",
+            Self::StackSegmentFault => "STACK SEGMENT FAULT",
+            Self::GeneralProtectionFault => "GENERAL PROTECTION FAULT",
+            Self::PageFault => "PAGE FAULT",
+            Self::X87FloatingPoint => "X87 FLOATING POINT",
+            Self::AlignmentCheck => "ALIGNMENT CHECK",
+            Self::MachineCheck => "MACHINE CHECK",
+            Self::SimdFloatingPoint => "SIMD FLOATING POINT",
+            Self::Virtualization => "VIRTUALIZATION",
+            Self::ControlProtection => "CONTROL PROTECTION",
+            Self::SecurityException => "SECURITY EXCEPTION",
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.mnemonic())
+    }
+}
+
+/// The descriptor table a selector error code points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// The error code of #TS, #NP, #SS and #GP, naming the descriptor the fault relates to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode {
+    /// The fault happened while delivering an external event, e.g. a hardware interrupt
+    pub external: bool,
+    pub table: DescriptorTable,
+    pub index: u16,
+}
+
+impl SelectorErrorCode {
+    pub fn from_bits(code: u64) -> Self {
+        let table = if code & (1 << 1) != 0 {
+            DescriptorTable::Idt
+        } else if code & (1 << 2) != 0 {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        };
+
+        Self {
+            external: code & 1 != 0,
+            table,
+            index: ((code >> 3) & 0x1FFF) as u16,
+        }
+    }
+}
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} entry {:#x}", self.table, self.index)?;
+        if self.external {
+            write!(f, ", during an external event")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns what the shadow stack or indirect branch tracking objected to, from the error
+/// code of a control protection exception.
+pub fn control_protection_cause(code: u64) -> &'static str {
+    match code & 0x7FFF {
+        1 => "near return to a different address than the shadow stack holds",
+        2 => "far return to a different address than the shadow stack holds",
+        3 => "indirect branch to an instruction other than ENDBRANCH",
+        4 => "RSTORSSP with an invalid shadow stack token",
+        5 => "SETSSBSY with an invalid supervisor shadow stack token",
+        _ => "unknown cause",
+    }
+}
+
+/// Formats the error code of `exception` with everything that can be decoded from it.
+struct ErrorCode(Exception, u64);
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (exception, code) = (self.0, self.1);
+        write!(f, "Error code: {:#x}", code)?;
+        match exception {
+            Exception::InvalidTss
+            | Exception::SegmentNotPresent
+            | Exception::StackSegmentFault
+            | Exception::GeneralProtectionFault
+                if code != 0 =>
+            {
+                write!(f, ", {}", SelectorErrorCode::from_bits(code))
+            }
+            Exception::ControlProtection => write!(f, ", {}", control_protection_cause(code)),
+            Exception::PageFault => {
+                write!(f, ", {:?}", PageFaultErrorCode::from_bits_truncate(code))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An error logged by a machine check bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineCheckError {
+    pub bank: u32,
+    pub status: u64,
+    /// The physical address the error relates to, if the bank recorded one
+    pub address: Option<u64>,
+    /// The hardware could not correct the error
+    pub uncorrected: bool,
+}
+
+/// Returns the errors currently logged by the machine check banks, none if the CPU lacks
+/// the machine check architecture.
+pub fn machine_check_errors() -> impl Iterator<Item = MachineCheckError> {
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    let banks = if features.edx & CPUID_FEATURES_EDX_MCA != 0 {
+        (unsafe { Msr::new(MCG_CAP_MSR).read() } & MCG_CAP_BANK_COUNT_MASK) as u32
+    } else {
+        0
+    };
+
+    (0..banks).filter_map(|bank| {
+        let status = unsafe { Msr::new(MC_STATUS_MSR + bank * MC_BANK_STRIDE).read() };
+        if status & MC_STATUS_VALID == 0 {
+            return None;
+        }
+        let address = if status & MC_STATUS_ADDRESS_VALID != 0 {
+            Some(unsafe { Msr::new(MC_ADDRESS_MSR + bank * MC_BANK_STRIDE).read() })
+        } else {
+            None
+        };
+
+        Some(MachineCheckError {
+            bank,
+            status,
+            address,
+            uncorrected: status & MC_STATUS_UNCORRECTED != 0,
+        })
+    })
+}
+
+/// Lets the CPU raise machine check exceptions instead of shutting down on hardware errors.
+pub(super) fn enable_machine_checks() {
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    if features.edx & CPUID_FEATURES_EDX_MCE != 0 {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+    }
+}
+
+/// The state of the interrupted code, as the CPU and `exception_entry` leave it on the
+/// stack before calling [dispatch_exception].
+#[derive(Debug)]
+#[repr(C)]
+struct ExceptionContext {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    vector: u64,
+    /// Zero for the exceptions without an error code
+    error_code: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl ExceptionContext {
+    /// Returns the registers of the interrupted code. The control and data segment registers
+    /// are read as they are now, an exception does not change them.
+    fn registers(&self) -> Registers {
+        Registers {
+            rax: self.rax,
+            rbx: self.rbx,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            rbp: self.rbp,
+            rsp: self.rsp,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rip: self.rip,
+            rflags: self.rflags,
+            cs: self.cs as u16,
+            ss: self.ss as u16,
+            ..Registers::capture()
+        }
+    }
+
+    fn error_code(&self, exception: Exception) -> Option<u64> {
+        if exception.has_error_code() {
+            Some(self.error_code)
+        } else {
+            None
+        }
+    }
+
+    fn resume_at(&mut self, address: u64) {
+        if address != 0 {
+            self.rip = address;
+        }
+    }
+}
+
+/// Defines the entry stub of an exception, which pushes a zero for the exceptions without
+/// an error code and the vector, so every exception reaches `exception_entry` with the same
+/// stack layout.
+macro_rules! entry_stub {
+    ($stub:ident, $exception:expr) => {
+        entry_stub!(@define $stub, $exception, "push 0");
+    };
+    ($stub:ident, $exception:expr, error_code) => {
+        entry_stub!(@define $stub, $exception, "");
+    };
+    (@define $stub:ident, $exception:expr, $push_error_code:literal) => {
+        global_asm!(
+            concat!(".global ", stringify!($stub)),
+            concat!(stringify!($stub), ":"),
+            $push_error_code,
+            "push {vector}",
+            "jmp exception_entry",
+            vector = const $exception as u8,
+        );
+        extern "C" {
+            fn $stub();
+        }
+    };
+}
+
+entry_stub!(divide_error_entry, Exception::DivideError);
+entry_stub!(overflow_entry, Exception::Overflow);
+entry_stub!(bound_range_exceeded_entry, Exception::BoundRangeExceeded);
+entry_stub!(invalid_opcode_entry, Exception::InvalidOpcode);
+entry_stub!(device_not_available_entry, Exception::DeviceNotAvailable);
+entry_stub!(double_fault_entry, Exception::DoubleFault, error_code);
+entry_stub!(invalid_tss_entry, Exception::InvalidTss, error_code);
+entry_stub!(
+    segment_not_present_entry,
+    Exception::SegmentNotPresent,
+    error_code
+);
+entry_stub!(
+    stack_segment_fault_entry,
+    Exception::StackSegmentFault,
+    error_code
+);
+entry_stub!(
+    general_protection_fault_entry,
+    Exception::GeneralProtectionFault,
+    error_code
+);
+entry_stub!(page_fault_entry, Exception::PageFault, error_code);
+entry_stub!(x87_floating_point_entry, Exception::X87FloatingPoint);
+entry_stub!(alignment_check_entry, Exception::AlignmentCheck, error_code);
+entry_stub!(machine_check_entry, Exception::MachineCheck);
+entry_stub!(simd_floating_point_entry, Exception::SimdFloatingPoint);
+entry_stub!(virtualization_entry, Exception::Virtualization);
+entry_stub!(
+    control_protection_entry,
+    Exception::ControlProtection,
+    error_code
+);
+entry_stub!(
+    security_exception_entry,
+    Exception::SecurityException,
+    error_code
+);
+
+// Saves the general purpose registers below the vector, error code and interrupt stack
+// frame, which makes an `ExceptionContext`, and restores them for the return. The stack is
+// 16 byte aligned at the call: the CPU aligns it before pushing the frame, and the frame,
+// error code, vector and registers take 22 quadwords.
+global_asm!(
+    ".global exception_entry",
+    "exception_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "cld",
+    "call dispatch_exception",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    // the vector and error code
+    "add rsp, 16",
+    "iretq",
+);
+
+/// Returns an entry stub as the handler type of an IDT entry.
+///
+/// This function is unsafe because the stub must be one defined by `entry_stub!`, which
+/// the CPU can call in place of any handler.
+unsafe fn stub<F>(entry: unsafe extern "C" fn()) -> F {
+    assert_eq!(mem::size_of::<F>(), mem::size_of_val(&entry));
+    mem::transmute_copy(&entry)
+}
+
+/// Installs the handlers of the exceptions. Only the debug trap, NMIs and breakpoints are
+/// handled in Rust directly, the faults go through an entry stub that saves the registers
+/// of the faulting code.
+pub(super) fn init_idt_exception_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.debug.set_handler_fn(debug_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+
+    unsafe {
+        idt.divide_error.set_handler_fn(stub(divide_error_entry));
+        idt.overflow.set_handler_fn(stub(overflow_entry));
+        idt.bound_range_exceeded
+            .set_handler_fn(stub(bound_range_exceeded_entry));
+        idt.invalid_opcode
+            .set_handler_fn(stub(invalid_opcode_entry));
+        idt.device_not_available
+            .set_handler_fn(stub(device_not_available_entry));
+        idt.invalid_tss.set_handler_fn(stub(invalid_tss_entry));
+        idt.segment_not_present
+            .set_handler_fn(stub(segment_not_present_entry));
+        idt.stack_segment_fault
+            .set_handler_fn(stub(stack_segment_fault_entry));
+        idt.general_protection_fault
+            .set_handler_fn(stub(general_protection_fault_entry));
+        idt.page_fault.set_handler_fn(stub(page_fault_entry));
+        idt.x87_floating_point
+            .set_handler_fn(stub(x87_floating_point_entry));
+        idt.alignment_check
+            .set_handler_fn(stub(alignment_check_entry));
+        idt.simd_floating_point
+            .set_handler_fn(stub(simd_floating_point_entry));
+        idt.virtualization
+            .set_handler_fn(stub(virtualization_entry));
+        idt.security_exception
+            .set_handler_fn(stub(security_exception_entry));
+
+        idt.double_fault
+            .set_handler_fn(stub(double_fault_entry))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt
+            .set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        idt.machine_check
+            .set_handler_fn(stub(machine_check_entry))
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+
+        // every entry has the same layout, whatever the type of its handler
+        let virtualization = ptr::addr_of!(idt.virtualization) as usize;
+        let security_exception = ptr::addr_of!(idt.security_exception) as usize;
+        let entries = idt as *mut InterruptDescriptorTable as *mut Entry<HandlerFuncWithErrCode>;
+        let entry = |exception: Exception| entries.add(exception.vector() as usize) as usize;
+        assert_eq!(entry(Exception::Virtualization), virtualization);
+        assert_eq!(entry(Exception::SecurityException), security_exception);
+        (*entries.add(CONTROL_PROTECTION_VECTOR)).set_handler_fn(stub(control_protection_entry));
+    }
+}
+
+/// Only tests raise exceptions on purpose, see the test version.
+#[cfg(not(test))]
+fn expected(_exception: Exception, _error_code: Option<u64>) -> Option<u64> {
+    None
+}
+
+fn resume_at(stack_frame: &mut InterruptStackFrame, address: u64) {
+    if address != 0 {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = VirtAddr::new(address));
+        }
+    }
+}
+
+/// Called by `exception_entry` for the exceptions with an entry stub.
+#[no_mangle]
+extern "C" fn dispatch_exception(context: &mut ExceptionContext) {
+    #[cfg(test)]
+    CAUGHT_REGISTERS.lock().replace(context.registers());
+
+    match Exception::from_vector(context.vector as u8) {
+        Some(Exception::DoubleFault) => double_fault(context),
+        Some(Exception::PageFault) => page_fault(context),
+        Some(Exception::MachineCheck) => machine_check(context),
+        Some(exception) => fault(exception, context),
+        None => unreachable!("no exception has vector {}", context.vector),
+    }
+}
+
+/// Handles an exception the kernel can't recover from, unless a test raised it on purpose.
+fn fault(exception: Exception, context: &mut ExceptionContext) {
+    let error_code = context.error_code(exception);
+    if let Some(address) = expected(exception, error_code) {
+        context.resume_at(address);
+        return;
+    }
+
+    debug::record_fault(context.registers());
+    match error_code {
+        Some(code) => panic!("EXCEPTION: {}\n{}", exception, ErrorCode(exception, code)),
+        None => panic!("EXCEPTION: {}", exception),
+    }
+}
+
+extern "x86-interrupt" fn debug_handler(mut stack_frame: InterruptStackFrame) {
+    if let Some(address) = expected(Exception::Debug, None) {
+        resume_at(&mut stack_frame, address);
+        return;
+    }
+    // a trap, without a debugger there is nothing to do but carry on
+    serial_println!("EXCEPTION: {}\n{:#?}", Exception::Debug, stack_frame);
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(mut stack_frame: InterruptStackFrame) {
+    if let Some(address) = expected(Exception::NonMaskableInterrupt, None) {
+        resume_at(&mut stack_frame, address);
+        return;
+    }
+
+    let reason = unsafe { Port::<u8>::new(SYSTEM_CONTROL_PORT).read() };
+    // an NMI can interrupt a print, waiting for the serial port would never end
+    serial::try_print(format_args!(
+        "NMI: I/O channel check {}, parity check {}\n\r",
+        reason & SYSTEM_CONTROL_IO_CHECK != 0,
+        reason & SYSTEM_CONTROL_PARITY_CHECK != 0
+    ));
+}
+
+extern "x86-interrupt" fn breakpoint_handler(mut stack_frame: InterruptStackFrame) {
+    if let Some(address) = expected(Exception::Breakpoint, None) {
+        resume_at(&mut stack_frame, address);
+        return;
+    }
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+fn double_fault(context: &ExceptionContext) -> ! {
+    debug::record_fault(context.registers());
+    // a fault while pushing onto a full stack shows up as a double fault, with CR2 in the
+    // guard page below it
+    match memory::find_region(Cr2::read()) {
+        Some(region) if region.kind == RegionKind::Guard => panic!(
+            "EXCEPTION: {}, stack overflow into {}",
+            Exception::DoubleFault,
+            region
+        ),
+        _ => panic!("EXCEPTION: {}", Exception::DoubleFault),
+    }
+}
+
+/// Backs demand-zero pages and panics on accesses no region allows.
+fn page_fault(context: &mut ExceptionContext) {
+    let address = Cr2::read();
+    let code = context.error_code;
+    let error_code = PageFaultErrorCode::from_bits_truncate(code);
+    let error = match memory::handle_page_fault(address, error_code) {
+        Ok(()) => return,
+        Err(error) => error,
+    };
+
+    if let Some(resume) = expected(Exception::PageFault, Some(code)) {
+        context.resume_at(resume);
+        return;
+    }
+
+    debug::record_fault(context.registers());
+    panic!(
+        "EXCEPTION: {}\nAccessed address: {:?}\n{}\n{}",
+        Exception::PageFault,
+        address,
+        ErrorCode(Exception::PageFault, code),
+        error
+    );
+}
+
+fn machine_check(context: &ExceptionContext) -> ! {
+    for error in machine_check_errors() {
+        serial_println!("MCE: {:x?}", error);
+    }
+    debug::record_fault(context.registers());
+    panic!("EXCEPTION: {}", Exception::MachineCheck)
+}
+
+/// Stands for no exception in `EXPECTED` and `CAUGHT`
+#[cfg(test)]
+const NONE: u8 = 0xFF;
+/// The vector a test is about to raise
+#[cfg(test)]
+static EXPECTED: AtomicU8 = AtomicU8::new(NONE);
+/// The vector of the last expected exception that was raised, and its error code
+#[cfg(test)]
+static CAUGHT: AtomicU8 = AtomicU8::new(NONE);
+#[cfg(test)]
+static CAUGHT_ERROR_CODE: AtomicU64 = AtomicU64::new(0);
+/// Where the handler resumes the test, zero for where the exception happened
+#[cfg(test)]
+static RESUME_ADDRESS: AtomicU64 = AtomicU64::new(0);
+/// The registers of the code interrupted by the last exception with an entry stub
+#[cfg(test)]
+static CAUGHT_REGISTERS: Mutex<Option<Registers>> = Mutex::new(None);
+
+/// A vector without a handler, raised by [segment_not_present_is_raised]
+#[cfg(test)]
+const MISSING_VECTOR: u8 = 0x90;
+
+/// Resumes a test that raised `exception` on purpose, see `raise!`. Returns the address
+/// to resume at, zero to return to where the exception happened, or `None` if the
+/// exception was not expected.
+#[cfg(test)]
+fn expected(exception: Exception, error_code: Option<u64>) -> Option<u64> {
+    EXPECTED
+        .compare_exchange(
+            exception.vector(),
+            NONE,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+        .ok()?;
+    CAUGHT_ERROR_CODE.store(error_code.unwrap_or(0), Ordering::Relaxed);
+    CAUGHT.store(exception.vector(), Ordering::Relaxed);
+    Some(RESUME_ADDRESS.swap(0, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+fn expect(exception: Exception) {
+    CAUGHT.store(NONE, Ordering::Relaxed);
+    RESUME_ADDRESS.store(0, Ordering::Relaxed);
+    EXPECTED.store(exception.vector(), Ordering::Relaxed);
+}
+
+/// Asserts that `exception` was raised since [expect] and returns its error code.
+#[cfg(test)]
+fn assert_caught(exception: Exception) -> u64 {
+    EXPECTED.store(NONE, Ordering::Relaxed);
+    let caught = CAUGHT.swap(NONE, Ordering::Relaxed);
+    assert_eq!(caught, exception.vector(), "{} was not raised", exception);
+    CAUGHT_ERROR_CODE.load(Ordering::Relaxed)
+}
+
+/// Runs the instructions, which raise `exception`, resumes after them and returns the
+/// error code. Operands follow the instructions after a semicolon.
+#[cfg(test)]
+macro_rules! raise {
+    ($exception:expr, $($instructions:literal),+ $(; $($operands:tt)*)?) => {{
+        expect($exception);
+        unsafe {
+            asm!(
+                "lea {resume}, [rip + 2f]",
+                "mov [{resume_address}], {resume}",
+                $($instructions,)+
+                "2:",
+                resume = out(reg) _,
+                resume_address = in(reg) &RESUME_ADDRESS as *const AtomicU64,
+                $($($operands)*)?
+            );
+        }
+        assert_caught($exception)
+    }};
+}
+
+#[test_case]
+fn breakpoint_exception_handling() {
+    x86_64::instructions::interrupts::int3();
+
+    raise!(Exception::Breakpoint, "int3");
+}
+
+#[test_case]
+fn divide_error_is_raised() {
+    raise!(
+        Exception::DivideError,
+        "div {divisor:e}";
+        divisor = in(reg) 0u32,
+        inout("eax") 1u32 => _,
+        inout("edx") 0u32 => _,
+    );
+}
+
+#[test_case]
+fn debug_is_raised() {
+    // INT1, which raises a debug trap without touching the debug registers
+    raise!(Exception::Debug, ".byte 0xf1");
+}
+
+#[test_case]
+fn non_maskable_interrupt_is_raised() {
+    expect(Exception::NonMaskableInterrupt);
+    if super::apic::is_active() {
+        super::apic::local::send_nmi_to_self();
+    } else {
+        unsafe { asm!("int 2") };
+    }
+    for _ in 0..1000 {
+        if CAUGHT.load(Ordering::Relaxed) != NONE {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    assert_caught(Exception::NonMaskableInterrupt);
+}
+
+#[test_case]
+fn overflow_is_raised() {
+    // INTO does not exist in 64 bit mode, so the vector is raised in software
+    raise!(Exception::Overflow, "int 4");
+}
+
+#[test_case]
+fn bound_range_exceeded_is_raised() {
+    // BOUND does not exist in 64 bit mode, so the vector is raised in software
+    raise!(Exception::BoundRangeExceeded, "int 5");
+}
+
+#[test_case]
+fn invalid_opcode_is_raised() {
+    raise!(Exception::InvalidOpcode, "ud2");
+}
+
+#[test_case]
+fn faults_capture_the_interrupted_registers() {
+    use x86_64::instructions::segmentation::{Segment, CS};
+
+    let instruction_pointer: u64;
+    raise!(
+        Exception::InvalidOpcode,
+        "lea {instruction_pointer}, [rip]",
+        "ud2";
+        instruction_pointer = out(reg) instruction_pointer,
+        in("r12") 0x1234_5678_u64,
+        in("r15") 0x8765_4321_u64,
+    );
+
+    let registers = CAUGHT_REGISTERS.lock().take().unwrap();
+    assert_eq!(registers.rip, instruction_pointer);
+    assert_eq!(registers.r12, 0x1234_5678);
+    assert_eq!(registers.r15, 0x8765_4321);
+    assert_eq!(registers.cs, CS::get_reg().0);
+}
+
+#[test_case]
+fn device_not_available_is_raised() {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+    raise!(Exception::DeviceNotAvailable, "fnop");
+    unsafe { Cr0::update(|flags| flags.remove(Cr0Flags::TASK_SWITCHED)) };
+}
+
+#[test_case]
+fn invalid_tss_is_decoded() {
+    // a TSS fault needs a task switch, which long mode does not have, so only the
+    // decoding of its error code is tested
+    let code = SelectorErrorCode::from_bits(0x2B);
+    assert_eq!(code.table, DescriptorTable::Gdt);
+    assert_eq!(code.index, 5);
+    assert!(code.external);
+    let text = alloc::format!("{}", ErrorCode(Exception::InvalidTss, 0x2B));
+    assert_eq!(
+        text,
+        "Error code: 0x2b, Gdt entry 0x5, during an external event"
+    );
+}
+
+#[test_case]
+fn segment_not_present_is_raised() {
+    let code = raise!(Exception::SegmentNotPresent, "int 0x90");
+    let code = SelectorErrorCode::from_bits(code);
+    assert_eq!(code.table, DescriptorTable::Idt);
+    assert_eq!(code.index, MISSING_VECTOR as u16);
+}
+
+#[test_case]
+fn stack_segment_fault_is_raised() {
+    // a non-canonical address relative to RSP is checked against the stack segment
+    let code = raise!(
+        Exception::StackSegmentFault,
+        "mov {value}, [rsp + {offset}]";
+        value = out(reg) _,
+        offset = in(reg) 1u64 << 63,
+    );
+    assert_eq!(code, 0);
+}
+
+#[test_case]
+fn general_protection_fault_is_raised() {
+    // the last possible GDT entry, far beyond the end of the GDT
+    let code = raise!(
+        Exception::GeneralProtectionFault,
+        "mov ds, {selector:x}";
+        selector = in(reg) 0xFFF8u16,
+    );
+    let code = SelectorErrorCode::from_bits(code);
+    assert_eq!(code.table, DescriptorTable::Gdt);
+    assert_eq!(code.index, 0x1FFF);
+}
+
+#[test_case]
+fn page_fault_is_raised() {
+    // no region covers the address, so the fault can't be resolved
+    let code = raise!(
+        Exception::PageFault,
+        "mov {value}, [{address}]";
+        value = out(reg) _,
+        address = in(reg) 0x_7777_4000_0000u64,
+    );
+    let code = PageFaultErrorCode::from_bits_truncate(code);
+    assert!(!code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    assert!(!code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+}
+
+#[test_case]
+fn x87_floating_point_is_raised() {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    // report x87 errors as exceptions instead of through IRQ 13
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::NUMERIC_ERROR)) };
+    // the default control word with division by zero unmasked
+    let control_word: u16 = 0x037B;
+    raise!(
+        Exception::X87FloatingPoint,
+        "fninit",
+        "fldcw [{control_word}]",
+        "fld1",
+        "fldz",
+        "fdivp",
+        "fwait";
+        control_word = in(reg) &control_word as *const u16,
+    );
+    unsafe { asm!("fninit") };
+}
+
+#[test_case]
+fn alignment_check_is_decoded() {
+    // alignment is only checked in user mode, which the kernel does not have yet
+    let text = alloc::format!("{}", ErrorCode(Exception::AlignmentCheck, 0));
+    assert_eq!(text, "Error code: 0x0");
+    assert_eq!(Exception::AlignmentCheck.vector(), 17);
+}
+
+#[test_case]
+fn machine_check_banks_are_clean() {
+    // machine checks are raised by failing hardware only, and their handler never returns
+    for error in machine_check_errors() {
+        assert!(
+            !error.uncorrected,
+            "uncorrected machine check: {:x?}",
+            error
+        );
+    }
+}
+
+#[test_case]
+fn simd_floating_point_is_raised() {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    unsafe {
+        Cr0::update(|flags| flags.remove(Cr0Flags::EMULATE_COPROCESSOR));
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+    // the default MXCSR with division by zero and invalid operations unmasked
+    let unmasked: u32 = 0x1D00;
+    let default: u32 = 0x1F80;
+    // the kernel is built without SSE, so the clobbered XMM registers can't be declared
+    raise!(
+        Exception::SimdFloatingPoint,
+        "ldmxcsr [{mxcsr}]",
+        "mov {one:e}, 0x3f800000",
+        "movd xmm0, {one:e}",
+        "xorps xmm1, xmm1",
+        "divss xmm0, xmm1";
+        mxcsr = in(reg) &unmasked as *const u32,
+        one = out(reg) _,
+    );
+    unsafe { asm!("ldmxcsr [{}]", in(reg) &default as *const u32) };
+}
+
+#[test_case]
+fn virtualization_is_raised() {
+    // only a hypervisor with EPT violation virtualization raises it, so it is raised in software
+    raise!(Exception::Virtualization, "int 20");
+}
+
+#[test_case]
+fn control_protection_is_decoded() {
+    // control flow enforcement is not enabled, so only the decoding is tested
+    let text = alloc::format!("{}", ErrorCode(Exception::ControlProtection, 3));
+    assert_eq!(
+        text,
+        "Error code: 0x3, indirect branch to an instruction other than ENDBRANCH"
+    );
+    assert_eq!(Exception::ControlProtection.mnemonic(), "#CP");
+}
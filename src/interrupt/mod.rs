@@ -1,7 +1,28 @@
+pub mod apic;
+pub mod exception;
 mod idt;
 mod pic;
 
+pub use apic::Topology;
+
 pub fn init() {
     idt::init();
     pic::init();
 }
+
+/// Moves interrupt delivery from the PICs to the APICs described by `topology`, if the CPU
+/// has a local APIC. The PICs stay in charge otherwise.
+///
+/// Has to be called after the memory subsystem is initialized, to map the APIC registers.
+pub fn init_apic(topology: &Topology) {
+    apic::init(topology);
+}
+
+/// Signals the end of the interrupt to whichever controller delivered it.
+fn end_of_interrupt(index: pic::InterruptIndex) {
+    if apic::is_active() {
+        apic::local::end_of_interrupt();
+    } else {
+        pic::end_of_interrupt(index);
+    }
+}
@@ -1,29 +1,42 @@
 use crate::{print, println};
-use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
 use spin::Mutex;
 use x86_64::instructions::{interrupts, port::Port};
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
-const PIC_1_OFFSET: u8 = 32;
+/// The vector of IRQ 0, the I/O APIC delivers IRQs to the same vectors as the PICs
+pub(super) const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
-const KEYBOARD_SCANCODE_PORT: u16 = 0x60;
+const PS2_DATA_PORT: u16 = 0x60;
+const PIC_1_COMMAND_PORT: u16 = 0x20;
+const PIC_1_DATA_PORT: u16 = 0x21;
+const PIC_2_DATA_PORT: u16 = 0xA1;
+const TIMER_IRQ: u8 = 0;
+const KEYBOARD_IRQ: u8 = 1;
+/// The line of the primary PIC the secondary one is connected to
+const CASCADE_IRQ: u8 = 2;
+const RTC_IRQ: u8 = 8;
+const MOUSE_IRQ: u8 = 12;
+/// The lowest priority line of each PIC, which it raises for interrupts that vanished
+/// before the CPU accepted them, even while masked
+const SPURIOUS_IRQ_1: u8 = 7;
+const SPURIOUS_IRQ_2: u8 = 15;
+const END_OF_INTERRUPT: u8 = 0x20;
+/// The IRQs the kernel handles, which are routed through the I/O APIC when it takes over
+pub(super) const HANDLED_IRQS: [u8; 4] = [TIMER_IRQ, KEYBOARD_IRQ, RTC_IRQ, MOUSE_IRQ];
 
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-lazy_static! {
-    static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-        Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-    );
-}
-
 pub fn init() {
     print!("Initializing PIC...   ");
 
     unsafe {
         PICS.lock().initialize();
+        // the firmware may have masked the mouse and with it the secondary PIC
+        unmask_irq(CASCADE_IRQ);
+        unmask_irq(RTC_IRQ);
+        unmask_irq(MOUSE_IRQ);
     }
 
     // enable external interrupts
@@ -35,6 +48,35 @@ pub fn init() {
 pub(super) fn init_idt_interrupt_handlers(idt: &mut InterruptDescriptorTable) {
     idt[InterruptIndex::Timer as usize].set_handler_fn(timer_interrupt_handler);
     idt[InterruptIndex::Keyboard as usize].set_handler_fn(keyboard_interrupt_handler);
+    idt[InterruptIndex::Rtc as usize].set_handler_fn(rtc_interrupt_handler);
+    idt[InterruptIndex::Mouse as usize].set_handler_fn(mouse_interrupt_handler);
+    idt[(PIC_1_OFFSET + SPURIOUS_IRQ_1) as usize].set_handler_fn(spurious_irq_1_handler);
+    idt[(PIC_1_OFFSET + SPURIOUS_IRQ_2) as usize].set_handler_fn(spurious_irq_2_handler);
+}
+
+/// Masks every interrupt on both PICs, once the I/O APIC delivers them instead.
+pub(super) fn disable() {
+    unsafe {
+        Port::<u8>::new(PIC_1_DATA_PORT).write(0xFF);
+        Port::<u8>::new(PIC_2_DATA_PORT).write(0xFF);
+    }
+}
+
+pub(super) fn end_of_interrupt(index: InterruptIndex) {
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(index as u8);
+    }
+}
+
+/// Allows the interrupt `irq` (0-15) through the PICs.
+unsafe fn unmask_irq(irq: u8) {
+    let (mut port, bit) = if irq < 8 {
+        (Port::<u8>::new(PIC_1_DATA_PORT), irq)
+    } else {
+        (Port::<u8>::new(PIC_2_DATA_PORT), irq - 8)
+    };
+    let mask = port.read();
+    port.write(mask & !(1 << bit));
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -42,32 +84,49 @@ pub(super) fn init_idt_interrupt_handlers(idt: &mut InterruptDescriptorTable) {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard, // implicitly gets value of Timer + 1
+    Rtc = PIC_2_OFFSET + RTC_IRQ - 8,
+    Mouse = PIC_2_OFFSET + MOUSE_IRQ - 8,
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer as u8);
-    }
+    crate::time::tick(crate::time::TickSource::Pit);
+
+    super::end_of_interrupt(InterruptIndex::Timer);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    let mut keyboard = KEYBOARD.lock();
-
-    let mut port = Port::<u8>::new(KEYBOARD_SCANCODE_PORT);
+    let mut port = Port::<u8>::new(PS2_DATA_PORT);
     let scancode = unsafe { port.read() };
 
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(keycode) => print!("{:?}", keycode),
-            }
-        }
-    }
+    // decoding happens in a task, see task::keyboard
+    crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard as u8);
-    }
+    super::end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "x86-interrupt" fn rtc_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // the RTC only raises the interrupt again once it has been acknowledged
+    crate::time::rtc::acknowledge_interrupt();
+    crate::time::tick(crate::time::TickSource::Rtc);
+
+    super::end_of_interrupt(InterruptIndex::Rtc);
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let mut port = Port::<u8>::new(PS2_DATA_PORT);
+    let byte = unsafe { port.read() };
+
+    // packets are assembled in a task, see task::mouse
+    crate::task::mouse::add_byte(byte);
+
+    super::end_of_interrupt(InterruptIndex::Mouse);
+}
+
+/// A spurious interrupt of the primary PIC, which must not be acknowledged.
+extern "x86-interrupt" fn spurious_irq_1_handler(_stack_frame: InterruptStackFrame) {}
+
+/// A spurious interrupt of the secondary PIC. The primary PIC saw a real interrupt on the
+/// cascade line, so only it is acknowledged.
+extern "x86-interrupt" fn spurious_irq_2_handler(_stack_frame: InterruptStackFrame) {
+    unsafe { Port::<u8>::new(PIC_1_COMMAND_PORT).write(END_OF_INTERRUPT) };
 }
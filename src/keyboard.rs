@@ -0,0 +1,316 @@
+use crate::ps2::{self, Ps2Error};
+use crate::serial_println;
+use crate::time::Instant;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, KeyboardLayout,
+};
+use spin::Mutex;
+
+const KEYBOARD_COMMAND_SET_LEDS: u8 = 0xED;
+pub(crate) const KEYBOARD_RESPONSE_ACK: u8 = 0xFA;
+pub(crate) const KEYBOARD_RESPONSE_RESEND: u8 = 0xFE;
+/// How often a byte is sent again when the keyboard asks for it
+const KEYBOARD_RETRIES: usize = 3;
+const KEYBOARD_RESPONSE_TIMEOUT: Duration = Duration::from_millis(20);
+
+static KEYBOARD: Mutex<KeyboardState> = Mutex::new(KeyboardState::new());
+/// The last response to a byte sent to the keyboard, 0 until one arrives
+static RESPONSE: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    Dvorak,
+    Azerty,
+    German,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::Dvorak,
+        Layout::Azerty,
+        Layout::German,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Us => "US",
+            Layout::Uk => "UK",
+            Layout::Dvorak => "Dvorak",
+            Layout::Azerty => "AZERTY",
+            Layout::German => "German",
+        }
+    }
+
+    fn map_keycode(
+        &self,
+        keycode: KeyCode,
+        modifiers: &pc_keyboard::Modifiers,
+        handle_control: HandleControl,
+    ) -> DecodedKey {
+        match self {
+            Layout::Us => layouts::Us104Key::map_keycode(keycode, modifiers, handle_control),
+            Layout::Uk => layouts::Uk105Key::map_keycode(keycode, modifiers, handle_control),
+            Layout::Dvorak => {
+                layouts::Dvorak104Key::map_keycode(keycode, modifiers, handle_control)
+            }
+            Layout::Azerty => layouts::Azerty::map_keycode(keycode, modifiers, handle_control),
+            Layout::German => layouts::De105Key::map_keycode(keycode, modifiers, handle_control),
+        }
+    }
+}
+
+/// The state of the modifier and lock keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    const fn new() -> Self {
+        Self {
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            alt: false,
+            alt_gr: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+        }
+    }
+
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// The LED bits of the PS/2 set LEDs command.
+    fn leds(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+struct KeyboardState {
+    layout: Layout,
+    handle_control: HandleControl,
+    modifiers: Modifiers,
+}
+
+impl KeyboardState {
+    const fn new() -> Self {
+        Self {
+            layout: Layout::Us,
+            handle_control: HandleControl::Ignore,
+            modifiers: Modifiers::new(),
+        }
+    }
+
+    /// Updates the modifiers for `event` and returns the key it produced, if any.
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+
+        match event.code {
+            KeyCode::ShiftLeft => modifiers.left_shift = down,
+            KeyCode::ShiftRight => modifiers.right_shift = down,
+            KeyCode::ControlLeft => modifiers.left_ctrl = down,
+            KeyCode::ControlRight => modifiers.right_ctrl = down,
+            KeyCode::AltLeft => modifiers.alt = down,
+            KeyCode::AltRight => modifiers.alt_gr = down,
+            // lock keys toggle on press, and repeat while held
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumpadLock if down => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            code if down => {
+                let layout_modifiers = pc_keyboard::Modifiers {
+                    lshift: modifiers.left_shift,
+                    rshift: modifiers.right_shift,
+                    lctrl: modifiers.left_ctrl,
+                    rctrl: modifiers.right_ctrl,
+                    numlock: modifiers.num_lock,
+                    capslock: modifiers.caps_lock,
+                    alt_gr: modifiers.alt_gr,
+                };
+                return Some(
+                    self.layout
+                        .map_keycode(code, &layout_modifiers, self.handle_control),
+                );
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+pub fn layout() -> Layout {
+    KEYBOARD.lock().layout
+}
+
+pub fn set_layout(layout: Layout) {
+    KEYBOARD.lock().layout = layout;
+    serial_println!("keyboard: switched to the {} layout", layout.name());
+}
+
+pub fn control_handling() -> HandleControl {
+    KEYBOARD.lock().handle_control
+}
+
+/// Sets whether Ctrl+letter produces the control characters U+0001 to U+001A, or the letter.
+pub fn set_control_handling(handle_control: HandleControl) {
+    KEYBOARD.lock().handle_control = handle_control;
+}
+
+pub fn modifiers() -> Modifiers {
+    KEYBOARD.lock().modifiers
+}
+
+/// Applies the current layout and modifiers to `event`, returning the key it produced.
+///
+/// Toggling a lock key updates the keyboard LEDs, so this must not be called from an
+/// interrupt handler.
+pub fn process_keyevent(event: KeyEvent) -> Option<DecodedKey> {
+    let (key, leds_before, leds_after) = {
+        let mut keyboard = KEYBOARD.lock();
+        let leds_before = keyboard.modifiers.leds();
+        let key = keyboard.process_keyevent(event);
+        (key, leds_before, keyboard.modifiers.leds())
+    };
+
+    if leds_before != leds_after {
+        if let Err(e) = set_leds(leds_after) {
+            serial_println!("keyboard: failed to update the LEDs: {:?}", e);
+        }
+    }
+
+    key
+}
+
+/// Sends the set LEDs command to the keyboard.
+fn set_leds(leds: u8) -> Result<(), Ps2Error> {
+    send_byte(KEYBOARD_COMMAND_SET_LEDS)?;
+    send_byte(leds)
+}
+
+/// Called by the keyboard interrupt handler with the responses it filters out of the
+/// scancode stream.
+pub(crate) fn response_received(response: u8) {
+    RESPONSE.store(response, Ordering::Release);
+}
+
+/// Sends a byte to the keyboard and waits for the acknowledgement, which arrives through
+/// the interrupt handler, so interrupts have to be enabled. Resends the byte if the
+/// keyboard asks to.
+fn send_byte(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..KEYBOARD_RETRIES {
+        RESPONSE.store(0, Ordering::Relaxed);
+        ps2::write_data(byte)?;
+
+        match wait_for_response()? {
+            KEYBOARD_RESPONSE_ACK => return Ok(()),
+            _ => continue,
+        }
+    }
+    Err(Ps2Error::UnexpectedResponse(KEYBOARD_RESPONSE_RESEND))
+}
+
+fn wait_for_response() -> Result<u8, Ps2Error> {
+    let start = Instant::now();
+    while start.elapsed() < KEYBOARD_RESPONSE_TIMEOUT {
+        match RESPONSE.swap(0, Ordering::Acquire) {
+            0 => core::hint::spin_loop(),
+            response => return Ok(response),
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+#[cfg(test)]
+fn press(state: &mut KeyboardState, code: KeyCode) -> Option<DecodedKey> {
+    let key = state.process_keyevent(KeyEvent::new(code, KeyState::Down));
+    assert_eq!(
+        state.process_keyevent(KeyEvent::new(code, KeyState::Up)),
+        None
+    );
+    key
+}
+
+#[test_case]
+fn layouts_can_be_switched() {
+    let mut state = KeyboardState::new();
+    assert_eq!(
+        press(&mut state, KeyCode::Q),
+        Some(DecodedKey::Unicode('q'))
+    );
+
+    state.layout = Layout::Azerty;
+    assert_eq!(
+        press(&mut state, KeyCode::Q),
+        Some(DecodedKey::Unicode('a'))
+    );
+    state.layout = Layout::German;
+    assert_eq!(
+        press(&mut state, KeyCode::Y),
+        Some(DecodedKey::Unicode('z'))
+    );
+    state.layout = Layout::Dvorak;
+    assert_eq!(
+        press(&mut state, KeyCode::S),
+        Some(DecodedKey::Unicode('o'))
+    );
+}
+
+#[test_case]
+fn modifiers_are_tracked() {
+    let mut state = KeyboardState::new();
+
+    state.process_keyevent(KeyEvent::new(KeyCode::ShiftLeft, KeyState::Down));
+    assert!(state.modifiers.shift());
+    assert_eq!(
+        press(&mut state, KeyCode::A),
+        Some(DecodedKey::Unicode('A'))
+    );
+    state.process_keyevent(KeyEvent::new(KeyCode::ShiftLeft, KeyState::Up));
+    assert!(!state.modifiers.shift());
+
+    press(&mut state, KeyCode::CapsLock);
+    assert!(state.modifiers.caps_lock);
+    assert_eq!(state.modifiers.leds(), 0b100);
+    assert_eq!(
+        press(&mut state, KeyCode::A),
+        Some(DecodedKey::Unicode('A'))
+    );
+    press(&mut state, KeyCode::CapsLock);
+    assert!(!state.modifiers.caps_lock);
+
+    state.handle_control = HandleControl::MapLettersToUnicode;
+    state.process_keyevent(KeyEvent::new(KeyCode::ControlRight, KeyState::Down));
+    assert!(state.modifiers.ctrl());
+    assert_eq!(
+        press(&mut state, KeyCode::C),
+        Some(DecodedKey::Unicode('\u{3}'))
+    );
+}
+
+#[test_case]
+fn leds_are_acknowledged() {
+    assert_eq!(set_leds(0), Ok(()));
+}
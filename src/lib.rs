@@ -1,97 +1,182 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
-#![feature(custom_test_frameworks, abi_x86_interrupt, asm)]
+#![feature(custom_test_frameworks, abi_x86_interrupt, asm, global_asm)]
+#![feature(alloc_error_handler, const_mut_refs, const_btree_new)]
 #![test_runner(crate::runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod bga;
+pub mod debug;
 pub mod gdt;
+pub mod graphics;
 pub mod interrupt;
+pub mod keyboard;
 pub mod memory;
+pub mod pci;
+pub mod power;
+pub mod ps2;
 pub mod serial;
+pub mod task;
 pub mod tests;
+pub mod time;
 pub mod vga;
-pub mod bga;
 
 use bootloader::{entry_point, BootInfo};
 pub use tests::runner::runner;
 
 pub fn start_kernel(boot_info: &'static BootInfo) -> ! {
-    use x86_64::registers::control::Cr3;
-
-    init();
-
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let l4_table = unsafe { active_level_4_page_table(phys_mem_offset) };
+    init(boot_info);
 
-    for (i, entry) in l4_table.iter().enumerate() {
-        if !entry.is_unused() {
-            serial_println!("L4 page table entry {}: {:?}", i, entry);
-        }
+    let mut bga_controller = bga::BgaController::init();
+    bga_controller.set_res(1024, 768, 0x20);
+    if !graphics::console::activate(bga_controller) {
+        serial_println!("No linear framebuffer, console output stays in the VGA text buffer");
     }
 
     println!("Hello World from Voluspa!");
+    show_splash();
 
-    let mut bga_controller = bga::BgaController::init();
-    bga_controller.set_res(1024, 768, 0x20);
-    bga_controller.write_gibberish();
-    //bga_controller.clear_screen(Pixel::new(255, 255, 255));
+    let mut executor = task::Executor::new();
+    executor.spawn(task::Task::new(task::keyboard::print_keypresses()));
+    executor.spawn(task::Task::new(task::mouse::track_pointer()));
+    executor.run()
+}
 
-    hlt_loop()
+/// Draws the boot splash over the framebuffer console, if it is active.
+fn show_splash() {
+    let splash = match graphics::bmp::Bmp::parse(graphics::bmp::BOOT_SPLASH) {
+        Ok(splash) => splash,
+        Err(e) => {
+            serial_println!("Invalid boot splash: {:?}", e);
+            return;
+        }
+    };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        graphics::console::with_console(|console| {
+            console.set_cursor_visible(false);
+            splash.draw_centered(console.canvas_mut());
+        });
+    });
 }
 
 #[cfg(test)]
 entry_point!(test_kernel_main);
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
     serial_println!("lib::_start");
-    init();
+    init(boot_info);
     test_main();
     hlt_loop()
 }
 
-pub fn init() {
+pub fn init(boot_info: &'static BootInfo) {
     println!("Voluspa is starting...");
 
     interrupt::init();
     gdt::init_gdt();
+    time::init();
+    memory::init(boot_info);
+    allocator::init_heap().expect("Heap initialization failed");
+    acpi::init();
+    if let Some(fadt) = acpi::fadt() {
+        time::set_century_register(fadt.century_register);
+    }
+    let topology = acpi::madt().map_or_else(interrupt::Topology::legacy, |m| m.apic_topology());
+    interrupt::init_apic(&topology);
+    if let Some(hpet) = acpi::hpet() {
+        time::hpet::init(&hpet);
+    }
+    pci::init();
+    ps2::init();
 
     println!("Voluspa startup sequence complete!");
 }
 
-use crate::memory::active_level_4_page_table;
-use core::panic::PanicInfo;
-use x86_64::VirtAddr;
 use crate::bga::Pixel;
+use core::fmt::Write;
+use core::panic::PanicInfo;
 
 pub fn vga_panic_handler(info: &PanicInfo) -> ! {
+    let (registers, backtrace) = debug::panic_state();
+    show_vga_panic(info, &registers, &backtrace);
+
+    hlt_loop()
+}
+
+/// Draws the panic screen into the VGA text buffer, directly so it stays there even while
+/// the framebuffer console is active.
+fn show_vga_panic(info: &PanicInfo, registers: &debug::Registers, backtrace: &debug::Backtrace) {
     use vga::{Color, ColorCode, VgaChar, WRITER};
 
+    let mut writer = WRITER.lock();
+
     // clear the screen with a blue background
     let clear_char = VgaChar::new(b' ', ColorCode::new(Color::White, Color::Blue));
-    WRITER.lock().fill_screen(clear_char);
+    writer.fill_screen(clear_char);
 
     let text_color = ColorCode::new(Color::Red, Color::Blue);
-    WRITER.lock().set_color(text_color);
-    WRITER
-        .lock()
-        .write_row(15, "                                     PANIC");
+    writer.set_color(text_color);
+    writer.write_row(15, "                                     PANIC");
 
     let normal_text_color = ColorCode::new(Color::White, Color::Blue);
-    WRITER.lock().set_color(normal_text_color);
+    writer.set_color(normal_text_color);
+
+    let _ = writeln!(writer, "{}", info);
+    let _ = writeln!(writer, "{}", registers);
+    let _ = writeln!(writer, "{}", backtrace);
+}
 
-    println!("{}", info);
+/// Reports the panic on serial and on the screen, the framebuffer console once it is
+/// active and the VGA text buffer before.
+pub fn screen_panic_handler(info: &PanicInfo) -> ! {
+    // whatever the panic interrupted never runs again, interrupt handlers included
+    x86_64::instructions::interrupts::disable();
+
+    let (registers, backtrace) = debug::panic_state();
+    show_serial_panic(info, &registers, &backtrace);
+
+    let shown = unsafe {
+        graphics::console::with_console_on_panic(|console| {
+            console.set_pointer(None);
+            console.set_cursor_visible(false);
+            console.set_color(vga::Color::White, vga::Color::Blue);
+            console.clear();
+
+            console.set_color(vga::Color::Red, vga::Color::Blue);
+            console.write_string("PANIC\n\n");
+            console.set_color(vga::Color::White, vga::Color::Blue);
+            let _ = writeln!(console, "{}", info);
+            let _ = writeln!(console, "{}", registers);
+            let _ = writeln!(console, "{}", backtrace);
+        })
+    };
+    if shown.is_none() {
+        show_vga_panic(info, &registers, &backtrace);
+    }
 
     hlt_loop()
 }
 
 pub fn serial_panic_handler(info: &PanicInfo) -> ! {
-    serial_println!("\n\n\n-- VOLUSPA KERNEL PANIC --");
-    serial_println!("{}", info);
+    let (registers, backtrace) = debug::panic_state();
+    show_serial_panic(info, &registers, &backtrace);
 
     hlt_loop();
 }
 
+fn show_serial_panic(info: &PanicInfo, registers: &debug::Registers, backtrace: &debug::Backtrace) {
+    serial_println!("\n\n\n-- VOLUSPA KERNEL PANIC --");
+    serial_println!("{}", info);
+    serial_println!("{}", registers);
+    serial_println!("{}", backtrace);
+}
+
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[FAILED]\n");
     serial_println!("Error: {}", info);
@@ -99,6 +184,11 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop()
 }
 
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    panic!("Allocation error: {:?}", layout)
+}
+
 pub fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
@@ -19,8 +19,8 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 }
 
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
-    voluspa_kernel::init();
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    voluspa_kernel::init(boot_info);
     test_main();
     voluspa_kernel::hlt_loop()
 }
@@ -28,7 +28,7 @@ fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    voluspa_kernel::serial_panic_handler(info);
+    voluspa_kernel::screen_panic_handler(info)
 }
 
 #[cfg(test)]
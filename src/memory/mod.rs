@@ -0,0 +1,689 @@
+mod region;
+
+pub use region::{
+    add_region, find_region, handle_page_fault, remove_region, PageFaultError, Region, RegionError,
+    RegionKind,
+};
+
+use crate::{print, println, serial_println};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use bootloader::BootInfo;
+use core::slice;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{
+    instructions::{interrupts, tlb},
+    registers::{control::Cr3, model_specific::Msr},
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, TranslateResult, UnmapError},
+        page::PageRangeInclusive,
+        page_table::FrameError,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = 64;
+
+const PAT_MSR: u32 = 0x277;
+/// The reset value of the page attribute table, with entry 1 changed from
+/// write-through to write-combining
+const PAT_VALUE: u64 = 0x0007_0406_0007_0106;
+
+/// Selects write-combining caching for a page, as programmed by [init_pat].
+pub const WRITE_COMBINING: PageTableFlags = PageTableFlags::WRITE_THROUGH;
+
+/// The global frame allocator, available after [init] has been called.
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// The active page table, only accessed through the functions of this module.
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub fn init(boot_info: &'static BootInfo) {
+    print!("Initializing frame allocator...   ");
+
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+
+    let allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset) };
+    let stats = allocator.stats();
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+
+    println!("[Ok]");
+    print!("Initializing page mapper...   ");
+
+    init_pat();
+
+    *MAPPER.lock() = Some(unsafe {
+        OffsetPageTable::new(
+            active_level_4_page_table(physical_memory_offset),
+            physical_memory_offset,
+        )
+    });
+
+    region::register_stack_guard();
+
+    println!("[Ok]");
+    serial_println!(
+        "Physical frames: {} total, {} usable, {} free, {} reserved",
+        stats.total,
+        stats.usable,
+        stats.free,
+        stats.reserved
+    );
+}
+
+/// Allocates a frame from the global frame allocator.
+///
+/// Returns `None` if the allocator is not initialized yet or if physical memory is exhausted.
+pub fn allocate_frame() -> Option<PhysFrame> {
+    FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()
+}
+
+/// Returns a frame to the global frame allocator.
+///
+/// This function is unsafe because the caller must guarantee that the frame is no longer
+/// mapped or otherwise in use.
+pub unsafe fn deallocate_frame(frame: PhysFrame) {
+    if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        allocator.deallocate_frame(frame);
+    }
+}
+
+/// Returns the statistics of the global frame allocator, or `None` if it is not initialized.
+pub fn frame_stats() -> Option<FrameStats> {
+    FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .map(|allocator| allocator.stats())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Frames described by the bootloader memory map
+    pub total: usize,
+    /// Frames that were usable when the kernel started
+    pub usable: usize,
+    /// Usable frames that are currently not allocated
+    pub free: usize,
+    /// Frames that are in use by the firmware, the bootloader or the kernel image
+    pub reserved: usize,
+}
+
+/// A frame allocator that keeps one bit per physical frame, a set bit meaning
+/// the frame is either allocated or not usable at all.
+///
+/// The bitmap itself lives in the first usable region that is large enough to hold it,
+/// and is accessed through the complete physical memory mapping of the bootloader.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize,
+    next_free: usize,
+    total: usize,
+    usable: usize,
+    free: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Creates the allocator from the memory map passed by the bootloader.
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed memory map is
+    /// valid, that the complete physical memory is mapped at `physical_memory_offset`, and that
+    /// it is only called once, since the usable frames are handed out without any bookkeeping
+    /// outside of this allocator.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let max_address = memory_map
+            .iter()
+            .map(|region| region.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_address / FRAME_SIZE) as usize;
+        let word_count = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_frames = ((word_count * 8) as u64 + FRAME_SIZE - 1) / FRAME_SIZE;
+
+        let bitmap_start = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .find(|region| {
+                region.range.end_frame_number - region.range.start_frame_number >= bitmap_frames
+            })
+            .map(|region| region.range.start_addr())
+            .expect("No usable memory region is large enough for the frame bitmap");
+
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, word_count);
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = Self {
+            bitmap,
+            frame_count,
+            next_free: 0,
+            total: 0,
+            usable: 0,
+            free: 0,
+        };
+
+        for region in memory_map.iter() {
+            let frames = region.range.start_frame_number..region.range.end_frame_number;
+            allocator.total += frames.clone().count();
+
+            if region.region_type == MemoryRegionType::Usable {
+                for frame in frames {
+                    allocator.clear_bit(frame as usize);
+                    allocator.usable += 1;
+                    allocator.free += 1;
+                }
+            }
+        }
+
+        // the bitmap occupies usable frames as well, those must never be handed out
+        let bitmap_start_frame = (bitmap_start / FRAME_SIZE) as usize;
+        for frame in bitmap_start_frame..bitmap_start_frame + bitmap_frames as usize {
+            allocator.set_bit(frame);
+            allocator.usable -= 1;
+            allocator.free -= 1;
+        }
+
+        allocator
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            usable: self.usable,
+            free: self.free,
+            reserved: self.total - self.usable,
+        }
+    }
+
+    /// Returns `true` if the given frame is usable and currently not allocated.
+    pub fn is_free(&self, frame: PhysFrame) -> bool {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        index < self.frame_count && !self.bit(index)
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+    }
+
+    fn clear_bit(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+    }
+
+    fn find_free(&self, start: usize) -> Option<usize> {
+        let start_word = start / BITS_PER_WORD;
+        let words = self.bitmap.len();
+
+        // wrap around once, so frames freed before the hint are found as well
+        (0..words)
+            .map(|i| (start_word + i) % words)
+            .find(|&word| self.bitmap[word] != u64::MAX)
+            .map(|word| word * BITS_PER_WORD + (!self.bitmap[word]).trailing_zeros() as usize)
+            .filter(|&index| index < self.frame_count)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let index = self.find_free(self.next_free)?;
+        self.set_bit(index);
+        self.free -= 1;
+        self.next_free = index + 1;
+
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            index as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(
+            index < self.frame_count,
+            "Deallocating frame outside of physical memory: {:?}",
+            frame
+        );
+        assert!(self.bit(index), "Double free of physical frame {:?}", frame);
+
+        self.clear_bit(index);
+        self.free += 1;
+        if index < self.next_free {
+            self.next_free = index;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// [init] has not been called yet
+    NotInitialized,
+    /// No physical frame was available to back the page or one of its page tables
+    OutOfFrames,
+    /// The page is already mapped to the contained frame
+    PageAlreadyMapped(PhysFrame),
+    /// The page is not mapped
+    PageNotMapped,
+    /// A parent page table entry maps a huge page, so the 4 KiB page cannot be (un)mapped
+    ParentEntryHugePage,
+    /// The page table entry points to a frame outside of physical memory
+    InvalidFrameAddress(PhysAddr),
+    /// The page table or the frame allocator is locked, e.g. by the code a fault interrupted
+    Locked,
+}
+
+impl From<MapToError<Size4KiB>> for MemoryError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        match error {
+            MapToError::FrameAllocationFailed => Self::OutOfFrames,
+            MapToError::ParentEntryHugePage => Self::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(frame) => Self::PageAlreadyMapped(frame),
+        }
+    }
+}
+
+impl From<UnmapError> for MemoryError {
+    fn from(error: UnmapError) -> Self {
+        match error {
+            UnmapError::PageNotMapped => Self::PageNotMapped,
+            UnmapError::ParentEntryHugePage => Self::ParentEntryHugePage,
+            UnmapError::InvalidFrameAddress(address) => Self::InvalidFrameAddress(address),
+        }
+    }
+}
+
+impl From<FlagUpdateError> for MemoryError {
+    fn from(error: FlagUpdateError) -> Self {
+        match error {
+            FlagUpdateError::PageNotMapped => Self::PageNotMapped,
+            FlagUpdateError::ParentEntryHugePage => Self::ParentEntryHugePage,
+        }
+    }
+}
+
+/// Hands out frames for new page tables from the global [FRAME_ALLOCATOR].
+struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        allocate_frame()
+    }
+}
+
+/// Runs `f` with the active page table, with interrupts disabled so an interrupt handler
+/// can never deadlock on the mapper.
+fn with_mapper<T>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>) -> Result<T, MemoryError>,
+) -> Result<T, MemoryError> {
+    interrupts::without_interrupts(|| match MAPPER.lock().as_mut() {
+        Some(mapper) => f(mapper),
+        None => Err(MemoryError::NotInitialized),
+    })
+}
+
+/// Returns the virtual address at which the bootloader mapped the complete physical memory.
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Translates a virtual address of the active page table to the physical address it maps to,
+/// including addresses inside 2 MiB and 1 GiB pages.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    with_mapper(|mapper| Ok(mapper.translate_addr(addr)))
+        .ok()
+        .flatten()
+}
+
+/// Returns the flags of the lowest level page table entry mapping `addr`, or `None` if
+/// the address is not mapped.
+pub fn page_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    with_mapper(|mapper| match mapper.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Ok(Some(flags)),
+        _ => Ok(None),
+    })
+    .ok()
+    .flatten()
+}
+
+/// Maps `page` to `frame` with the given flags.
+///
+/// This function is unsafe because the caller must guarantee that the frame is not in use
+/// elsewhere, since mapping it twice creates aliasing mutable memory.
+pub unsafe fn map_page(
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), MemoryError> {
+    with_mapper(|mapper| {
+        mapper
+            .map_to(page, frame, flags, &mut GlobalFrameAllocator)?
+            .flush();
+        Ok(())
+    })
+}
+
+/// Maps `page` to a freshly allocated, zeroed frame without waiting for the page table or
+/// the frame allocator, for the page fault handler which may have interrupted their owner.
+///
+/// Returns [MemoryError::Locked] instead of deadlocking if either of them is locked.
+pub fn try_map_zeroed_page(page: Page, flags: PageTableFlags) -> Result<(), MemoryError> {
+    let mut mapper_guard = MAPPER.try_lock().ok_or(MemoryError::Locked)?;
+    let mapper = mapper_guard.as_mut().ok_or(MemoryError::NotInitialized)?;
+    let mut allocator_guard = FRAME_ALLOCATOR.try_lock().ok_or(MemoryError::Locked)?;
+    let allocator = allocator_guard
+        .as_mut()
+        .ok_or(MemoryError::NotInitialized)?;
+
+    let frame = allocator.allocate_frame().ok_or(MemoryError::OutOfFrames)?;
+    unsafe {
+        let frame_address = physical_memory_offset() + frame.start_address().as_u64();
+        frame_address
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, FRAME_SIZE as usize);
+
+        match mapper.map_to(page, frame, flags, allocator) {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(e) => {
+                allocator.deallocate_frame(frame);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Maps every page in `pages` to a freshly allocated frame.
+///
+/// If the frame allocator runs out of memory, the pages mapped so far are unmapped again
+/// and their frames are returned to the allocator.
+pub fn map_range(pages: PageRangeInclusive, flags: PageTableFlags) -> Result<(), MemoryError> {
+    for page in pages {
+        let result = allocate_frame()
+            .ok_or(MemoryError::OutOfFrames)
+            .and_then(|frame| unsafe {
+                map_page(page, frame, flags).map_err(|error| {
+                    deallocate_frame(frame);
+                    error
+                })
+            });
+
+        if let Err(error) = result {
+            if page != pages.start {
+                // roll back, so a failed mapping does not leak frames
+                let _ = unmap_range(Page::range_inclusive(pages.start, page - 1));
+            }
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps the physical range starting at `start` to the pages in `pages`, one frame per page.
+///
+/// This function is unsafe because the caller must guarantee that the physical range is not
+/// handed out by the frame allocator, e.g. because it is memory mapped I/O.
+pub unsafe fn map_physical_range(
+    start: PhysFrame,
+    pages: PageRangeInclusive,
+    flags: PageTableFlags,
+) -> Result<(), MemoryError> {
+    for (i, page) in pages.enumerate() {
+        map_page(page, start + i as u64, flags)?;
+    }
+
+    Ok(())
+}
+
+/// Unmaps `page` and returns the frame it was mapped to, without deallocating it.
+pub fn unmap_page(page: Page) -> Result<PhysFrame, MemoryError> {
+    with_mapper(|mapper| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        Ok(frame)
+    })
+}
+
+/// Unmaps every page in `pages` and returns their frames to the frame allocator.
+///
+/// Pages that are not mapped are skipped.
+pub fn unmap_range(pages: PageRangeInclusive) -> Result<(), MemoryError> {
+    for page in pages {
+        match unmap_page(page) {
+            Ok(frame) => unsafe { deallocate_frame(frame) },
+            Err(MemoryError::PageNotMapped) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Points the already mapped `page` to `frame`, keeping its flags, and returns the frame
+/// it was mapped to before.
+///
+/// This function is unsafe for the same reason as [map_page].
+pub unsafe fn remap_page(page: Page, frame: PhysFrame) -> Result<PhysFrame, MemoryError> {
+    with_mapper(|mapper| {
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            TranslateResult::NotMapped => return Err(MemoryError::PageNotMapped),
+            TranslateResult::InvalidFrameAddress(address) => {
+                return Err(MemoryError::InvalidFrameAddress(address))
+            }
+        };
+
+        let (old_frame, flush) = mapper.unmap(page)?;
+        flush.ignore();
+        mapper
+            .map_to(page, frame, flags, &mut GlobalFrameAllocator)?
+            .flush();
+        Ok(old_frame)
+    })
+}
+
+/// Replaces the flags of every page in `pages`, e.g. to make a range read-only.
+pub fn protect_range(pages: PageRangeInclusive, flags: PageTableFlags) -> Result<(), MemoryError> {
+    with_mapper(|mapper| {
+        for page in pages {
+            unsafe { mapper.update_flags(page, flags)? }.flush();
+        }
+        Ok(())
+    })
+}
+
+/// Reprograms the page attribute table so that [WRITE_COMBINING] pages use
+/// write-combining instead of write-through caching.
+fn init_pat() {
+    unsafe {
+        Msr::new(PAT_MSR).write(PAT_VALUE);
+        asm!("wbinvd", options(nostack, preserves_flags));
+    }
+    tlb::flush_all();
+}
+
+unsafe fn active_level_4_page_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+/// Translates the given virtual address to the mapped physical address, or
+/// `None` if the address is not mapped.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    translate_addr_inner(addr, physical_memory_offset)
+}
+
+fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame = level_4_table_frame;
+
+    for (level, &index) in table_indexes.iter().enumerate() {
+        let virt = physical_memory_offset + frame.start_address().as_u64();
+        let table_ptr: *const PageTable = virt.as_ptr();
+        let table = unsafe { &*table_ptr };
+
+        let entry = &table[index];
+        frame = match entry.frame() {
+            Ok(frame) => frame,
+            Err(FrameError::FrameNotPresent) => return None,
+            Err(FrameError::HugeFrame) => {
+                // a huge page in the level 3 table maps 1 GiB, in the level 2 table 2 MiB
+                let offset_mask = match level {
+                    1 => Size1GiB::SIZE - 1,
+                    2 => Size2MiB::SIZE - 1,
+                    _ => return None,
+                };
+                return Some(entry.addr() + (addr.as_u64() & offset_mask));
+            }
+        };
+    }
+
+    Some(frame.start_address() + u64::from(addr.page_offset()))
+}
+
+#[test_case]
+fn frame_allocation_and_reuse() {
+    let before = frame_stats().unwrap();
+
+    let frame = allocate_frame().expect("out of physical frames");
+    assert_eq!(frame_stats().unwrap().free, before.free - 1);
+
+    unsafe { deallocate_frame(frame) };
+    assert_eq!(frame_stats().unwrap(), before);
+
+    // the freed frame is the lowest free one again, so it is handed out next
+    let again = allocate_frame().unwrap();
+    assert_eq!(frame, again);
+    unsafe { deallocate_frame(again) };
+}
+
+#[test_case]
+fn allocated_frames_are_distinct() {
+    let mut frames = [None; 16];
+    for slot in frames.iter_mut() {
+        *slot = allocate_frame();
+    }
+
+    for (i, a) in frames.iter().enumerate() {
+        assert!(a.is_some());
+        for b in frames.iter().skip(i + 1) {
+            assert_ne!(a, b);
+        }
+    }
+
+    for frame in frames.iter().flatten() {
+        unsafe { deallocate_frame(*frame) };
+    }
+}
+
+#[test_case]
+fn translate_physical_memory_mapping() {
+    let offset = physical_memory_offset();
+    let vga_buffer = PhysAddr::new(0xb8000);
+
+    assert_eq!(translate(offset + vga_buffer.as_u64()), Some(vga_buffer));
+    assert_eq!(
+        unsafe { translate_addr(offset + vga_buffer.as_u64(), offset) },
+        Some(vga_buffer)
+    );
+}
+
+#[test_case]
+fn translate_matches_mapper() {
+    let offset = physical_memory_offset();
+    let kernel_code = VirtAddr::new(init as usize as u64);
+    let stack = VirtAddr::from_ptr(&offset);
+
+    for &addr in &[kernel_code, stack, offset + 0x1234u64, VirtAddr::new(0)] {
+        assert_eq!(translate(addr), unsafe { translate_addr(addr, offset) });
+    }
+}
+
+#[test_case]
+fn map_protect_and_unmap_range() {
+    let start = Page::containing_address(VirtAddr::new(0x_7777_0000_0000));
+    let pages = Page::range_inclusive(start, start + 3);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    map_range(pages, flags).unwrap();
+    let frame = PhysFrame::containing_address(translate(start.start_address()).unwrap());
+    assert_eq!(
+        unsafe { map_page(start, frame, flags) },
+        Err(MemoryError::PageAlreadyMapped(frame))
+    );
+
+    let ptr: *mut u64 = (start + 2).start_address().as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(0xdead_beef);
+        assert_eq!(ptr.read_volatile(), 0xdead_beef);
+    }
+
+    protect_range(pages, PageTableFlags::PRESENT).unwrap();
+    assert!(!page_flags(start.start_address())
+        .unwrap()
+        .contains(PageTableFlags::WRITABLE));
+
+    let free_before = frame_stats().unwrap().free;
+    unmap_range(pages).unwrap();
+    assert_eq!(frame_stats().unwrap().free, free_before + 4);
+    assert_eq!(translate(start.start_address()), None);
+    assert_eq!(unmap_page(start), Err(MemoryError::PageNotMapped));
+}
+
+#[test_case]
+fn remap_page_to_other_frame() {
+    let page = Page::containing_address(VirtAddr::new(0x_7777_1000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    map_range(Page::range_inclusive(page, page), flags).unwrap();
+
+    let frame = allocate_frame().unwrap();
+    let old_frame = unsafe { remap_page(page, frame).unwrap() };
+    assert_eq!(translate(page.start_address()), Some(frame.start_address()));
+    assert_eq!(page_flags(page.start_address()), Some(flags));
+
+    unsafe { deallocate_frame(old_frame) };
+    unmap_range(Page::range_inclusive(page, page)).unwrap();
+}
+
+#[test_case]
+fn try_map_zeroed_page_does_not_wait_for_locks() {
+    let page = Page::containing_address(VirtAddr::new(0x_7777_2000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let locked = interrupts::without_interrupts(|| {
+        let _allocator = FRAME_ALLOCATOR.lock();
+        try_map_zeroed_page(page, flags)
+    });
+    assert_eq!(locked, Err(MemoryError::Locked));
+    assert_eq!(translate(page.start_address()), None);
+
+    try_map_zeroed_page(page, flags).unwrap();
+    let ptr: *const u64 = page.start_address().as_ptr();
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+    unmap_range(Page::range_inclusive(page, page)).unwrap();
+}
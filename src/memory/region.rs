@@ -0,0 +1,314 @@
+use super::{translate, try_map_zeroed_page, unmap_range, MemoryError, FRAME_SIZE};
+use crate::serial_println;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// The most regions the table holds, it is a fixed array so the page fault handler never
+/// depends on the heap
+const MAX_REGIONS: usize = 64;
+/// How far below the current stack pointer [register_stack_guard] looks for the guard page
+const MAX_STACK_PAGES: u64 = 1024;
+
+/// The regions of the address space the page fault handler knows about. Only locked with
+/// interrupts disabled, and only tried by the page fault handler.
+static REGIONS: Mutex<[Option<Region>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Backed by a zeroed frame on the first access to each page
+    DemandZero,
+    /// Never mapped, an access means the stack above it overflowed
+    Guard,
+}
+
+/// A page aligned range of virtual addresses with a fault policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// The first address after the region
+    pub end: VirtAddr,
+    pub kind: RegionKind,
+    /// The flags pages of demand-zero regions are mapped with
+    pub flags: PageTableFlags,
+}
+
+impl Region {
+    pub fn new(
+        name: &'static str,
+        start: VirtAddr,
+        pages: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+    ) -> Self {
+        Self {
+            name,
+            start,
+            end: start + pages * FRAME_SIZE,
+            kind,
+            flags: flags | PageTableFlags::PRESENT,
+        }
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    fn overlaps(&self, other: &Region) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:#x}-{:#x} ({:?})",
+            self.name,
+            self.start.as_u64(),
+            self.end.as_u64(),
+            self.kind
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The region is empty or does not start and end on page boundaries
+    Unaligned,
+    Overlaps(Region),
+    TableFull,
+}
+
+/// Why a page fault could not be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// No region covers the address
+    NoRegion,
+    /// The address is in the guard page of a stack
+    StackOverflow(Region),
+    /// The access is not allowed by the flags of the region
+    AccessViolation(Region),
+    /// Backing the page failed, e.g. because physical memory is exhausted
+    MappingFailed(Region, MemoryError),
+    /// The fault happened while the region table, the page table or the frame allocator
+    /// was locked
+    TableLocked,
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoRegion => write!(f, "Region: none, invalid access"),
+            Self::StackOverflow(region) => write!(f, "Region: {}, stack overflow", region),
+            Self::AccessViolation(region) => write!(f, "Region: {}, access violation", region),
+            Self::MappingFailed(region, e) => {
+                write!(f, "Region: {}, backing failed: {:?}", region, e)
+            }
+            Self::TableLocked => write!(f, "Region: unknown, the memory tables are locked"),
+        }
+    }
+}
+
+/// Adds `region` to the table consulted by the page fault handler.
+pub fn add_region(region: Region) -> Result<(), RegionError> {
+    let aligned = |address: VirtAddr| address.is_aligned(FRAME_SIZE);
+    if region.start >= region.end || !aligned(region.start) || !aligned(region.end) {
+        return Err(RegionError::Unaligned);
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        if let Some(other) = regions
+            .iter()
+            .flatten()
+            .find(|other| other.overlaps(&region))
+        {
+            return Err(RegionError::Overlaps(*other));
+        }
+        let slot = regions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegionError::TableFull)?;
+        *slot = Some(region);
+        Ok(())
+    })
+}
+
+/// Removes the region starting at `start` and returns the frames backing its pages to the
+/// frame allocator.
+pub fn remove_region(start: VirtAddr) -> Option<Region> {
+    let region = interrupts::without_interrupts(|| {
+        REGIONS
+            .lock()
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(region) if region.start == start))?
+            .take()
+    })?;
+
+    if region.kind == RegionKind::DemandZero {
+        let pages = Page::range_inclusive(
+            Page::containing_address(region.start),
+            Page::containing_address(region.end - 1u64),
+        );
+        if let Err(e) = unmap_range(pages) {
+            serial_println!("Memory: unmapping {} failed: {:?}", region, e);
+        }
+    }
+    Some(region)
+}
+
+/// Returns the region containing `address`, or `None` if there is none or the table is
+/// locked, so this is safe to call from exception handlers.
+pub fn find_region(address: VirtAddr) -> Option<Region> {
+    interrupts::without_interrupts(|| {
+        REGIONS
+            .try_lock()?
+            .iter()
+            .flatten()
+            .find(|region| region.contains(address))
+            .copied()
+    })
+}
+
+/// Resolves a page fault at `address`, by backing demand-zero pages with a fresh frame.
+/// Returns why the fault can't be resolved otherwise.
+pub fn handle_page_fault(
+    address: VirtAddr,
+    error_code: PageFaultErrorCode,
+) -> Result<(), PageFaultError> {
+    let region = {
+        // the faulting code may hold the lock, waiting for it would never end
+        let regions = REGIONS.try_lock().ok_or(PageFaultError::TableLocked)?;
+        regions
+            .iter()
+            .flatten()
+            .find(|region| region.contains(address))
+            .copied()
+            .ok_or(PageFaultError::NoRegion)?
+    };
+
+    if region.kind == RegionKind::Guard {
+        return Err(PageFaultError::StackOverflow(region));
+    }
+
+    let flags = region.flags;
+    let violation = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        || (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+            && !flags.contains(PageTableFlags::WRITABLE))
+        || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            && flags.contains(PageTableFlags::NO_EXECUTE))
+        || (error_code.contains(PageFaultErrorCode::USER_MODE)
+            && !flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    if violation {
+        return Err(PageFaultError::AccessViolation(region));
+    }
+
+    match try_map_zeroed_page(Page::containing_address(address), flags) {
+        Ok(()) => Ok(()),
+        // the page table says not present but the mapper disagrees, the TLB was stale
+        Err(MemoryError::PageAlreadyMapped(_)) => {
+            x86_64::instructions::tlb::flush(address);
+            Ok(())
+        }
+        // the faulting code may hold the page table or the frame allocator
+        Err(MemoryError::Locked) => Err(PageFaultError::TableLocked),
+        Err(e) => Err(PageFaultError::MappingFailed(region, e)),
+    }
+}
+
+/// Registers the guard page the bootloader leaves below the kernel stack, the first
+/// unmapped page below the current stack pointer.
+pub(super) fn register_stack_guard() {
+    let stack_pointer: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack)) };
+
+    let top = Page::<Size4KiB>::containing_address(VirtAddr::new(stack_pointer));
+    let guard = (1..MAX_STACK_PAGES)
+        .map(|i| top - i)
+        .find(|page| translate(page.start_address()).is_none());
+
+    match guard {
+        Some(page) => {
+            let region = Region::new(
+                "kernel stack guard",
+                page.start_address(),
+                1,
+                RegionKind::Guard,
+                PageTableFlags::empty(),
+            );
+            if let Err(e) = add_region(region) {
+                serial_println!("Memory: registering {} failed: {:?}", region, e);
+            }
+        }
+        None => serial_println!("Memory: no guard page below the kernel stack"),
+    }
+}
+
+#[test_case]
+fn demand_zero_pages_are_backed_on_access() {
+    let start = VirtAddr::new(0x_7777_2000_0000);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    add_region(Region::new("test", start, 4, RegionKind::DemandZero, flags)).unwrap();
+    assert_eq!(translate(start + 2 * FRAME_SIZE), None);
+
+    let ptr: *mut u64 = (start + 2 * FRAME_SIZE + 8u64).as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(0xdead_beef);
+        assert_eq!(ptr.read_volatile(), 0xdead_beef);
+    }
+    assert!(translate(start + 2 * FRAME_SIZE).is_some());
+    // only the touched page is backed
+    assert_eq!(translate(start), None);
+
+    let free_before = super::frame_stats().unwrap().free;
+    assert_eq!(remove_region(start).map(|region| region.start), Some(start));
+    assert_eq!(super::frame_stats().unwrap().free, free_before + 1);
+    assert_eq!(translate(start + 2 * FRAME_SIZE), None);
+    assert_eq!(find_region(start), None);
+}
+
+#[test_case]
+fn invalid_accesses_are_refused() {
+    let guard_start = VirtAddr::new(0x_7777_3000_0000);
+    let guard = Region::new(
+        "guard",
+        guard_start,
+        1,
+        RegionKind::Guard,
+        PageTableFlags::empty(),
+    );
+    let read_only_start = guard_start + FRAME_SIZE;
+    let read_only = Region::new(
+        "read-only",
+        read_only_start,
+        1,
+        RegionKind::DemandZero,
+        PageTableFlags::empty(),
+    );
+    add_region(guard).unwrap();
+    add_region(read_only).unwrap();
+    assert_eq!(add_region(guard), Err(RegionError::Overlaps(guard)));
+
+    let write = PageFaultErrorCode::CAUSED_BY_WRITE;
+    assert_eq!(
+        handle_page_fault(guard_start + 8u64, write),
+        Err(PageFaultError::StackOverflow(guard))
+    );
+    assert_eq!(
+        handle_page_fault(read_only_start, write),
+        Err(PageFaultError::AccessViolation(read_only))
+    );
+    assert_eq!(
+        handle_page_fault(read_only_start + FRAME_SIZE, write),
+        Err(PageFaultError::NoRegion)
+    );
+
+    remove_region(guard_start).unwrap();
+    remove_region(read_only_start).unwrap();
+}
@@ -0,0 +1,85 @@
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// Serializes access to the address/data port pair, a config access is two separate port writes.
+static CONFIG_PORTS: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(PCI_CONFIG_ADDRESS), Port::new(PCI_CONFIG_DATA)));
+
+/// The location of a function on the PCI bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn config_address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset as u32 & 0xFC)
+    }
+
+    /// Reads the dword at `offset` (rounded down to a multiple of 4) of the configuration space.
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let address = self.config_address(offset);
+        interrupts::without_interrupts(|| {
+            let mut ports = CONFIG_PORTS.lock();
+            unsafe {
+                ports.0.write(address);
+                ports.1.read()
+            }
+        })
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Writes the dword at `offset` (rounded down to a multiple of 4) of the configuration space.
+    ///
+    /// This function is unsafe because configuration writes can change where a device
+    /// decodes memory and I/O accesses.
+    pub unsafe fn write_u32(&self, offset: u8, value: u32) {
+        let address = self.config_address(offset);
+        interrupts::without_interrupts(|| {
+            let mut ports = CONFIG_PORTS.lock();
+            ports.0.write(address);
+            ports.1.write(value);
+        })
+    }
+
+    /// Writes the word at `offset` (rounded down to a multiple of 2), keeping the other half
+    /// of the dword intact.
+    ///
+    /// This function is unsafe for the same reason as [write_u32](Self::write_u32).
+    pub unsafe fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xFFFF << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
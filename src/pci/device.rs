@@ -0,0 +1,252 @@
+use super::config::PciAddress;
+use alloc::vec::Vec;
+use core::fmt;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderType {
+    /// A regular device
+    General,
+    /// A bridge to another PCI bus
+    PciToPci {
+        secondary_bus: u8,
+    },
+    PciToCardBus,
+    Unknown(u8),
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        is_64bit: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl Bar {
+    pub fn is_memory(&self) -> bool {
+        matches!(self, Bar::Memory { .. })
+    }
+}
+
+/// An entry of the capabilities list, e.g. MSI (0x05) or MSI-X (0x11).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// The offset of the capability structure in the configuration space
+    pub offset: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: HeaderType,
+    pub multifunction: bool,
+    pub bars: [Option<Bar>; 6],
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+    pub capabilities: Vec<Capability>,
+}
+
+impl PciDevice {
+    /// Reads and decodes the configuration space of the function at `address`,
+    /// or returns `None` if there is no function.
+    pub fn probe(address: PciAddress) -> Option<Self> {
+        let id = address.read_u32(0x00);
+        let vendor_id = id as u16;
+        if vendor_id == 0xFFFF {
+            return None;
+        }
+
+        let class_reg = address.read_u32(0x08);
+        let header = address.read_u8(0x0E);
+        let header_type = match header & 0x7F {
+            0x00 => HeaderType::General,
+            0x01 => HeaderType::PciToPci {
+                secondary_bus: address.read_u8(0x19),
+            },
+            0x02 => HeaderType::PciToCardBus,
+            other => HeaderType::Unknown(other),
+        };
+
+        let bar_count = match header_type {
+            HeaderType::General => 6,
+            HeaderType::PciToPci { .. } => 2,
+            _ => 0,
+        };
+
+        let mut device = Self {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class_reg >> 24) as u8,
+            subclass: (class_reg >> 16) as u8,
+            prog_if: (class_reg >> 8) as u8,
+            revision: class_reg as u8,
+            header_type,
+            multifunction: header & 0x80 != 0,
+            bars: [None; 6],
+            interrupt_line: address.read_u8(0x3C),
+            interrupt_pin: address.read_u8(0x3D),
+            capabilities: Vec::new(),
+        };
+
+        let mut index = 0;
+        while index < bar_count {
+            let (bar, slots) = unsafe { decode_bar(address, index) };
+            device.bars[index] = bar;
+            index += slots;
+        }
+
+        if address.read_u16(0x06) & STATUS_CAPABILITIES_LIST != 0 {
+            device.capabilities = read_capabilities(address);
+        }
+
+        Some(device)
+    }
+
+    pub fn find_capability(&self, id: u8) -> Option<Capability> {
+        self.capabilities.iter().copied().find(|cap| cap.id == id)
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+impl fmt::Display for PciDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} {} ({:02x}:{:02x}:{:02x}) irq {}",
+            self.address,
+            self.vendor_id,
+            self.device_id,
+            self.class_name(),
+            self.class,
+            self.subclass,
+            self.prog_if,
+            self.interrupt_line
+        )
+    }
+}
+
+/// Decodes the base address register `index` and returns it with the number of
+/// register slots it occupies (2 for 64-bit memory BARs).
+///
+/// The size is determined by writing all ones and reading back the mask, during which
+/// decoding is disabled in the command register. This function is unsafe because the
+/// device must not be in use by a driver while its BARs are probed.
+unsafe fn decode_bar(address: PciAddress, index: usize) -> (Option<Bar>, usize) {
+    let offset = 0x10 + (index as u8) * 4;
+    let original = address.read_u32(offset);
+    let is_io = original & 0x1 != 0;
+    let is_64bit = !is_io && (original >> 1) & 0x3 == 0x2;
+    let slots = if is_64bit { 2 } else { 1 };
+
+    let command = address.read_u16(0x04);
+    address.write_u16(0x04, command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE));
+
+    address.write_u32(offset, 0xFFFF_FFFF);
+    let mask_low = address.read_u32(offset);
+    address.write_u32(offset, original);
+
+    let (original_high, mask_high) = if is_64bit {
+        let original_high = address.read_u32(offset + 4);
+        address.write_u32(offset + 4, 0xFFFF_FFFF);
+        let mask_high = address.read_u32(offset + 4);
+        address.write_u32(offset + 4, original_high);
+        (original_high, mask_high)
+    } else {
+        (0, 0)
+    };
+
+    address.write_u16(0x04, command);
+
+    if mask_low == 0 && mask_high == 0 {
+        // unimplemented BAR
+        return (None, slots);
+    }
+
+    let bar = if is_io {
+        let mask = mask_low & !0x3;
+        Bar::Io {
+            port: original & !0x3,
+            size: (!mask).wrapping_add(1) & 0xFFFF,
+        }
+    } else {
+        let mask = (mask_high as u64) << 32 | (mask_low & !0xF) as u64;
+        let mask = if is_64bit {
+            mask
+        } else {
+            mask | 0xFFFF_FFFF_0000_0000
+        };
+        Bar::Memory {
+            address: (original_high as u64) << 32 | (original & !0xF) as u64,
+            size: (!mask).wrapping_add(1),
+            prefetchable: original & 0x8 != 0,
+            is_64bit,
+        }
+    };
+
+    (Some(bar), slots)
+}
+
+fn read_capabilities(address: PciAddress) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    let mut offset = address.read_u8(0x34) & 0xFC;
+
+    // the list lives in the 256 byte header, so more than 48 entries means it loops
+    while offset != 0 && capabilities.len() < 48 {
+        let header = address.read_u16(offset);
+        capabilities.push(Capability {
+            id: header as u8,
+            offset,
+        });
+        offset = (header >> 8) as u8 & 0xFC;
+    }
+
+    capabilities
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI-to-PCI bridge",
+        (0x06, 0x80) => "Other bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus controller",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Unknown device",
+    }
+}
@@ -0,0 +1,142 @@
+mod config;
+mod device;
+
+pub use config::PciAddress;
+pub use device::{class_name, Bar, Capability, HeaderType, PciDevice};
+
+use crate::{print, println, serial_println};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// All functions found on the PCI bus, filled by [init].
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+pub fn init() {
+    print!("Scanning PCI bus...   ");
+
+    let mut devices = Vec::new();
+    scan(&mut devices);
+    devices.sort_by_key(|device| device.address);
+
+    for device in devices.iter() {
+        serial_println!("PCI: {}", device);
+    }
+
+    println!("[Ok]");
+    serial_println!("PCI: found {} functions", devices.len());
+    *DEVICES.lock() = devices;
+}
+
+/// Returns all functions found on the PCI bus.
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+/// Returns the first function with the given vendor and device ID.
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+        .cloned()
+}
+
+/// Returns all functions with the given class and subclass, e.g. `(0x01, 0x06)` for SATA controllers.
+pub fn find_by_class(class: u8, subclass: u8) -> Vec<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .filter(|device| device.class == class && device.subclass == subclass)
+        .cloned()
+        .collect()
+}
+
+/// Enumerates all buses reachable from the host bridges.
+fn scan(devices: &mut Vec<PciDevice>) {
+    let host_bridge = PciAddress::new(0, 0, 0);
+    let multiple_host_bridges = host_bridge.read_u8(0x0E) & 0x80 != 0;
+
+    if multiple_host_bridges {
+        // every function of the first device is a host bridge responsible for the bus of the same number
+        for function in 0..8 {
+            if PciAddress::new(0, 0, function).read_u16(0x00) != 0xFFFF {
+                scan_bus(function, devices);
+            }
+        }
+    } else {
+        scan_bus(0, devices);
+    }
+}
+
+fn scan_bus(bus: u8, devices: &mut Vec<PciDevice>) {
+    for device in 0..32 {
+        let device = match PciDevice::probe(PciAddress::new(bus, device, 0)) {
+            Some(device) => device,
+            None => continue,
+        };
+
+        let functions = if device.multifunction { 1..8 } else { 1..1 };
+        let address = device.address;
+        add_function(device, devices);
+
+        for function in functions {
+            if let Some(device) = PciDevice::probe(PciAddress::new(bus, address.device, function)) {
+                add_function(device, devices);
+            }
+        }
+    }
+}
+
+fn add_function(device: PciDevice, devices: &mut Vec<PciDevice>) {
+    let secondary_bus = match device.header_type {
+        HeaderType::PciToPci { secondary_bus } => Some(secondary_bus),
+        _ => None,
+    };
+    devices.push(device);
+
+    if let Some(bus) = secondary_bus {
+        // a misconfigured bridge must not send the scan in circles
+        if bus != 0 && !devices.iter().any(|device| device.address.bus == bus) {
+            scan_bus(bus, devices);
+        }
+    }
+}
+
+#[test_case]
+fn host_bridge_is_found() {
+    let host_bridges = find_by_class(0x06, 0x00);
+    assert!(!host_bridges.is_empty());
+    assert_eq!(host_bridges[0].address, PciAddress::new(0, 0, 0));
+    assert_eq!(host_bridges[0].header_type, HeaderType::General);
+}
+
+#[test_case]
+fn vga_device_has_framebuffer_bar() {
+    // QEMU's stdvga
+    let vga = find_by_id(0x1234, 0x1111).expect("no stdvga device");
+    assert_eq!((vga.class, vga.subclass), (0x03, 0x00));
+
+    match vga.bars[0] {
+        Some(Bar::Memory {
+            address,
+            size,
+            prefetchable,
+            ..
+        }) => {
+            assert_ne!(address, 0);
+            assert!(size >= 16 * 1024 * 1024);
+            assert_eq!(address % size, 0);
+            assert!(prefetchable);
+        }
+        other => panic!("unexpected BAR0: {:?}", other),
+    }
+}
+
+#[test_case]
+fn every_function_is_listed_once() {
+    let devices = devices();
+    assert!(!devices.is_empty());
+    for (i, a) in devices.iter().enumerate() {
+        assert!(devices.iter().skip(i + 1).all(|b| a.address != b.address));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::acpi::{self, AddressSpace, Fadt, GenericAddress};
+use crate::time::pit;
+use crate::{hlt_loop, memory, ps2, serial_println};
+use x86_64::instructions::{interrupts, port::Port, tables::lidt};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+/// The sleep state that turns the machine off
+const SOFT_OFF: u8 = 5;
+
+/// Set while the system is in ACPI mode instead of legacy mode
+const PM1_CONTROL_SCI_ENABLE: u16 = 1 << 0;
+const PM1_CONTROL_SLEEP_TYPE_SHIFT: u16 = 10;
+const PM1_CONTROL_SLEEP_TYPE_MASK: u16 = 0b111 << PM1_CONTROL_SLEEP_TYPE_SHIFT;
+const PM1_CONTROL_SLEEP_ENABLE: u16 = 1 << 13;
+
+/// The number of waits the firmware gets to switch to ACPI mode, about a second in total
+const ACPI_ENABLE_WAITS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    /// There is no FADT, or the ACPI tables were not found at all
+    NoAcpi,
+    /// Neither the DSDT nor an SSDT defines the sleep state
+    NoSleepState,
+    /// The firmware did not switch to ACPI mode
+    AcpiEnableTimeout,
+    /// The hardware was asked, but the machine is still running
+    Ignored,
+    /// A PM1 control register is neither in I/O nor in memory space, or its port is out of
+    /// range
+    UnsupportedRegister(GenericAddress),
+}
+
+/// A PM1 control register, which the FADT may place in I/O or memory space.
+enum ControlRegister {
+    Port(Port<u16>),
+    Memory(*mut u16),
+}
+
+impl ControlRegister {
+    fn new(register: GenericAddress) -> Result<Self, PowerError> {
+        match register.address_space {
+            AddressSpace::Io if register.address <= u64::from(u16::MAX) => {
+                Ok(Self::Port(Port::new(register.address as u16)))
+            }
+            AddressSpace::Memory => {
+                let address = memory::physical_memory_offset() + register.address;
+                Ok(Self::Memory(address.as_mut_ptr()))
+            }
+            _ => Err(PowerError::UnsupportedRegister(register)),
+        }
+    }
+
+    fn read(&mut self) -> u16 {
+        unsafe {
+            match self {
+                Self::Port(port) => port.read(),
+                Self::Memory(pointer) => pointer.read_volatile(),
+            }
+        }
+    }
+
+    fn write(&mut self, value: u16) {
+        unsafe {
+            match self {
+                Self::Port(port) => port.write(value),
+                Self::Memory(pointer) => pointer.write_volatile(value),
+            }
+        }
+    }
+}
+
+/// Turns the machine off by entering the ACPI soft off state.
+///
+/// Halts forever if that fails, as there is no other generic way to power off a PC.
+pub fn shutdown() -> ! {
+    serial_println!("Power: shutting down");
+    interrupts::disable();
+
+    let error = enter_soft_off();
+    serial_println!("Power: shutdown failed ({:?}), halting", error);
+    hlt_loop()
+}
+
+/// Restarts the machine, trying the ACPI reset register, the keyboard controller and
+/// finally a triple fault.
+pub fn reboot() -> ! {
+    serial_println!("Power: rebooting");
+    interrupts::disable();
+
+    let reset_register =
+        acpi::fadt().and_then(|fadt| Some((fadt.reset_register?, fadt.reset_value)));
+    if let Some((register, value)) = reset_register {
+        write_reset_register(register, value);
+        wait();
+        serial_println!("Power: the ACPI reset register had no effect");
+    }
+
+    if ps2::pulse_reset_line().is_ok() {
+        wait();
+    }
+    serial_println!("Power: the keyboard controller reset had no effect, triple faulting");
+    triple_fault()
+}
+
+/// Waits about 55 ms, without relying on interrupts.
+fn wait() {
+    pit::measure_cycles(u16::MAX, || ());
+}
+
+/// Enters the sleep state S5. Only returns if the machine is still running, with the reason.
+fn enter_soft_off() -> PowerError {
+    match write_soft_off() {
+        Ok(()) => {
+            wait();
+            PowerError::Ignored
+        }
+        Err(e) => e,
+    }
+}
+
+fn write_soft_off() -> Result<(), PowerError> {
+    let fadt = acpi::fadt().ok_or(PowerError::NoAcpi)?;
+    let sleep_type = acpi::sleep_type(SOFT_OFF).ok_or(PowerError::NoSleepState)?;
+    // both registers are checked before either is written
+    let mut pm1a = ControlRegister::new(fadt.pm1a_control_block)?;
+    let pm1b = fadt
+        .pm1b_control_block
+        .map(ControlRegister::new)
+        .transpose()?;
+    enable_acpi_mode(&fadt, &mut pm1a)?;
+
+    write_sleep_type(&mut pm1a, sleep_type.pm1a);
+    if let Some(mut pm1b) = pm1b {
+        write_sleep_type(&mut pm1b, sleep_type.pm1b);
+    }
+    Ok(())
+}
+
+/// Hands the power management hardware from the firmware to the kernel, which is
+/// necessary for writes to the PM1 control registers to have an effect.
+fn enable_acpi_mode(fadt: &Fadt, control: &mut ControlRegister) -> Result<(), PowerError> {
+    let mut enabled = || control.read() & PM1_CONTROL_SCI_ENABLE != 0;
+
+    // without an SMI command port the system is always in ACPI mode
+    if enabled() || fadt.smi_command_port == 0 || fadt.acpi_enable == 0 {
+        return Ok(());
+    }
+
+    unsafe { Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable) };
+    for _ in 0..ACPI_ENABLE_WAITS {
+        if enabled() {
+            return Ok(());
+        }
+        wait();
+    }
+    Err(PowerError::AcpiEnableTimeout)
+}
+
+fn write_sleep_type(control: &mut ControlRegister, sleep_type: u16) {
+    let value = control.read() & !PM1_CONTROL_SLEEP_TYPE_MASK;
+    let sleep_type = (sleep_type << PM1_CONTROL_SLEEP_TYPE_SHIFT) & PM1_CONTROL_SLEEP_TYPE_MASK;
+    control.write(value | sleep_type | PM1_CONTROL_SLEEP_ENABLE);
+}
+
+fn write_reset_register(register: GenericAddress, value: u8) {
+    match register.address_space {
+        AddressSpace::Io => unsafe { Port::<u8>::new(register.address as u16).write(value) },
+        AddressSpace::Memory => unsafe {
+            let address = memory::physical_memory_offset() + register.address;
+            address.as_mut_ptr::<u8>().write_volatile(value);
+        },
+        space => serial_println!("Power: reset register in unsupported {:?} space", space),
+    }
+}
+
+/// Resets the CPU by raising an exception that can't be delivered, which the double fault
+/// that follows can't be either.
+fn triple_fault() -> ! {
+    let empty_idt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::new(0),
+    };
+    unsafe {
+        lidt(&empty_idt);
+        asm!("int3", options(nomem, nostack));
+    }
+    hlt_loop()
+}
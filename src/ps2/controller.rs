@@ -0,0 +1,199 @@
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Set while the controller has not consumed the last byte written
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_PORT_2: u8 = 0xA7;
+const COMMAND_ENABLE_PORT_2: u8 = 0xA8;
+const COMMAND_TEST_PORT_2: u8 = 0xA9;
+const COMMAND_SELF_TEST: u8 = 0xAA;
+const COMMAND_TEST_PORT_1: u8 = 0xAB;
+const COMMAND_DISABLE_PORT_1: u8 = 0xAD;
+const COMMAND_ENABLE_PORT_1: u8 = 0xAE;
+/// Sends the next data byte to the device on the second port
+const COMMAND_WRITE_PORT_2: u8 = 0xD4;
+/// Pulses the CPU reset line, the low bit of the output port
+const COMMAND_PULSE_RESET: u8 = 0xFE;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+pub(crate) const DEVICE_ACK: u8 = 0xFA;
+pub(crate) const DEVICE_RESEND: u8 = 0xFE;
+const DEVICE_RETRIES: usize = 3;
+
+/// The number of status polls before giving up, roughly a microsecond each
+const TIMEOUT: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    SelfTestFailed(u8),
+    PortTestFailed {
+        port: Ps2Port,
+        result: u8,
+    },
+    /// A device answered a command with something else than an acknowledgement
+    UnexpectedResponse(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The keyboard port
+    First,
+    /// The mouse port
+    Second,
+}
+
+/// Access to the 8042 PS/2 controller.
+///
+/// Reading responses by polling only works while the port interrupts are disabled,
+/// otherwise the interrupt handlers consume them.
+pub(crate) struct Controller {
+    data: Port<u8>,
+    status: PortReadOnly<u8>,
+    command: PortWriteOnly<u8>,
+}
+
+impl Controller {
+    pub const fn new() -> Self {
+        Self {
+            data: Port::new(DATA_PORT),
+            status: PortReadOnly::new(STATUS_PORT),
+            command: PortWriteOnly::new(COMMAND_PORT),
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        unsafe { self.status.read() }
+    }
+
+    fn wait_for_input_empty(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if self.status() & STATUS_INPUT_FULL == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    pub fn write_command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        unsafe { self.command.write(command) };
+        Ok(())
+    }
+
+    pub fn write_data(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_for_input_empty()?;
+        unsafe { self.data.write(byte) };
+        Ok(())
+    }
+
+    pub fn read_data(&mut self) -> Result<u8, Ps2Error> {
+        self.read_data_timeout(TIMEOUT)
+    }
+
+    /// Like [read_data](Self::read_data), for responses that take longer, e.g. after a reset.
+    pub fn read_data_timeout(&mut self, timeout: usize) -> Result<u8, Ps2Error> {
+        for _ in 0..timeout {
+            if self.status() & STATUS_OUTPUT_FULL != 0 {
+                return Ok(unsafe { self.data.read() });
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    /// Discards any bytes waiting in the output buffer.
+    pub fn flush(&mut self) {
+        // the buffer is a single byte, but devices may still be sending
+        for _ in 0..16 {
+            if self.status() & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            unsafe { self.data.read() };
+        }
+    }
+
+    pub fn read_config(&mut self) -> Result<u8, Ps2Error> {
+        self.write_command(COMMAND_READ_CONFIG)?;
+        self.read_data()
+    }
+
+    pub fn write_config(&mut self, config: u8) -> Result<(), Ps2Error> {
+        self.write_command(COMMAND_WRITE_CONFIG)?;
+        self.write_data(config)
+    }
+
+    pub fn self_test(&mut self) -> Result<(), Ps2Error> {
+        self.write_command(COMMAND_SELF_TEST)?;
+        match self.read_data()? {
+            SELF_TEST_PASSED => Ok(()),
+            result => Err(Ps2Error::SelfTestFailed(result)),
+        }
+    }
+
+    pub fn test_port(&mut self, port: Ps2Port) -> Result<(), Ps2Error> {
+        self.write_command(match port {
+            Ps2Port::First => COMMAND_TEST_PORT_1,
+            Ps2Port::Second => COMMAND_TEST_PORT_2,
+        })?;
+        match self.read_data()? {
+            PORT_TEST_PASSED => Ok(()),
+            result => Err(Ps2Error::PortTestFailed { port, result }),
+        }
+    }
+
+    /// Enabling and disabling ports has no response, so it can't fail other than by timing out,
+    /// which the following commands will notice.
+    pub fn enable_port(&mut self, port: Ps2Port) {
+        let _ = self.write_command(match port {
+            Ps2Port::First => COMMAND_ENABLE_PORT_1,
+            Ps2Port::Second => COMMAND_ENABLE_PORT_2,
+        });
+    }
+
+    pub fn disable_port(&mut self, port: Ps2Port) {
+        let _ = self.write_command(match port {
+            Ps2Port::First => COMMAND_DISABLE_PORT_1,
+            Ps2Port::Second => COMMAND_DISABLE_PORT_2,
+        });
+    }
+
+    /// Sends a byte to the device on `port` and waits for it to be acknowledged,
+    /// resending it if the device asks to.
+    pub fn send_to_device(&mut self, port: Ps2Port, byte: u8) -> Result<(), Ps2Error> {
+        for _ in 0..DEVICE_RETRIES {
+            if port == Ps2Port::Second {
+                self.write_command(COMMAND_WRITE_PORT_2)?;
+            }
+            self.write_data(byte)?;
+
+            match self.read_data()? {
+                DEVICE_ACK => return Ok(()),
+                DEVICE_RESEND => continue,
+                response => return Err(Ps2Error::UnexpectedResponse(response)),
+            }
+        }
+        Err(Ps2Error::UnexpectedResponse(DEVICE_RESEND))
+    }
+}
+
+/// Sends a byte to the keyboard without waiting for the acknowledgement, which arrives
+/// through the keyboard interrupt handler.
+pub(crate) fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    Controller::new().write_data(byte)
+}
+
+/// Asks the controller to reset the CPU, which restarts the machine on most chipsets.
+pub(crate) fn pulse_reset_line() -> Result<(), Ps2Error> {
+    Controller::new().write_command(COMMAND_PULSE_RESET)
+}
@@ -0,0 +1,99 @@
+mod controller;
+pub mod mouse;
+
+pub(crate) use controller::{pulse_reset_line, write_data};
+pub use controller::{Ps2Error, Ps2Port};
+
+use crate::{print, println, serial_println};
+use controller::Controller;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+
+const CONFIG_PORT_1_INTERRUPT: u8 = 1 << 0;
+const CONFIG_PORT_2_INTERRUPT: u8 = 1 << 1;
+const CONFIG_PORT_2_CLOCK_DISABLED: u8 = 1 << 5;
+/// Translates keyboard scancodes to set 1, which the keyboard task decodes
+const CONFIG_PORT_1_TRANSLATION: u8 = 1 << 6;
+
+static DUAL_CHANNEL: AtomicBool = AtomicBool::new(false);
+
+pub fn init() {
+    print!("Initializing PS/2 controller...   ");
+
+    // the interrupt handlers would steal the responses read during initialization
+    match interrupts::without_interrupts(init_controller) {
+        Ok(()) => println!("[Ok]"),
+        Err(e) => {
+            println!("[Failed]");
+            serial_println!("PS/2: controller initialization failed: {:?}", e);
+        }
+    }
+}
+
+/// Returns whether the controller has a second port, for the mouse.
+pub fn is_dual_channel() -> bool {
+    DUAL_CHANNEL.load(Ordering::Relaxed)
+}
+
+/// Performs the initialization sequence of the 8042, see <https://wiki.osdev.org/%228042%22_PS/2_Controller>.
+fn init_controller() -> Result<(), Ps2Error> {
+    let mut controller = Controller::new();
+
+    controller.disable_port(Ps2Port::First);
+    controller.disable_port(Ps2Port::Second);
+    controller.flush();
+
+    let mut config = controller.read_config()?;
+    let mut dual_channel = config & CONFIG_PORT_2_CLOCK_DISABLED != 0;
+    config &= !(CONFIG_PORT_1_INTERRUPT | CONFIG_PORT_2_INTERRUPT);
+    config |= CONFIG_PORT_1_TRANSLATION;
+    controller.write_config(config)?;
+
+    controller.self_test()?;
+    // some controllers reset themselves during the self test
+    controller.write_config(config)?;
+
+    if dual_channel {
+        // the second clock only gets enabled if there is a second port
+        controller.enable_port(Ps2Port::Second);
+        dual_channel = controller.read_config()? & CONFIG_PORT_2_CLOCK_DISABLED == 0;
+        controller.disable_port(Ps2Port::Second);
+    }
+
+    controller.test_port(Ps2Port::First)?;
+    if dual_channel {
+        if let Err(e) = controller.test_port(Ps2Port::Second) {
+            serial_println!("PS/2: second port unusable: {:?}", e);
+            dual_channel = false;
+        }
+    }
+    DUAL_CHANNEL.store(dual_channel, Ordering::Relaxed);
+
+    controller.enable_port(Ps2Port::First);
+    config |= CONFIG_PORT_1_INTERRUPT;
+
+    if dual_channel {
+        controller.enable_port(Ps2Port::Second);
+        match mouse::init(&mut controller) {
+            Ok(mouse_type) => {
+                serial_println!("PS/2: found {:?} mouse", mouse_type);
+                config |= CONFIG_PORT_2_INTERRUPT;
+                config &= !CONFIG_PORT_2_CLOCK_DISABLED;
+            }
+            Err(e) => {
+                serial_println!("PS/2: no mouse on the second port: {:?}", e);
+            }
+        }
+    }
+
+    controller.write_config(config)?;
+    serial_println!(
+        "PS/2: {} controller initialized",
+        if dual_channel {
+            "dual channel"
+        } else {
+            "single channel"
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use super::controller::Controller;
+use super::{Ps2Error, Ps2Port};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xF3;
+const COMMAND_GET_ID: u8 = 0xF2;
+const COMMAND_ENABLE_REPORTING: u8 = 0xF4;
+const COMMAND_RESET: u8 = 0xFF;
+
+const RESET_PASSED: u8 = 0xAA;
+/// The reset self test can take up to a second
+const RESET_TIMEOUT: usize = 1_000_000;
+const SAMPLE_RATE: u8 = 100;
+
+const PACKET_LEFT_BUTTON: u8 = 1 << 0;
+const PACKET_RIGHT_BUTTON: u8 = 1 << 1;
+const PACKET_MIDDLE_BUTTON: u8 = 1 << 2;
+/// Always set in the first byte of a packet, used to resynchronize
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+const NO_MOUSE: u8 = 0xFF;
+/// The ID the detected mouse reports, or `NO_MOUSE`
+static MOUSE_ID: AtomicU8 = AtomicU8::new(NO_MOUSE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseType {
+    /// A standard three button mouse with 3 byte packets
+    Standard,
+    /// An IntelliMouse with a scroll wheel and 4 byte packets
+    Wheel,
+    /// An IntelliMouse Explorer with a scroll wheel and five buttons
+    FiveButton,
+}
+
+impl MouseType {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x00 => Some(MouseType::Standard),
+            0x03 => Some(MouseType::Wheel),
+            0x04 => Some(MouseType::FiveButton),
+            _ => None,
+        }
+    }
+
+    pub fn packet_size(&self) -> usize {
+        match self {
+            MouseType::Standard => 3,
+            MouseType::Wheel | MouseType::FiveButton => 4,
+        }
+    }
+}
+
+/// Returns the type of the mouse on the second PS/2 port, if one was found.
+pub fn mouse_type() -> Option<MouseType> {
+    MouseType::from_id(MOUSE_ID.load(Ordering::Relaxed))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// A decoded mouse packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// The horizontal movement, positive to the right
+    pub dx: i16,
+    /// The vertical movement, positive downwards like screen coordinates
+    pub dy: i16,
+    /// The scroll wheel movement, positive when scrolled towards the user
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+/// Resets the mouse, detects its type and enables reporting.
+pub(super) fn init(controller: &mut Controller) -> Result<MouseType, Ps2Error> {
+    controller.send_to_device(Ps2Port::Second, COMMAND_RESET)?;
+    match controller.read_data_timeout(RESET_TIMEOUT)? {
+        RESET_PASSED => {}
+        response => return Err(Ps2Error::UnexpectedResponse(response)),
+    }
+    // the ID, which is always 0 after a reset
+    controller.read_data()?;
+
+    // the magic sample rate sequences unlock the wheel and then the extra buttons
+    let mut id = 0;
+    for sequence in [[200, 100, 80], [200, 200, 80]] {
+        for rate in sequence {
+            set_sample_rate(controller, rate)?;
+        }
+        id = get_id(controller)?;
+    }
+    set_sample_rate(controller, SAMPLE_RATE)?;
+
+    let mouse_type = MouseType::from_id(id).ok_or(Ps2Error::UnexpectedResponse(id))?;
+    controller.send_to_device(Ps2Port::Second, COMMAND_ENABLE_REPORTING)?;
+    MOUSE_ID.store(id, Ordering::Relaxed);
+    Ok(mouse_type)
+}
+
+fn set_sample_rate(controller: &mut Controller, rate: u8) -> Result<(), Ps2Error> {
+    controller.send_to_device(Ps2Port::Second, COMMAND_SET_SAMPLE_RATE)?;
+    controller.send_to_device(Ps2Port::Second, rate)
+}
+
+fn get_id(controller: &mut Controller) -> Result<u8, Ps2Error> {
+    controller.send_to_device(Ps2Port::Second, COMMAND_GET_ID)?;
+    controller.read_data()
+}
+
+/// Assembles the bytes received from the mouse into packets.
+pub struct PacketDecoder {
+    bytes: [u8; 4],
+    len: usize,
+    packet_size: usize,
+}
+
+impl PacketDecoder {
+    pub fn new(mouse_type: MouseType) -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+            packet_size: mouse_type.packet_size(),
+        }
+    }
+
+    /// Adds a byte and returns the event if it completed a packet.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // bytes lost during a transfer shift the packet, skip until a plausible first byte
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return None;
+        }
+
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_size {
+            return None;
+        }
+        self.len = 0;
+
+        let flags = self.bytes[0];
+        if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) != 0 {
+            return None;
+        }
+
+        // the movement is a 9 bit two's complement number with the sign in the flags
+        let movement = |value: u8, sign: u8| -> i16 {
+            if flags & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+        let wheel = if self.packet_size == 4 {
+            // the upper bits are extra buttons, the lower 4 bits a signed number
+            ((self.bytes[3] << 4) as i8) >> 4
+        } else {
+            0
+        };
+
+        Some(MouseEvent {
+            dx: movement(self.bytes[1], PACKET_X_SIGN),
+            dy: -movement(self.bytes[2], PACKET_Y_SIGN),
+            wheel,
+            buttons: MouseButtons {
+                left: flags & PACKET_LEFT_BUTTON != 0,
+                right: flags & PACKET_RIGHT_BUTTON != 0,
+                middle: flags & PACKET_MIDDLE_BUTTON != 0,
+            },
+        })
+    }
+}
+
+#[test_case]
+fn packets_are_decoded() {
+    let mut decoder = PacketDecoder::new(MouseType::Wheel);
+
+    // left button, moving left and up, one notch away from the user
+    assert_eq!(decoder.add_byte(0b0001_1001), None);
+    assert_eq!(decoder.add_byte(0xFE), None);
+    assert_eq!(decoder.add_byte(0x05), None);
+    let event = decoder.add_byte(0x0F).unwrap();
+    assert_eq!((event.dx, event.dy, event.wheel), (-2, -5, -1));
+    assert!(event.buttons.left && !event.buttons.right);
+
+    // a stray byte without the always-one bit is skipped
+    assert_eq!(decoder.add_byte(0x00), None);
+    for byte in [0b0010_1000, 0x00, 0xF0] {
+        assert_eq!(decoder.add_byte(byte), None);
+    }
+    let event = decoder.add_byte(0x01).unwrap();
+    assert_eq!((event.dx, event.dy, event.wheel), (0, 16, 1));
+}
+
+#[test_case]
+fn mouse_is_detected() {
+    // QEMU emulates an IntelliMouse
+    assert!(super::is_dual_channel());
+    assert!(matches!(
+        mouse_type(),
+        Some(MouseType::Wheel | MouseType::FiveButton)
+    ));
+}
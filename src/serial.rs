@@ -24,6 +24,17 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Prints `args` unless the serial port is in use, for handlers that may interrupt a print
+/// and must not wait for it. Returns whether anything was printed.
+pub fn try_print(args: fmt::Arguments) -> bool {
+    use fmt::Write;
+
+    match SERIAL1.try_lock() {
+        Some(mut serial_port) => serial_port.write_fmt(args).is_ok(),
+        None => false,
+    }
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -0,0 +1,188 @@
+use super::{Task, TaskId};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+/// The maximum number of tasks that can be woken up between two runs of the executor
+const TASK_QUEUE_SIZE: usize = 100;
+
+/// An executor that only polls tasks after they have been woken up, and halts the CPU
+/// while there is nothing to do.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    /// Tasks that have been woken up, shared with their wakers, which may be called from
+    /// interrupt handlers
+    task_queue: Arc<ArrayQueue<TaskId>>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new(TASK_QUEUE_SIZE)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("task with id {:?} already spawned", id);
+        }
+        self.task_queue.push(id).expect("task queue full");
+    }
+
+    /// Returns the number of tasks that have not finished yet.
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Polls every task that has been woken up, until none are left.
+    pub fn run_ready_tasks(&mut self) {
+        // destructure to borrow the fields separately in the loop
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Some(id) = task_queue.pop() {
+            let task = match tasks.get_mut(&id) {
+                Some(task) => task,
+                // the task finished before a stale wakeup was processed
+                None => continue,
+            };
+
+            let waker = waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::new(id, task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                tasks.remove(&id);
+                waker_cache.remove(&id);
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        // an interrupt between the check and the hlt could wake a task, which would then
+        // not run until the next interrupt, so the check happens with interrupts disabled
+        // and `sti; hlt` enables them atomically with halting
+        interrupts::disable();
+        if self.task_queue.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+struct TaskWaker {
+    id: TaskId,
+    task_queue: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn new(id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, task_queue }))
+    }
+
+    fn wake_task(&self) {
+        // must not allocate or block, wakers are called from interrupt handlers
+        if self.task_queue.push(self.id).is_err() {
+            crate::serial_println!("WARNING: task queue full, dropping wakeup of {:?}", self.id);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[test_case]
+fn only_woken_tasks_are_polled() {
+    use alloc::rc::Rc;
+    use core::cell::{Cell, RefCell};
+    use core::future::Future;
+    use core::pin::Pin;
+
+    struct PollFn<F>(F);
+
+    impl<F: FnMut(&mut Context) -> Poll<()> + Unpin> Future for PollFn<F> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            (self.0)(context)
+        }
+    }
+
+    let polls = Rc::new(Cell::new(0));
+    let waker = Rc::new(RefCell::new(None::<Waker>));
+    let mut executor = Executor::new();
+
+    let (task_polls, task_waker) = (polls.clone(), waker.clone());
+    executor.spawn(Task::new(PollFn(move |context: &mut Context| {
+        task_polls.set(task_polls.get() + 1);
+        if task_polls.get() == 2 {
+            return Poll::Ready(());
+        }
+        *task_waker.borrow_mut() = Some(context.waker().clone());
+        Poll::Pending
+    })));
+
+    executor.run_ready_tasks();
+    executor.run_ready_tasks();
+    assert_eq!(polls.get(), 1);
+    assert_eq!(executor.task_count(), 1);
+
+    waker.borrow_mut().take().unwrap().wake();
+    executor.run_ready_tasks();
+    assert_eq!(polls.get(), 2);
+    assert_eq!(executor.task_count(), 0);
+}
+
+#[test_case]
+fn yielding_tasks_interleave() {
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+
+    for name in ['a', 'b'] {
+        let order = order.clone();
+        executor.spawn(Task::new(async move {
+            order.borrow_mut().push(name);
+            super::yield_now().await;
+            order.borrow_mut().push(name);
+        }));
+    }
+
+    executor.run_ready_tasks();
+    assert_eq!(*order.borrow(), ['a', 'b', 'a', 'b']);
+}
@@ -0,0 +1,192 @@
+use crate::{print, serial_println};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyEvent, Keyboard, ScancodeSet1};
+use spin::Once;
+
+/// The number of scancodes that can be buffered before new ones are dropped
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+static SCANCODE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Scancodes lost because the queue was full or not created yet
+static DROPPED_SCANCODES: AtomicU64 = AtomicU64::new(0);
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Called by the keyboard interrupt handler, so it must not block or allocate.
+pub(crate) fn add_scancode(scancode: u8) {
+    // responses to commands sent by the keyboard module, not keys
+    if scancode == crate::keyboard::KEYBOARD_RESPONSE_ACK
+        || scancode == crate::keyboard::KEYBOARD_RESPONSE_RESEND
+    {
+        crate::keyboard::response_received(scancode);
+        return;
+    }
+
+    let queued = match SCANCODE_QUEUE.get() {
+        Some(queue) => queue.push(scancode).is_ok(),
+        None => false,
+    };
+
+    if queued {
+        WAKER.wake();
+    } else {
+        DROPPED_SCANCODES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the total number of scancodes that were dropped.
+pub fn dropped_scancodes() -> u64 {
+    DROPPED_SCANCODES.load(Ordering::Relaxed)
+}
+
+/// The raw scancodes received by the keyboard interrupt handler.
+///
+/// There can only be one stream, as every scancode is delivered exactly once.
+pub struct ScancodeStream {
+    /// The dropped scancodes already reported over serial
+    reported_drops: u64,
+}
+
+impl ScancodeStream {
+    /// Creates the stream, panics if it already exists.
+    pub fn new() -> Self {
+        if STREAM_TAKEN.swap(true, Ordering::AcqRel) {
+            panic!("ScancodeStream::new must only be called once");
+        }
+        SCANCODE_QUEUE.call_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE));
+
+        Self {
+            reported_drops: dropped_scancodes(),
+        }
+    }
+
+    fn report_drops(&mut self) {
+        let dropped = dropped_scancodes();
+        if dropped != self.reported_drops {
+            serial_println!(
+                "WARNING: scancode queue full, dropped {} scancodes ({} in total)",
+                dropped - self.reported_drops,
+                dropped
+            );
+            self.reported_drops = dropped;
+        }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        self.report_drops();
+        let queue = SCANCODE_QUEUE
+            .get()
+            .expect("scancode queue not initialized");
+
+        // fast path
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // a scancode may arrive between the first check and registering the waker
+        WAKER.register(context.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes the scancodes of a [ScancodeStream] into key presses and releases,
+/// without applying a keyboard layout.
+pub struct KeyEventStream {
+    scancodes: ScancodeStream,
+    /// Only used to decode scancodes, the layout matters for `process_keyevent` only
+    decoder: Keyboard<layouts::Us104Key, ScancodeSet1>,
+}
+
+impl KeyEventStream {
+    pub fn new(scancodes: ScancodeStream) -> Self {
+        Self {
+            scancodes,
+            decoder: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
+        }
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<KeyEvent>> {
+        loop {
+            let scancode = match Pin::new(&mut self.scancodes).poll_next(context) {
+                Poll::Ready(Some(scancode)) => scancode,
+                other => return other.map(|_| None),
+            };
+
+            // extended keys take multiple scancodes
+            match self.decoder.add_byte(scancode) {
+                Ok(Some(event)) => return Poll::Ready(Some(event)),
+                Ok(None) => {}
+                Err(e) => {
+                    serial_println!("keyboard: undecodable scancode {:#04x}: {:?}", scancode, e);
+                }
+            }
+        }
+    }
+}
+
+/// Prints the characters typed on the keyboard, using the layout selected in [keyboard](crate::keyboard).
+pub async fn print_keypresses() {
+    let mut events = KeyEventStream::new(ScancodeStream::new());
+
+    while let Some(event) = events.next().await {
+        if let Some(key) = crate::keyboard::process_keyevent(event) {
+            match key {
+                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::RawKey(keycode) => print!("{:?}", keycode),
+            }
+        }
+    }
+}
+
+#[test_case]
+fn scancodes_are_queued_and_counted() {
+    use super::simple_executor::SimpleExecutor;
+    use super::Task;
+    use x86_64::instructions::interrupts;
+
+    let mut scancodes = ScancodeStream::new();
+    let dropped = dropped_scancodes();
+
+    // keep the real keyboard out of the way
+    interrupts::without_interrupts(|| {
+        for _ in 0..SCANCODE_QUEUE_SIZE {
+            add_scancode(0x1E);
+        }
+        add_scancode(0x9E);
+        assert_eq!(dropped_scancodes(), dropped + 1);
+
+        while SCANCODE_QUEUE.get().unwrap().pop().is_some() {}
+        // pressing and releasing A
+        add_scancode(0x1E);
+        add_scancode(0x9E);
+    });
+
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(async move {
+        assert_eq!(scancodes.next().await, Some(0x1E));
+        let mut events = KeyEventStream::new(scancodes);
+        let release = events.next().await.unwrap();
+        assert_eq!(release.code, pc_keyboard::KeyCode::A);
+        assert_eq!(release.state, pc_keyboard::KeyState::Up);
+    }));
+    executor.run();
+}
@@ -0,0 +1,124 @@
+use crate::graphics::console;
+use crate::graphics::cursor::Cursor;
+use crate::ps2::mouse::{self, MouseEvent, MouseType, PacketDecoder};
+use crate::serial_println;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+/// The number of bytes that can be buffered before new ones are dropped, 64 packets
+const BYTE_QUEUE_SIZE: usize = 256;
+
+static BYTE_QUEUE: Once<ArrayQueue<u8>> = Once::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Bytes lost because the queue was full or not created yet
+static DROPPED_BYTES: AtomicU64 = AtomicU64::new(0);
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Called by the mouse interrupt handler, so it must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    let queued = match BYTE_QUEUE.get() {
+        Some(queue) => queue.push(byte).is_ok(),
+        None => false,
+    };
+
+    if queued {
+        WAKER.wake();
+    } else {
+        DROPPED_BYTES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the total number of bytes from the mouse that were dropped.
+pub fn dropped_bytes() -> u64 {
+    DROPPED_BYTES.load(Ordering::Relaxed)
+}
+
+/// The packets sent by the PS/2 mouse.
+///
+/// There can only be one stream, as every packet is delivered exactly once.
+pub struct MouseEventStream {
+    decoder: PacketDecoder,
+    /// The dropped bytes already reported over serial
+    reported_drops: u64,
+}
+
+impl MouseEventStream {
+    /// Creates the stream, panics if it already exists.
+    pub fn new() -> Self {
+        if STREAM_TAKEN.swap(true, Ordering::AcqRel) {
+            panic!("MouseEventStream::new must only be called once");
+        }
+        BYTE_QUEUE.call_once(|| ArrayQueue::new(BYTE_QUEUE_SIZE));
+
+        // without a mouse the stream simply never yields anything
+        let mouse_type = mouse::mouse_type().unwrap_or(MouseType::Standard);
+        Self {
+            decoder: PacketDecoder::new(mouse_type),
+            reported_drops: dropped_bytes(),
+        }
+    }
+
+    fn report_drops(&mut self) {
+        let dropped = dropped_bytes();
+        if dropped != self.reported_drops {
+            serial_println!(
+                "WARNING: mouse queue full, dropped {} bytes ({} in total)",
+                dropped - self.reported_drops,
+                dropped
+            );
+            self.reported_drops = dropped;
+        }
+    }
+}
+
+impl Stream for MouseEventStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<MouseEvent>> {
+        self.report_drops();
+        let queue = BYTE_QUEUE.get().expect("mouse queue not initialized");
+
+        loop {
+            let byte = match queue.pop() {
+                Some(byte) => byte,
+                None => {
+                    // a byte may arrive between the check and registering the waker
+                    WAKER.register(context.waker());
+                    match queue.pop() {
+                        Some(byte) => {
+                            WAKER.take();
+                            byte
+                        }
+                        None => return Poll::Pending,
+                    }
+                }
+            };
+
+            if let Some(event) = self.decoder.add_byte(byte) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+}
+
+/// Shows a mouse pointer on the framebuffer console and moves it with the mouse.
+pub async fn track_pointer() {
+    let mut events = MouseEventStream::new();
+    interrupts::without_interrupts(|| {
+        console::with_console(|console| console.set_pointer(Some(Cursor::arrow())))
+    });
+
+    while let Some(event) = events.next().await {
+        interrupts::without_interrupts(|| {
+            console::with_console(|console| {
+                console.move_pointer_by(event.dx as isize, event.dy as isize)
+            })
+        });
+    }
+}
@@ -0,0 +1,73 @@
+use super::Task;
+use alloc::collections::VecDeque;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// An executor that polls all tasks round-robin until they complete, without any
+/// notion of wakeups. Mostly useful for tests and for bringing up new tasks.
+pub struct SimpleExecutor {
+    task_queue: VecDeque<Task>,
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimpleExecutor {
+    pub fn new() -> Self {
+        Self {
+            task_queue: VecDeque::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: Task) {
+        self.task_queue.push_back(task)
+    }
+
+    /// Polls the tasks until all of them are finished.
+    pub fn run(&mut self) {
+        while let Some(mut task) = self.task_queue.pop_front() {
+            let waker = dummy_waker();
+            let mut context = Context::from_waker(&waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {}
+                Poll::Pending => self.task_queue.push_back(task),
+            }
+        }
+    }
+}
+
+fn dummy_raw_waker() -> RawWaker {
+    fn no_op(_: *const ()) {}
+    fn clone(_: *const ()) -> RawWaker {
+        dummy_raw_waker()
+    }
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, no_op, no_op, no_op);
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn dummy_waker() -> Waker {
+    unsafe { Waker::from_raw(dummy_raw_waker()) }
+}
+
+#[test_case]
+fn runs_tasks_to_completion() {
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    let counter = Rc::new(Cell::new(0));
+    let mut executor = SimpleExecutor::new();
+
+    for _ in 0..3 {
+        let counter = counter.clone();
+        executor.spawn(Task::new(async move {
+            super::yield_now().await;
+            counter.set(counter.get() + 1);
+        }));
+    }
+
+    executor.run();
+    assert_eq!(counter.get(), 3);
+}
@@ -0,0 +1,107 @@
+use crate::allocator::{heap_stats, HEAP_MAX_SIZE, HEAP_SIZE};
+use crate::serial_println;
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
+use core::alloc::Layout;
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn large_allocation() {
+    // bigger than the largest fixed block size, so it is served by the fallback allocator
+    let size = HEAP_SIZE / 4;
+    let buffer = vec![0xabu8; size];
+    assert_eq!(buffer.len(), size);
+    assert!(buffer.iter().all(|&byte| byte == 0xab));
+}
+
+#[test_case]
+fn many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn freed_memory_is_reused() {
+    let first = Box::new([0u8; 64]);
+    let address = &*first as *const [u8; 64] as usize;
+    drop(first);
+
+    let second = Box::new([1u8; 64]);
+    assert_eq!(&*second as *const [u8; 64] as usize, address);
+}
+
+#[test_case]
+fn collections() {
+    let mut map = BTreeMap::new();
+    for i in 0..100 {
+        let mut value = String::from("value ");
+        value.push(char::from(b'0' + (i % 10) as u8));
+        map.insert(i, value);
+    }
+
+    assert_eq!(map.len(), 100);
+    assert_eq!(map[&42], "value 2");
+}
+
+#[test_case]
+fn heap_grows_on_demand() {
+    let size = HEAP_SIZE * 4;
+    let buffer = vec![0x5au8; size];
+    assert!(heap_stats().size >= HEAP_SIZE + size);
+    assert_eq!(buffer[size - 1], 0x5a);
+}
+
+#[test_case]
+fn heap_growth_is_capped() {
+    let layout = Layout::from_size_align(HEAP_MAX_SIZE, 8).unwrap();
+    let ptr = unsafe { alloc::alloc::alloc(layout) };
+    assert!(ptr.is_null());
+    assert!(heap_stats().size <= HEAP_MAX_SIZE);
+}
+
+#[test_case]
+fn heap_stats_track_allocations() {
+    let before = heap_stats();
+
+    let buffer: Vec<u8> = Vec::with_capacity(4096);
+    let during = heap_stats();
+    assert_eq!(during.in_use, before.in_use + 4096);
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.total_allocations, before.total_allocations + 1);
+    assert!(during.peak >= during.in_use);
+
+    drop(buffer);
+    let after = heap_stats();
+    assert_eq!(after.in_use, before.in_use);
+    assert_eq!(after.allocations, before.allocations);
+    assert!(after.fragmentation() <= 100);
+    serial_println!("{}", after);
+}
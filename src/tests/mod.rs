@@ -1,5 +1,7 @@
 #[allow(unused_imports)]
 use crate::{print, println};
 
+#[cfg(test)]
+mod heap;
 pub mod runner;
 pub use runner::{isa_debug_exit_qemu, QemuExitCode};
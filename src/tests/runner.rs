@@ -28,8 +28,9 @@ where
 {
     fn run(&self) {
         serial_print!("{}...\t", T::test_name());
+        let start = crate::time::Instant::now();
         self();
-        serial_println!("[Ok]");
+        serial_println!("[Ok] ({:?})", start.elapsed());
     }
 }
 
@@ -0,0 +1,395 @@
+use super::TickSource;
+use crate::acpi::{AddressSpace, HpetTable};
+use crate::interrupt::apic::{self, Polarity, TriggerMode, HPET_VECTORS};
+use crate::memory::{self, MemoryError};
+use crate::{print, println, serial_println};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The comparator driving the tick clock while the HPET is the tick source
+pub const TICK_COMPARATOR: u8 = 0;
+
+/// The virtual address the registers get mapped to, after the APIC registers
+const REGISTERS_START: u64 = 0x_6666_1000_0000;
+
+const REGISTER_CAPABILITIES: usize = 0x000;
+const REGISTER_CONFIGURATION: usize = 0x010;
+const REGISTER_MAIN_COUNTER: usize = 0x0F0;
+/// The registers of comparator `n` follow at `n` times `COMPARATOR_STRIDE`
+const REGISTER_COMPARATOR_CONFIGURATION: usize = 0x100;
+const REGISTER_COMPARATOR_VALUE: usize = 0x108;
+const COMPARATOR_STRIDE: usize = 0x20;
+
+const CAPABILITIES_COMPARATORS_SHIFT: u64 = 8;
+const CAPABILITIES_COMPARATORS_MASK: u64 = 0x1F;
+const CAPABILITIES_PERIOD_SHIFT: u64 = 32;
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+/// Wires the first two comparators to IRQ 0 and 8, in place of the PIT and the RTC
+const CONFIGURATION_LEGACY_REPLACEMENT: u64 = 1 << 1;
+
+const COMPARATOR_LEVEL_TRIGGERED: u64 = 1 << 1;
+const COMPARATOR_INTERRUPT_ENABLE: u64 = 1 << 2;
+const COMPARATOR_PERIODIC: u64 = 1 << 3;
+const COMPARATOR_PERIODIC_CAPABLE: u64 = 1 << 4;
+/// Lets the next write to the value register of a periodic comparator set the comparator,
+/// the one after sets the period
+const COMPARATOR_SET_VALUE: u64 = 1 << 6;
+const COMPARATOR_ROUTE_SHIFT: u64 = 9;
+const COMPARATOR_ROUTE_MASK: u64 = 0x1F << COMPARATOR_ROUTE_SHIFT;
+const COMPARATOR_FSB_ENABLE: u64 = 1 << 14;
+/// A bit per I/O APIC input the comparator can be routed to
+const COMPARATOR_ROUTE_CAPABILITIES_SHIFT: u64 = 32;
+
+/// The lowest GSI a comparator gets routed to, the ones below belong to ISA devices
+const FIRST_FREE_GSI: u32 = 16;
+/// The longest main counter period the specification allows, 100 ns
+const MAXIMUM_PERIOD: u64 = 100_000_000;
+const FEMTOSECONDS_PER_NANOSECOND: u128 = 1_000_000;
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// The virtual address the registers are mapped to, zero while the HPET is not enabled
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// The period of the main counter in femtoseconds, zero while the HPET is not enabled
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+/// The fewest main counter ticks between two comparator interrupts
+static MINIMUM_TICK: AtomicU64 = AtomicU64::new(1);
+static COMPARATOR_COUNT: AtomicU8 = AtomicU8::new(0);
+/// A bit per comparator that is routed to its vector in [HPET_VECTORS]
+static ROUTED: AtomicU8 = AtomicU8::new(0);
+static INTERRUPTS: [AtomicU64; HPET_VECTORS.len()] =
+    [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    /// The registers are not in memory space
+    UnsupportedAddressSpace(AddressSpace),
+    MappingFailed(MemoryError),
+    /// The main counter period in femtoseconds is zero or longer than allowed
+    InvalidPeriod(u64),
+    /// [init] did not succeed
+    NotEnabled,
+    NoSuchComparator(u8),
+    /// The comparator is not connected to an interrupt vector
+    NotRouted(u8),
+    PeriodicUnsupported(u8),
+}
+
+/// Enables the HPET described by `table` and routes its first comparators through the
+/// I/O APIC to [HPET_VECTORS].
+///
+/// Has to be called after the APICs are initialized. Without them the main counter still
+/// works, but the comparators stay unrouted.
+pub fn init(table: &HpetTable) {
+    print!("Initializing HPET...   ");
+
+    match interrupts::without_interrupts(|| enable(table)) {
+        Ok(()) => {
+            println!("[Ok]");
+            serial_println!(
+                "HPET: {} kHz main counter, {} comparators, {} routed",
+                frequency() / 1000,
+                comparator_count(),
+                ROUTED.load(Ordering::Relaxed).count_ones()
+            );
+        }
+        Err(e) => {
+            println!("[Failed]");
+            serial_println!("HPET: {:?}", e);
+        }
+    }
+}
+
+fn enable(table: &HpetTable) -> Result<(), HpetError> {
+    let address_space = table.base_address.address_space;
+    if address_space != AddressSpace::Memory {
+        return Err(HpetError::UnsupportedAddressSpace(address_space));
+    }
+    let base = map_registers(PhysAddr::new(table.base_address.address))
+        .map_err(HpetError::MappingFailed)?;
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+
+    let capabilities = read(REGISTER_CAPABILITIES);
+    let period = capabilities >> CAPABILITIES_PERIOD_SHIFT;
+    if period == 0 || period > MAXIMUM_PERIOD {
+        BASE.store(0, Ordering::Relaxed);
+        return Err(HpetError::InvalidPeriod(period));
+    }
+    let count = ((capabilities >> CAPABILITIES_COMPARATORS_SHIFT) & CAPABILITIES_COMPARATORS_MASK)
+        as u8
+        + 1;
+
+    // halt the main counter and silence every comparator the firmware may have left running
+    let configuration = read(REGISTER_CONFIGURATION);
+    write(
+        REGISTER_CONFIGURATION,
+        configuration & !(CONFIGURATION_ENABLE | CONFIGURATION_LEGACY_REPLACEMENT),
+    );
+    for comparator in 0..count {
+        let register = comparator_register(REGISTER_COMPARATOR_CONFIGURATION, comparator);
+        let disabled = COMPARATOR_INTERRUPT_ENABLE
+            | COMPARATOR_PERIODIC
+            | COMPARATOR_LEVEL_TRIGGERED
+            | COMPARATOR_FSB_ENABLE;
+        write(register, read(register) & !disabled);
+    }
+
+    let mut routed = 0;
+    let mut used_gsis = 0u32;
+    for comparator in 0..count.min(HPET_VECTORS.len() as u8) {
+        let register = comparator_register(REGISTER_COMPARATOR_CONFIGURATION, comparator);
+        let configuration = read(register);
+        let allowed = (configuration >> COMPARATOR_ROUTE_CAPABILITIES_SHIFT) as u32 & !used_gsis;
+        let gsi = match (FIRST_FREE_GSI..32).find(|gsi| allowed & (1 << gsi) != 0) {
+            Some(gsi) => gsi,
+            None => {
+                serial_println!("HPET: comparator {} has no free GSI", comparator);
+                continue;
+            }
+        };
+        let vector = HPET_VECTORS[comparator as usize];
+        if !apic::route_gsi(gsi, vector, Polarity::ActiveHigh, TriggerMode::Edge) {
+            serial_println!(
+                "HPET: GSI {} of comparator {} can't be routed",
+                gsi,
+                comparator
+            );
+            continue;
+        }
+
+        let route = (gsi as u64) << COMPARATOR_ROUTE_SHIFT;
+        write(register, (configuration & !COMPARATOR_ROUTE_MASK) | route);
+        used_gsis |= 1 << gsi;
+        routed |= 1 << comparator;
+    }
+
+    PERIOD.store(period, Ordering::Relaxed);
+    MINIMUM_TICK.store(table.minimum_tick.max(1) as u64, Ordering::Relaxed);
+    COMPARATOR_COUNT.store(count, Ordering::Relaxed);
+    ROUTED.store(routed, Ordering::Relaxed);
+    write(
+        REGISTER_CONFIGURATION,
+        read(REGISTER_CONFIGURATION) | CONFIGURATION_ENABLE,
+    );
+    Ok(())
+}
+
+/// Maps the register page at `address` uncached to `REGISTERS_START`.
+fn map_registers(address: PhysAddr) -> Result<VirtAddr, MemoryError> {
+    let page = Page::containing_address(VirtAddr::new(REGISTERS_START));
+    let frame = PhysFrame::containing_address(address);
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::NO_CACHE;
+
+    match unsafe { memory::map_physical_range(frame, Page::range_inclusive(page, page), flags) } {
+        Ok(()) => {}
+        // mapped by an earlier attempt
+        Err(MemoryError::PageAlreadyMapped(mapped)) if mapped == frame => {}
+        Err(e) => return Err(e),
+    }
+    Ok(page.start_address() + (address.as_u64() - frame.start_address().as_u64()))
+}
+
+fn read(register: usize) -> u64 {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "HPET is not enabled");
+    unsafe { ((base as usize + register) as *const u64).read_volatile() }
+}
+
+fn write(register: usize, value: u64) {
+    let base = BASE.load(Ordering::Relaxed);
+    assert_ne!(base, 0, "HPET is not enabled");
+    unsafe { ((base as usize + register) as *mut u64).write_volatile(value) }
+}
+
+fn comparator_register(register: usize, comparator: u8) -> usize {
+    register + comparator as usize * COMPARATOR_STRIDE
+}
+
+/// Returns `true` if [init] found and enabled an HPET.
+pub fn is_available() -> bool {
+    PERIOD.load(Ordering::Relaxed) != 0
+}
+
+/// Returns the main counter, which counts up once every [counter_period].
+pub fn counter() -> u64 {
+    read(REGISTER_MAIN_COUNTER)
+}
+
+/// Returns the period of the main counter in femtoseconds, zero while the HPET is not enabled.
+pub fn counter_period() -> u64 {
+    PERIOD.load(Ordering::Relaxed)
+}
+
+/// Returns the rate the main counter counts up at in Hz, zero while the HPET is not enabled.
+pub fn frequency() -> u64 {
+    FEMTOSECONDS_PER_SECOND
+        .checked_div(counter_period())
+        .unwrap_or(0)
+}
+
+/// Returns the time `ticks` main counter ticks take.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * counter_period() as u128 / FEMTOSECONDS_PER_NANOSECOND;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Returns the number of main counter ticks closest to `duration`, at least the minimum
+/// tick of the HPET.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let period = counter_period().max(1) as u128;
+    let ticks = (duration.as_nanos() * FEMTOSECONDS_PER_NANOSECOND + period / 2) / period;
+    (ticks.min(u64::MAX as u128) as u64).max(MINIMUM_TICK.load(Ordering::Relaxed))
+}
+
+pub fn comparator_count() -> u8 {
+    COMPARATOR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the interrupt of `comparator` reaches its vector in [HPET_VECTORS].
+pub fn is_routed(comparator: u8) -> bool {
+    comparator < comparator_count() && ROUTED.load(Ordering::Relaxed) & (1 << comparator) != 0
+}
+
+/// Returns `true` if `comparator` is routed and can fire periodically.
+pub fn is_periodic_capable(comparator: u8) -> bool {
+    is_routed(comparator)
+        && read(comparator_register(
+            REGISTER_COMPARATOR_CONFIGURATION,
+            comparator,
+        )) & COMPARATOR_PERIODIC_CAPABLE
+            != 0
+}
+
+/// Returns the number of interrupts `comparator` raised so far.
+pub fn interrupts(comparator: u8) -> u64 {
+    INTERRUPTS
+        .get(comparator as usize)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the configuration register of `comparator`, if it can raise interrupts.
+fn routed_comparator(comparator: u8) -> Result<usize, HpetError> {
+    if !is_available() {
+        Err(HpetError::NotEnabled)
+    } else if comparator >= comparator_count() {
+        Err(HpetError::NoSuchComparator(comparator))
+    } else if !is_routed(comparator) {
+        Err(HpetError::NotRouted(comparator))
+    } else {
+        Ok(comparator_register(
+            REGISTER_COMPARATOR_CONFIGURATION,
+            comparator,
+        ))
+    }
+}
+
+/// Fires the interrupt of `comparator` every `period`, as close as the main counter
+/// resolution allows, and returns that period.
+pub fn start_periodic_timer(comparator: u8, period: Duration) -> Result<Duration, HpetError> {
+    let register = routed_comparator(comparator)?;
+    if !is_periodic_capable(comparator) {
+        return Err(HpetError::PeriodicUnsupported(comparator));
+    }
+    let value_register = comparator_register(REGISTER_COMPARATOR_VALUE, comparator);
+    let ticks = duration_to_ticks(period);
+
+    interrupts::without_interrupts(|| {
+        let configuration = read(register);
+        write(
+            register,
+            configuration
+                | COMPARATOR_INTERRUPT_ENABLE
+                | COMPARATOR_PERIODIC
+                | COMPARATOR_SET_VALUE,
+        );
+        write(value_register, counter().wrapping_add(ticks));
+        write(value_register, ticks);
+    });
+    Ok(ticks_to_duration(ticks))
+}
+
+/// Fires the interrupt of `comparator` once after `delay`.
+pub fn start_one_shot_timer(comparator: u8, delay: Duration) -> Result<(), HpetError> {
+    let register = routed_comparator(comparator)?;
+    let value_register = comparator_register(REGISTER_COMPARATOR_VALUE, comparator);
+    let ticks = duration_to_ticks(delay);
+
+    interrupts::without_interrupts(|| {
+        // the comparator must not fire at its old value while the new one is written
+        let configuration = read(register) & !(COMPARATOR_INTERRUPT_ENABLE | COMPARATOR_PERIODIC);
+        write(register, configuration);
+        write(value_register, counter().wrapping_add(ticks));
+        write(register, configuration | COMPARATOR_INTERRUPT_ENABLE);
+    });
+    Ok(())
+}
+
+/// Stops `comparator` in every mode.
+pub fn stop_timer(comparator: u8) {
+    if !is_available() || comparator >= comparator_count() {
+        return;
+    }
+    let register = comparator_register(REGISTER_COMPARATOR_CONFIGURATION, comparator);
+    write(
+        register,
+        read(register) & !(COMPARATOR_INTERRUPT_ENABLE | COMPARATOR_PERIODIC),
+    );
+}
+
+/// Called by the interrupt handlers of the comparators.
+pub(crate) fn comparator_interrupt(comparator: u8) {
+    INTERRUPTS[comparator as usize].fetch_add(1, Ordering::Relaxed);
+    if comparator == TICK_COMPARATOR {
+        super::tick(TickSource::Hpet);
+    }
+}
+
+/// Halts until `comparator` raised `count` interrupts, or gives up after `ticks` ticks of
+/// the tick clock. Returns whether the interrupt count was reached.
+#[cfg(test)]
+fn wait_for_interrupts(comparator: u8, count: u64, ticks: u64) -> bool {
+    let deadline = super::ticks() + ticks;
+    while interrupts(comparator) < count && super::ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+    interrupts(comparator) >= count
+}
+
+#[test_case]
+fn main_counter_advances() {
+    if !is_available() {
+        return;
+    }
+
+    let start = counter();
+    super::sleep_ms(2);
+    assert!(ticks_to_duration(counter() - start) >= Duration::from_millis(1));
+}
+
+#[test_case]
+fn comparators_fire_in_both_modes() {
+    // the tick comparator is left to the tick clock
+    let comparator = TICK_COMPARATOR + 1;
+    if !is_routed(comparator) {
+        return;
+    }
+
+    let before = interrupts(comparator);
+    start_one_shot_timer(comparator, Duration::from_millis(1)).unwrap();
+    assert!(wait_for_interrupts(comparator, before + 1, 50));
+    // a one-shot comparator must not fire again
+    super::sleep_ms(5);
+    assert_eq!(interrupts(comparator), before + 1);
+
+    if is_periodic_capable(comparator) {
+        let period = start_periodic_timer(comparator, Duration::from_millis(1)).unwrap();
+        assert!(period > Duration::from_micros(900) && period < Duration::from_micros(1100));
+        assert!(wait_for_interrupts(comparator, before + 4, 50));
+        stop_timer(comparator);
+    }
+}
@@ -0,0 +1,124 @@
+use super::tsc;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::time::Duration;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// A point in time since boot with nanosecond resolution, for measuring how long something
+/// takes. Works like `std::time::Instant`.
+///
+/// Instants are read from the time stamp counter once it is calibrated, and from the tick
+/// clock before that or if the CPU has no time stamp counter, so instants taken before
+/// [super::init] cannot be compared with later ones.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        let nanos = match tsc::frequency() {
+            Some(frequency) => {
+                let cycles = tsc::cycles_since_base() as u128;
+                (cycles * NANOS_PER_SECOND / frequency as u128) as u64
+            }
+            None => super::uptime().as_nanos() as u64,
+        };
+        Self { nanos }
+    }
+
+    /// Returns the time passed since this instant was taken.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    /// Returns the time from `earlier` to `self`.
+    ///
+    /// Panics if `earlier` is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier)
+            .expect("supplied instant is later than self")
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.nanos
+            .checked_sub(earlier.nanos)
+            .map(Duration::from_nanos)
+    }
+
+    /// Returns the time from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.nanos.checked_add(nanos).map(|nanos| Self { nanos })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.nanos.checked_sub(nanos).map(|nanos| Self { nanos })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl fmt::Debug for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instant({:?})", Duration::from_nanos(self.nanos))
+    }
+}
+
+#[test_case]
+fn instants_are_monotonic() {
+    let start = Instant::now();
+    super::sleep_ms(2);
+    let end = Instant::now();
+
+    assert!(end > start);
+    assert!(end - start >= Duration::from_millis(1));
+    assert!(start.elapsed() >= end - start);
+    assert_eq!(start + (end - start), end);
+    assert_eq!(end - (end - start), start);
+    assert_eq!(start.checked_duration_since(end), None);
+    assert_eq!(start.saturating_duration_since(end), Duration::ZERO);
+}
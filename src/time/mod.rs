@@ -0,0 +1,388 @@
+pub mod hpet;
+mod instant;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+pub use instant::Instant;
+pub use rtc::DateTime;
+
+use crate::{print, println};
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The rate of the timer interrupt the kernel starts with, in Hz
+pub const DEFAULT_TIMER_FREQUENCY: u32 = 1000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The time since boot, advanced by every tick
+static UPTIME_NANOS: AtomicU64 = AtomicU64::new(0);
+static NANOS_PER_TICK: AtomicU64 = AtomicU64::new(0);
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+/// The unix timestamp of the moment the uptime was zero, as read from the RTC in [init]
+static BOOT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+/// Wakers of sleeping tasks by the tick they wake up at, and a unique ID to allow
+/// several tasks to wake up at the same tick. Only locked with interrupts disabled,
+/// as the timer interrupt handler locks it too.
+static SLEEPERS: Mutex<BTreeMap<(u64, u64), Sleeper>> = Mutex::new(BTreeMap::new());
+static NEXT_SLEEPER_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Sleeper {
+    waker: Waker,
+    woken: bool,
+}
+
+/// The interrupt driving the tick clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+    /// Channel 0 of the PIT on IRQ 0
+    Pit,
+    /// The periodic interrupt of the RTC on IRQ 8, limited to powers of two from 2 to 8192 Hz
+    Rtc,
+    /// The first comparator of the HPET, routed through the I/O APIC, see [hpet::init]
+    Hpet,
+}
+
+pub fn init() {
+    print!("Initializing PIT...   ");
+    let frequency = set_tick_source(TickSource::Pit, DEFAULT_TIMER_FREQUENCY);
+    println!("[Ok]");
+    crate::serial_println!("PIT: timer interrupt at {} Hz", frequency);
+
+    print!("Calibrating TSC...   ");
+    match tsc::calibrate() {
+        Some(frequency) => {
+            println!("[Ok]");
+            crate::serial_println!(
+                "TSC: {} kHz, {}",
+                frequency / 1000,
+                if tsc::is_invariant() {
+                    "invariant"
+                } else {
+                    "not invariant, may drift with power states"
+                }
+            );
+        }
+        None => println!("[No TSC, using the tick clock]"),
+    }
+
+    print!("Reading RTC...   ");
+    let now = read_boot_timestamp();
+    println!("[Ok]");
+    crate::serial_println!("RTC: {}", now);
+}
+
+/// Tells the RTC driver which CMOS register holds the century, as found in the FADT, and
+/// reads the wall clock again with it.
+pub fn set_century_register(register: Option<u8>) {
+    rtc::set_century_register(register);
+    read_boot_timestamp();
+}
+
+/// Derives the boot time from the RTC and the uptime, and returns the current time.
+fn read_boot_timestamp() -> DateTime {
+    let now = rtc::read();
+    BOOT_TIMESTAMP.store(
+        now.unix_timestamp().saturating_sub(uptime().as_secs()),
+        Ordering::Relaxed,
+    );
+    now
+}
+
+/// Reprograms the interrupt of the current tick source to the closest possible rate to
+/// `frequency` and returns that rate. Ticks already counted keep their duration.
+pub fn set_timer_frequency(frequency: u32) -> u32 {
+    set_tick_source(tick_source(), frequency)
+}
+
+/// Drives the tick clock by `source` at the closest possible rate to `frequency` and returns
+/// that rate. Ticks already counted keep their duration.
+///
+/// The PIT keeps running when another source takes over, its interrupts are just not
+/// counted. The PIT also stands in for the HPET when that can't drive the tick clock.
+pub fn set_tick_source(source: TickSource, frequency: u32) -> u32 {
+    let source = match source {
+        TickSource::Hpet if !hpet::is_periodic_capable(hpet::TICK_COMPARATOR) => {
+            crate::serial_println!("HPET: can't drive the tick clock, using the PIT");
+            TickSource::Pit
+        }
+        source => source,
+    };
+
+    interrupts::without_interrupts(|| {
+        let (frequency, nanos) = match source {
+            TickSource::Pit => {
+                let divisor = pit::divisor_for(frequency);
+                let divisor_cycles = if divisor == 0 { 65536 } else { divisor as u64 };
+                pit::set_divisor(divisor);
+                (
+                    pit::frequency_for(divisor),
+                    divisor_cycles * NANOS_PER_SECOND / pit::PIT_FREQUENCY as u64,
+                )
+            }
+            TickSource::Rtc => {
+                let rate = rtc::rate_for(frequency);
+                rtc::enable_periodic_interrupt(rate);
+                let frequency = rtc::frequency_for(rate);
+                (frequency, NANOS_PER_SECOND / frequency as u64)
+            }
+            TickSource::Hpet => {
+                let period = Duration::from_nanos(NANOS_PER_SECOND / frequency.max(1) as u64);
+                let period = hpet::start_periodic_timer(hpet::TICK_COMPARATOR, period)
+                    .unwrap_or(period)
+                    .as_nanos() as u64;
+                ((NANOS_PER_SECOND / period.max(1)) as u32, period)
+            }
+        };
+
+        let previous = tick_source();
+        if previous == TickSource::Rtc && source != TickSource::Rtc {
+            rtc::disable_periodic_interrupt();
+        }
+        if previous == TickSource::Hpet && source != TickSource::Hpet {
+            hpet::stop_timer(hpet::TICK_COMPARATOR);
+        }
+        TICK_SOURCE.store(source as u8, Ordering::Relaxed);
+        NANOS_PER_TICK.store(nanos, Ordering::Relaxed);
+        frequency
+    })
+}
+
+pub fn tick_source() -> TickSource {
+    match TICK_SOURCE.load(Ordering::Relaxed) {
+        source if source == TickSource::Rtc as u8 => TickSource::Rtc,
+        source if source == TickSource::Hpet as u8 => TickSource::Hpet,
+        _ => TickSource::Pit,
+    }
+}
+
+/// Returns the duration of a tick, or zero before [init].
+pub fn tick_duration() -> Duration {
+    Duration::from_nanos(NANOS_PER_TICK.load(Ordering::Relaxed))
+}
+
+/// Called by the interrupt handler of every tick source, only ticks of the current one count.
+pub(crate) fn tick(source: TickSource) {
+    if source != tick_source() {
+        return;
+    }
+
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    UPTIME_NANOS.fetch_add(NANOS_PER_TICK.load(Ordering::Relaxed), Ordering::Relaxed);
+
+    // the wakers are only woken here, dropping them could free memory, which must not
+    // happen in an interrupt handler
+    let mut sleepers = SLEEPERS.lock();
+    for (_, sleeper) in sleepers.range_mut(..(now + 1, 0)) {
+        if !sleeper.woken {
+            sleeper.waker.wake_by_ref();
+            sleeper.woken = true;
+        }
+    }
+}
+
+/// Returns the number of timer interrupts since [init].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since [init], with the resolution of a tick.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(UPTIME_NANOS.load(Ordering::Relaxed))
+}
+
+/// Returns the seconds since 1970-01-01 00:00:00, as kept by the RTC.
+pub fn unix_timestamp() -> u64 {
+    BOOT_TIMESTAMP.load(Ordering::Relaxed) + uptime().as_secs()
+}
+
+/// Returns the current date and time, as kept by the RTC.
+///
+/// This only reads the RTC once in [init] and advances it with the tick clock since, see
+/// [rtc::read] for the current value of the RTC itself.
+pub fn now() -> DateTime {
+    DateTime::from_unix_timestamp(unix_timestamp())
+}
+
+/// Returns the number of ticks covering at least `duration`.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos_per_tick = NANOS_PER_TICK.load(Ordering::Relaxed).max(1) as u128;
+    let ticks = (duration.as_nanos() + nanos_per_tick - 1) / nanos_per_tick;
+    ticks as u64
+}
+
+/// Halts the CPU until `ticks` timer interrupts have passed.
+///
+/// Interrupts must be enabled, otherwise this never returns.
+pub fn sleep_ticks(ticks: u64) {
+    let target = self::ticks() + ticks;
+    while self::ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Spins until `ticks` timer interrupts have passed, for when halting is not an option.
+pub fn busy_sleep_ticks(ticks: u64) {
+    let target = self::ticks() + ticks;
+    while self::ticks() < target {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn sleep_ms(milliseconds: u64) {
+    sleep_ticks(duration_to_ticks(Duration::from_millis(milliseconds)));
+}
+
+pub fn busy_sleep_ms(milliseconds: u64) {
+    busy_sleep_ticks(duration_to_ticks(Duration::from_millis(milliseconds)));
+}
+
+/// Returns a future that completes after `duration`, for use in tasks.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::until_tick(ticks() + duration_to_ticks(duration))
+}
+
+/// A future completing once the tick counter reaches a deadline.
+pub struct Sleep {
+    deadline: u64,
+    /// The key in `SLEEPERS` while a waker is registered
+    key: Option<(u64, u64)>,
+}
+
+impl Sleep {
+    pub fn until_tick(deadline: u64) -> Self {
+        Self {
+            deadline,
+            key: None,
+        }
+    }
+
+    fn unregister(&mut self) {
+        if let Some(key) = self.key.take() {
+            interrupts::without_interrupts(|| SLEEPERS.lock().remove(&key));
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if ticks() >= self.deadline {
+            self.unregister();
+            return Poll::Ready(());
+        }
+
+        let key = match self.key {
+            Some(key) => key,
+            None => (
+                self.deadline,
+                NEXT_SLEEPER_ID.fetch_add(1, Ordering::Relaxed),
+            ),
+        };
+        let sleeper = Sleeper {
+            waker: context.waker().clone(),
+            woken: false,
+        };
+        interrupts::without_interrupts(|| SLEEPERS.lock().insert(key, sleeper));
+        self.key = Some(key);
+
+        // the deadline may have passed before the waker was registered
+        if ticks() >= self.deadline {
+            self.unregister();
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+#[test_case]
+fn ticks_advance() {
+    let (ticks_before, uptime_before) = (ticks(), uptime());
+    sleep_ms(10);
+    assert!(ticks() >= ticks_before + duration_to_ticks(Duration::from_millis(10)));
+    assert!(uptime() - uptime_before >= Duration::from_millis(10));
+
+    let ticks_before = ticks();
+    busy_sleep_ticks(2);
+    assert!(ticks() >= ticks_before + 2);
+}
+
+#[test_case]
+fn rtc_drives_the_tick_clock() {
+    let frequency = set_tick_source(TickSource::Rtc, 1024);
+    assert_eq!(frequency, 1024);
+    assert_eq!(tick_duration(), Duration::from_nanos(976_562));
+
+    let ticks_before = ticks();
+    sleep_ticks(4);
+    assert!(ticks() >= ticks_before + 4);
+
+    set_tick_source(TickSource::Pit, DEFAULT_TIMER_FREQUENCY);
+    assert_eq!(tick_source(), TickSource::Pit);
+}
+
+#[test_case]
+fn hpet_drives_the_tick_clock() {
+    if !hpet::is_periodic_capable(hpet::TICK_COMPARATOR) {
+        return;
+    }
+
+    let frequency = set_tick_source(TickSource::Hpet, 2000);
+    assert_eq!(tick_source(), TickSource::Hpet);
+    assert!((1990..=2010).contains(&frequency));
+
+    let ticks_before = ticks();
+    sleep_ticks(4);
+    assert!(ticks() >= ticks_before + 4);
+
+    set_tick_source(TickSource::Pit, DEFAULT_TIMER_FREQUENCY);
+    assert_eq!(tick_source(), TickSource::Pit);
+    // the comparator stopped along with the switch
+    let interrupts = hpet::interrupts(hpet::TICK_COMPARATOR);
+    sleep_ms(5);
+    assert_eq!(hpet::interrupts(hpet::TICK_COMPARATOR), interrupts);
+}
+
+#[test_case]
+fn wall_clock_follows_the_rtc() {
+    let (clock, rtc) = (unix_timestamp(), rtc::read().unix_timestamp());
+    // the tick clock may lag behind by the fraction of a second the RTC had passed at boot
+    assert!(clock <= rtc + 1 && rtc <= clock + 2);
+}
+
+#[test_case]
+fn sleeping_tasks_are_woken() {
+    use crate::task::{Executor, Task};
+
+    let start = uptime();
+    let mut executor = Executor::new();
+    for milliseconds in [5, 1, 3] {
+        executor.spawn(Task::new(sleep(Duration::from_millis(milliseconds))));
+    }
+
+    while executor.task_count() > 0 {
+        executor.run_ready_tasks();
+        x86_64::instructions::hlt();
+    }
+    assert!(uptime() - start >= Duration::from_millis(5));
+    assert!(interrupts::without_interrupts(|| SLEEPERS
+        .lock()
+        .is_empty()));
+}
@@ -0,0 +1,93 @@
+use spin::Mutex;
+use x86_64::instructions::port::{Port, PortWriteOnly};
+
+/// The frequency of the oscillator driving the PIT, in Hz
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// Controls the gate of channel 2 and reports its output, shared with the PC speaker
+const CHANNEL_2_CONTROL_PORT: u16 = 0x61;
+/// Channel 0, low byte then high byte, mode 2 (rate generator), binary
+const COMMAND_CHANNEL_0_RATE_GENERATOR: u8 = 0b00_11_010_0;
+/// Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary
+const COMMAND_CHANNEL_2_ONE_SHOT: u8 = 0b10_11_000_0;
+/// The rate generator mode does not work with a divisor of 1
+const MIN_RATE_DIVISOR: u32 = 2;
+const CHANNEL_2_GATE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+static PORTS: Mutex<(Port<u8>, PortWriteOnly<u8>)> =
+    Mutex::new((Port::new(CHANNEL_0_PORT), PortWriteOnly::new(COMMAND_PORT)));
+
+/// Returns the divisor for channel 0 that gets closest to `frequency`, 0 standing for 65536.
+pub fn divisor_for(frequency: u32) -> u16 {
+    let divisor = (PIT_FREQUENCY + frequency / 2) / frequency.max(1);
+    match divisor.max(MIN_RATE_DIVISOR) {
+        divisor @ 2..=65535 => divisor as u16,
+        _ => 0,
+    }
+}
+
+/// Returns the frequency in Hz that `divisor` results in.
+pub fn frequency_for(divisor: u16) -> u32 {
+    let divisor = if divisor == 0 { 65536 } else { divisor as u32 };
+    PIT_FREQUENCY / divisor
+}
+
+/// Programs channel 0, which drives IRQ 0, to fire every `divisor` oscillator cycles.
+pub(super) fn set_divisor(divisor: u16) {
+    let mut ports = PORTS.lock();
+    let (channel_0, command) = &mut *ports;
+    unsafe {
+        command.write(COMMAND_CHANNEL_0_RATE_GENERATOR);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Counts `cycles` oscillator cycles down on channel 2, which is not connected to an
+/// interrupt, and returns the values `read` returned right after the count started and
+/// right after it ran out.
+///
+/// Interrupts should be disabled, so the two reads are as close as possible to the
+/// start and the end of the count.
+pub(crate) fn measure_cycles<T>(cycles: u16, mut read: impl FnMut() -> T) -> (T, T) {
+    let mut ports = PORTS.lock();
+    let command = &mut ports.1;
+    let mut channel_2 = Port::<u8>::new(CHANNEL_2_PORT);
+    let mut control = Port::<u8>::new(CHANNEL_2_CONTROL_PORT);
+
+    unsafe {
+        // open the gate, but keep the speaker quiet
+        let value = control.read();
+        control.write((value & !SPEAKER_ENABLE) | CHANNEL_2_GATE);
+
+        command.write(COMMAND_CHANNEL_2_ONE_SHOT);
+        channel_2.write(cycles as u8);
+        // the count starts with the high byte
+        channel_2.write((cycles >> 8) as u8);
+        let start = read();
+
+        while control.read() & CHANNEL_2_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+        let end = read();
+
+        control.write(value);
+        (start, end)
+    }
+}
+
+#[test_case]
+fn divisors_are_rounded_and_clamped() {
+    assert_eq!(divisor_for(1000), 1193);
+    assert_eq!(frequency_for(1193), 1000);
+    assert_eq!(divisor_for(PIT_FREQUENCY), 2);
+    assert_eq!(divisor_for(u32::MAX), 2);
+    // the slowest rate is about 18.2 Hz
+    assert_eq!(divisor_for(1), 0);
+    assert_eq!(frequency_for(0), 18);
+}
@@ -0,0 +1,343 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+/// The frequency of the oscillator driving the periodic interrupt, in Hz
+pub const RTC_FREQUENCY: u32 = 32768;
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+/// Set in the index to keep NMIs disabled while a register is selected, the index is
+/// written without it after each access to enable them again
+const NMI_DISABLE: u8 = 0x80;
+/// Left selected after each access, the RTC expects status register D between accesses
+const REGISTER_STATUS_D: u8 = 0x0D;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+const REGISTER_STATUS_C: u8 = 0x0C;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+const HOURS_PM: u8 = 1 << 7;
+
+/// The fastest and slowest rates that can be selected in status register A, lower values
+/// are reserved or do not work on every chipset
+const FASTEST_RATE: u8 = 3;
+const SLOWEST_RATE: u8 = 15;
+
+/// Assumed when there is no century register
+const DEFAULT_CENTURY: u16 = 20;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+static PORTS: Mutex<(Port<u8>, Port<u8>)> =
+    Mutex::new((Port::new(INDEX_PORT), Port::new(DATA_PORT)));
+/// The CMOS register holding the century, as announced by the ACPI FADT, zero if there is none
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+/// A date and time of day in UTC, or whatever time zone the firmware clock is set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Converts seconds since 1970-01-01 00:00:00 to a date and time.
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let days = (timestamp / SECONDS_PER_DAY) as i64;
+        let seconds = timestamp % SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Returns the seconds since 1970-01-01 00:00:00, saturating at zero for earlier dates.
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        let seconds = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        (days * SECONDS_PER_DAY as i64 + seconds).max(0) as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Returns the days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    // count years from March, so the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [days_from_civil].
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u8, day as u8)
+}
+
+/// Tells the driver which CMOS register holds the century, as found in the ACPI FADT.
+pub(super) fn set_century_register(register: Option<u8>) {
+    CENTURY_REGISTER.store(register.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Reads the current date and time from the real-time clock.
+pub fn read() -> DateTime {
+    // the registers are inconsistent while the clock updates, which happens once per
+    // second and takes up to 2 ms, so read until two reads in a row agree
+    let mut last = read_raw();
+    loop {
+        let current = read_raw();
+        if current == last {
+            return decode(current, read_register(REGISTER_STATUS_B));
+        }
+        last = current;
+    }
+}
+
+/// The registers as read from the clock, in the format selected by status register B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawDateTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+fn read_raw() -> RawDateTime {
+    while read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+
+    let century_register = CENTURY_REGISTER.load(Ordering::Relaxed);
+    RawDateTime {
+        second: read_register(REGISTER_SECONDS),
+        minute: read_register(REGISTER_MINUTES),
+        hour: read_register(REGISTER_HOURS),
+        day: read_register(REGISTER_DAY),
+        month: read_register(REGISTER_MONTH),
+        year: read_register(REGISTER_YEAR),
+        century: match century_register {
+            0 => None,
+            register => Some(read_register(register)),
+        },
+    }
+}
+
+fn decode(raw: RawDateTime, status_b: u8) -> DateTime {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let value = |value: u8| if binary { value } else { from_bcd(value) };
+
+    let pm = raw.hour & HOURS_PM != 0;
+    let mut hour = value(raw.hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let century = raw.century.map_or(DEFAULT_CENTURY, |c| value(c) as u16);
+    DateTime {
+        year: century * 100 + value(raw.year) as u16,
+        month: value(raw.month),
+        day: value(raw.day),
+        hour,
+        minute: value(raw.minute),
+        second: value(raw.second),
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn read_register(register: u8) -> u8 {
+    interrupts::without_interrupts(|| {
+        let mut ports = PORTS.lock();
+        let (index, data) = &mut *ports;
+        unsafe {
+            index.write(NMI_DISABLE | register);
+            let value = data.read();
+            index.write(REGISTER_STATUS_D);
+            value
+        }
+    })
+}
+
+fn write_register(register: u8, value: u8) {
+    interrupts::without_interrupts(|| {
+        let mut ports = PORTS.lock();
+        let (index, data) = &mut *ports;
+        unsafe {
+            index.write(NMI_DISABLE | register);
+            data.write(value);
+            index.write(REGISTER_STATUS_D);
+        }
+    })
+}
+
+/// Returns the rate selector of the periodic interrupt closest to `frequency`, limited to
+/// the rates the RTC supports.
+pub fn rate_for(frequency: u32) -> u8 {
+    (FASTEST_RATE..=SLOWEST_RATE)
+        .min_by_key(|&rate| (frequency_for(rate) as i64 - frequency as i64).abs())
+        .unwrap()
+}
+
+/// Returns the frequency in Hz of the periodic interrupt at `rate`.
+pub fn frequency_for(rate: u8) -> u32 {
+    RTC_FREQUENCY >> (rate - 1)
+}
+
+/// Enables the periodic interrupt on IRQ 8 at `rate`, see [rate_for].
+pub(super) fn enable_periodic_interrupt(rate: u8) {
+    interrupts::without_interrupts(|| {
+        let status_a = read_register(REGISTER_STATUS_A);
+        write_register(REGISTER_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        let status_b = read_register(REGISTER_STATUS_B);
+        write_register(REGISTER_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        acknowledge_interrupt();
+    });
+}
+
+pub(super) fn disable_periodic_interrupt() {
+    interrupts::without_interrupts(|| {
+        let status_b = read_register(REGISTER_STATUS_B);
+        write_register(REGISTER_STATUS_B, status_b & !STATUS_B_PERIODIC_INTERRUPT);
+        acknowledge_interrupt();
+    });
+}
+
+/// Reads status register C, without which the RTC does not raise IRQ 8 again.
+pub(crate) fn acknowledge_interrupt() {
+    read_register(REGISTER_STATUS_C);
+}
+
+#[test_case]
+fn unix_timestamps_round_trip() {
+    let epoch = DateTime::from_unix_timestamp(0);
+    assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+    assert_eq!((epoch.hour, epoch.minute, epoch.second), (0, 0, 0));
+
+    // 2000-02-29 12:34:56, a leap day in a century leap year
+    let leap_day = DateTime {
+        year: 2000,
+        month: 2,
+        day: 29,
+        hour: 12,
+        minute: 34,
+        second: 56,
+    };
+    assert_eq!(leap_day.unix_timestamp(), 951_827_696);
+    assert_eq!(DateTime::from_unix_timestamp(951_827_696), leap_day);
+
+    for &timestamp in &[1_234_567_890, 4_102_444_799, 1_633_046_400] {
+        assert_eq!(
+            DateTime::from_unix_timestamp(timestamp).unix_timestamp(),
+            timestamp
+        );
+    }
+}
+
+#[test_case]
+fn registers_are_decoded() {
+    let raw = RawDateTime {
+        second: 0x59,
+        minute: 0x30,
+        hour: HOURS_PM | 0x12,
+        day: 0x31,
+        month: 0x12,
+        year: 0x21,
+        century: None,
+    };
+    let decoded = decode(raw, 0);
+    assert_eq!((decoded.year, decoded.month, decoded.day), (2021, 12, 31));
+    assert_eq!((decoded.hour, decoded.minute, decoded.second), (12, 30, 59));
+    // 12 AM is midnight
+    assert_eq!(decode(RawDateTime { hour: 0x12, ..raw }, 0).hour, 0);
+
+    let binary = RawDateTime {
+        hour: 23,
+        year: 99,
+        century: Some(19),
+        ..raw
+    };
+    let decoded = decode(binary, STATUS_B_BINARY | STATUS_B_24_HOUR);
+    assert_eq!((decoded.year, decoded.hour), (1999, 23));
+}
+
+#[test_case]
+fn periodic_rates() {
+    assert_eq!(rate_for(1024), 6);
+    assert_eq!(frequency_for(6), 1024);
+    // 1000 Hz is closer to 1024 Hz than to 512 Hz, 700 Hz the other way around
+    assert_eq!(rate_for(1000), 6);
+    assert_eq!(rate_for(700), 7);
+    assert_eq!(rate_for(RTC_FREQUENCY), FASTEST_RATE);
+    assert_eq!(rate_for(1), SLOWEST_RATE);
+    assert_eq!(frequency_for(SLOWEST_RATE), 2);
+}
+
+#[test_case]
+fn clock_reads_a_plausible_date() {
+    let now = read();
+    assert!(now.year >= 2021);
+    assert!((1..=12).contains(&now.month) && (1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
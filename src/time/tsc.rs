@@ -0,0 +1,91 @@
+use super::pit;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+/// The number of PIT cycles a calibration run lasts, about 10 ms
+const CALIBRATION_CYCLES: u16 = 11_932;
+const CALIBRATION_RUNS: usize = 5;
+
+const CPUID_FEATURES: u32 = 0x1;
+const CPUID_FEATURES_EDX_TSC: u32 = 1 << 4;
+const CPUID_MAX_EXTENDED_LEAF: u32 = 0x8000_0000;
+const CPUID_ADVANCED_POWER_MANAGEMENT: u32 = 0x8000_0007;
+const CPUID_POWER_MANAGEMENT_EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+/// The rate of the time stamp counter in Hz, zero if it has not been calibrated
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The counter value at calibration, the origin of [super::Instant]
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns `true` if the CPU has a time stamp counter.
+pub fn is_present() -> bool {
+    let features = unsafe { __cpuid(CPUID_FEATURES) };
+    features.edx & CPUID_FEATURES_EDX_TSC != 0
+}
+
+/// Returns `true` if the time stamp counter runs at a constant rate in every power state,
+/// which is required for it to be used as a clock.
+pub fn is_invariant() -> bool {
+    let max_leaf = unsafe { __cpuid(CPUID_MAX_EXTENDED_LEAF) }.eax;
+    if max_leaf < CPUID_ADVANCED_POWER_MANAGEMENT {
+        return false;
+    }
+
+    let power_management = unsafe { __cpuid(CPUID_ADVANCED_POWER_MANAGEMENT) };
+    power_management.edx & CPUID_POWER_MANAGEMENT_EDX_INVARIANT_TSC != 0
+}
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Returns the calibrated rate of the time stamp counter in Hz, or `None` before [calibrate]
+/// succeeded.
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+/// Returns the number of counter cycles since calibration.
+pub(super) fn cycles_since_base() -> u64 {
+    read().saturating_sub(BASE.load(Ordering::Relaxed))
+}
+
+/// Measures the rate of the time stamp counter against PIT channel 2 and returns it in Hz.
+///
+/// Returns `None` if the CPU has no time stamp counter.
+pub fn calibrate() -> Option<u64> {
+    if !is_present() {
+        return None;
+    }
+
+    let mut samples = [0; CALIBRATION_RUNS];
+    for sample in samples.iter_mut() {
+        let (start, end) =
+            interrupts::without_interrupts(|| pit::measure_cycles(CALIBRATION_CYCLES, read));
+        *sample = end - start;
+    }
+
+    // an SMI or a slow port access can stretch single runs, the median ignores those
+    samples.sort_unstable();
+    let cycles = samples[CALIBRATION_RUNS / 2];
+    let frequency = cycles * pit::PIT_FREQUENCY as u64 / CALIBRATION_CYCLES as u64;
+
+    BASE.store(read(), Ordering::Relaxed);
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    Some(frequency)
+}
+
+#[test_case]
+fn calibrated_frequency_is_plausible() {
+    if let Some(frequency) = frequency() {
+        // anything between 100 MHz and 10 GHz
+        assert!((100_000_000..10_000_000_000).contains(&frequency));
+        let (before, after) = (read(), read());
+        assert!(after >= before);
+    }
+}
@@ -4,6 +4,7 @@ const VGA_BUFFER_WIDTH: usize = 80;
 const VGA_SQUARE_ASCII_CODE: u8 = 0xfe;
 const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Black);
 
+use crate::graphics::console;
 use core::fmt;
 use core::fmt::Write;
 use lazy_static::lazy_static;
@@ -189,7 +190,11 @@ const fn is_valid_ascii(code: &u8) -> bool {
 pub fn _print(args: fmt::Arguments) {
     // prevent a deadlock
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        // once graphics mode is active the text buffer isn't visible anymore
+        let written = console::with_console(|console| console.write_fmt(args).unwrap());
+        if written.is_none() {
+            WRITER.lock().write_fmt(args).unwrap();
+        }
     });
 }
 
@@ -1,13 +1,16 @@
 #![no_std]
 #![no_main]
 
+use bootloader::{entry_point, BootInfo};
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
 use voluspa_kernel::tests::{isa_debug_exit_qemu, QemuExitCode};
 use voluspa_kernel::{serial_print, serial_println};
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    voluspa_kernel::init();
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    voluspa_kernel::init(boot_info);
 
     serial_print!("{}...\t", "stack_overflow::_start");
     stack_overflow();
@@ -28,11 +31,53 @@ fn stack_overflow() {
     volatile::Volatile::new(0).read();
 }
 
+/// Collects the start of the panic message, since there is no heap to format it into
+struct MessageBuffer {
+    bytes: [u8; 512],
+    len: usize,
+}
+
+impl MessageBuffer {
+    fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len];
+        // the message may be cut off in the middle of a character
+        match core::str::from_utf8(bytes) {
+            Ok(message) => message,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        }
+    }
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    serial_println!("[Ok]");
+    let mut message = MessageBuffer {
+        bytes: [0; 512],
+        len: 0,
+    };
+    let _ = write!(message, "{}", info);
+    let message = message.as_str();
 
-    isa_debug_exit_qemu(QemuExitCode::Success);
+    // the overflow must be reported as one, in the guard page of the kernel stack
+    if message.contains("stack overflow")
+        && message.contains("kernel stack guard")
+        && message.contains("(Guard)")
+    {
+        serial_println!("[Ok]");
+        isa_debug_exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[FAILED]");
+        serial_println!("Unexpected panic: {}", message);
+        isa_debug_exit_qemu(QemuExitCode::Failure);
+    }
 
     voluspa_kernel::hlt_loop()
 }
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Linker for the kernel: links with rust-lld, then writes the symbol table into the linked
+# ELF. Every build of a kernel or test binary goes through here, so `cargo build`,
+# `cargo run`, `cargo test` and `cargo bootimage` all produce symbolized images.
+set -e
+
+tools="$(dirname "$0")"
+symbol_table="$tools/../target/symbol_table"
+
+rust-lld "$@"
+
+output=
+previous=
+for arg in "$@"; do
+    if [ "$previous" = "-o" ]; then
+        output="$arg"
+    fi
+    previous="$arg"
+done
+
+if [ ! -x "$symbol_table" ] || [ "$tools/symbol_table.rs" -nt "$symbol_table" ]; then
+    mkdir -p "$(dirname "$symbol_table")"
+    rustc --edition 2018 -O "$tools/symbol_table.rs" -o "$symbol_table"
+fi
+"$symbol_table" "$output"
@@ -0,0 +1,248 @@
+//! Writes the symbol table of a kernel ELF into its `.symbols` section, which the kernel
+//! uses to symbolize backtraces. Runs after linking, from `tools/linker.sh`.
+//!
+//! The layout matches `src/debug/symbols.rs`, all integers are little endian:
+//!
+//! ```text
+//! header:  magic "VSYM", u32 table length in bytes, u32 symbol count
+//! symbols: u64 address, u32 size, u32 name offset, sorted by address
+//! names:   u16 length followed by the demangled name, at the offsets of the symbols
+//! ```
+
+use std::convert::TryInto;
+use std::{env, fs, process};
+
+const MAGIC: &[u8; 4] = b"VSYM";
+const SECTION_NAME: &str = ".symbols";
+const HEADER_SIZE: usize = 12;
+const SYMBOL_SIZE: usize = 16;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+struct Symbol {
+    address: u64,
+    size: u32,
+    name: String,
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => fail("usage: symbol_table <kernel elf>"),
+    };
+    let mut elf = fs::read(&path).unwrap_or_else(|e| fail(&format!("reading {}: {}", path, e)));
+    if elf.get(..4) != Some(b"\x7fELF") || elf.get(4) != Some(&2) {
+        fail(&format!("{} is not a 64-bit ELF file", path));
+    }
+
+    let sections = sections(&elf);
+    let names = &sections[u16_at(&elf, 0x3E) as usize];
+    let section_name = |section: &Section| string_at(&elf, names.offset + section.name as usize);
+
+    // binaries that never symbolize a backtrace leave the table out
+    let target = match sections
+        .iter()
+        .find(|section| section_name(section) == SECTION_NAME)
+    {
+        Some(target) => target,
+        None => return,
+    };
+    if elf.get(target.offset..target.offset + 4) != Some(&MAGIC[..]) {
+        fail(&format!(
+            "{} does not start with the symbol table magic",
+            SECTION_NAME
+        ));
+    }
+    let symtab = sections
+        .iter()
+        .find(|section| section.kind == SHT_SYMTAB)
+        .unwrap_or_else(|| fail(&format!("{} has no symbol table, was it stripped?", path)));
+
+    let symbols = symbols(&elf, symtab, &sections[symtab.link]);
+    let table = encode(&symbols);
+    if table.len() > target.size {
+        fail(&format!(
+            "the symbol table needs {} bytes but {} only holds {}, raise SYMBOL_TABLE_CAPACITY",
+            table.len(),
+            SECTION_NAME,
+            target.size
+        ));
+    }
+
+    let (offset, count) = (target.offset, symbols.len());
+    elf[offset..offset + table.len()].copy_from_slice(&table);
+    fs::write(&path, &elf).unwrap_or_else(|e| fail(&format!("writing {}: {}", path, e)));
+    eprintln!("Symbol table: {} functions, {} bytes", count, table.len());
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("symbol_table: {}", message);
+    process::exit(1)
+}
+
+fn u16_at(elf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(elf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(elf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(elf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(elf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap())
+}
+
+fn string_at(elf: &[u8], offset: usize) -> &str {
+    let end = elf[offset..].iter().position(|&b| b == 0).unwrap_or(0);
+    std::str::from_utf8(&elf[offset..offset + end]).unwrap_or("")
+}
+
+fn sections(elf: &[u8]) -> Vec<Section> {
+    let offset = u64_at(elf, 0x28) as usize;
+    let entry_size = u16_at(elf, 0x3A) as usize;
+    let count = u16_at(elf, 0x3C) as usize;
+
+    (0..count)
+        .map(|i| {
+            let header = offset + i * entry_size;
+            Section {
+                name: u32_at(elf, header),
+                kind: u32_at(elf, header + 0x04),
+                offset: u64_at(elf, header + 0x18) as usize,
+                size: u64_at(elf, header + 0x20) as usize,
+                link: u32_at(elf, header + 0x28) as usize,
+            }
+        })
+        .collect()
+}
+
+/// Collects the defined functions of the symbol table, sorted by address.
+fn symbols(elf: &[u8], symtab: &Section, strtab: &Section) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = (0..symtab.size / 24)
+        .map(|i| symtab.offset + i * 24)
+        .filter(|&entry| elf[entry + 4] & 0xF == STT_FUNC && u64_at(elf, entry + 8) != 0)
+        .map(|entry| Symbol {
+            address: u64_at(elf, entry + 8),
+            size: u64_at(elf, entry + 16) as u32,
+            name: demangle(string_at(elf, strtab.offset + u32_at(elf, entry) as usize)),
+        })
+        .collect();
+
+    symbols.sort_by_key(|symbol| symbol.address);
+    symbols.dedup_by_key(|symbol| symbol.address);
+    symbols
+}
+
+fn encode(symbols: &[Symbol]) -> Vec<u8> {
+    let mut names = Vec::new();
+    let mut entries = Vec::new();
+    let names_offset = HEADER_SIZE + symbols.len() * SYMBOL_SIZE;
+
+    for symbol in symbols {
+        let name = &symbol.name.as_bytes()[..symbol.name.len().min(u16::MAX as usize)];
+        entries.extend_from_slice(&symbol.address.to_le_bytes());
+        entries.extend_from_slice(&symbol.size.to_le_bytes());
+        entries.extend_from_slice(&((names_offset + names.len()) as u32).to_le_bytes());
+        names.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        names.extend_from_slice(name);
+    }
+
+    let mut table = Vec::with_capacity(names_offset + names.len());
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&((names_offset + names.len()) as u32).to_le_bytes());
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&names);
+    table
+}
+
+/// Demangles a legacy Rust symbol name like `_ZN14voluspa_kernel4init17h0123456789abcdefE`
+/// to `voluspa_kernel::init`, other names are returned unchanged.
+fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN") {
+        Some(rest) => rest,
+        None => return name.to_string(),
+    };
+
+    let mut path = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let length = match rest[..digits].parse::<usize>() {
+            Ok(length) if digits + length <= rest.len() => length,
+            _ => return name.to_string(),
+        };
+        path.push(&rest[digits..digits + length]);
+        rest = &rest[digits + length..];
+    }
+
+    let is_hash = |segment: &&str| {
+        segment.len() == 17
+            && segment.starts_with('h')
+            && segment[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    if path.last().map_or(false, is_hash) {
+        path.pop();
+    }
+
+    path.iter()
+        .map(|segment| unescape(segment))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Replaces the escapes of legacy mangling, like `$LT$` for `<` and `..` for `::`.
+fn unescape(segment: &str) -> String {
+    // a leading underscore only keeps the segment from starting with an escape
+    let mut segment = if segment.starts_with("_$") {
+        &segment[1..]
+    } else {
+        segment
+    };
+    let mut unescaped = String::new();
+
+    while let Some(c) = segment.chars().next() {
+        if segment.starts_with("..") {
+            unescaped.push_str("::");
+            segment = &segment[2..];
+        } else if c == '$' {
+            let end = match segment[1..].find('$') {
+                Some(end) => end + 1,
+                None => break,
+            };
+            let escape = &segment[1..end];
+            let replacement = match escape {
+                "SP" => Some('@'),
+                "BP" => Some('*'),
+                "RF" => Some('&'),
+                "LT" => Some('<'),
+                "GT" => Some('>'),
+                "LP" => Some('('),
+                "RP" => Some(')'),
+                "C" => Some(','),
+                _ => escape
+                    .strip_prefix('u')
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(std::char::from_u32),
+            };
+            match replacement {
+                Some(replacement) => unescaped.push(replacement),
+                None => unescaped.push_str(&segment[..=end]),
+            }
+            segment = &segment[end + 1..];
+        } else {
+            unescaped.push(c);
+            segment = &segment[c.len_utf8()..];
+        }
+    }
+    unescaped.push_str(segment);
+    unescaped
+}